@@ -0,0 +1,138 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader FullscreenVertexOutput
+#import bevy_pbr::mesh_view_types as pbr_types
+#import bevy_render::view View
+#import ocean::surface SurfaceSettings
+#import ocean::surface INVERSION_ITERATIONS
+#import ocean::surface sample_displacement
+#import ocean::surface local_to_plane_uv
+#import ocean::surface refracted_sun_dir
+#import ocean::surface caustic_intensity
+
+struct CausticsSettings {
+    strength: f32,
+    focus: f32,
+    max_depth: f32,
+}
+
+@group(0) @binding(0)
+var screen_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> settings: CausticsSettings;
+@group(0) @binding(2)
+var<uniform> view: View;
+@group(0) @binding(3)
+var<uniform> lights: pbr_types::Lights;
+@group(0) @binding(4)
+var depth_texture: texture_depth_2d;
+@group(0) @binding(5)
+var displacement_textures: texture_2d_array<f32>;
+@group(0) @binding(6)
+var prev_displacement_textures: texture_2d_array<f32>;
+@group(0) @binding(7)
+var gradient_textures: texture_2d_array<f32>;
+@group(0) @binding(8)
+var prev_gradient_textures: texture_2d_array<f32>;
+@group(0) @binding(9)
+var directional_shadow_textures: texture_depth_2d_array;
+@group(0) @binding(10)
+var directional_shadow_sampler: sampler_comparison;
+@group(0) @binding(11)
+var<uniform> surface: SurfaceSettings;
+
+const PI: f32 = 3.141592653589793;
+
+
+// How much of the sun reaches a point, as bevy's pbr shader computes it for the first cascade covering the point
+fn sun_shadow(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let light = &lights.directional_lights[0u];
+    if (((*light).flags & pbr_types::DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) == 0u) {
+        return 1.0;
+    }
+
+    let view_z = dot(vec4(view.inverse_view[0].z, view.inverse_view[1].z, view.inverse_view[2].z, view.inverse_view[3].z), vec4(world_position, 1.0));
+    var cascade_index = 0u;
+    for (; cascade_index < (*light).num_cascades; cascade_index++) {
+        if (-view_z < (*light).cascades[cascade_index].far_bound) {
+            break;
+        }
+    }
+    if (cascade_index >= (*light).num_cascades) {
+        return 1.0;
+    }
+
+    let cascade = &(*light).cascades[cascade_index];
+    let normal_offset = (*light).shadow_normal_bias * (*cascade).texel_size * normal;
+    let depth_offset = (*light).shadow_depth_bias * (*light).direction_to_light;
+    let clip = (*cascade).view_projection * vec4(world_position + normal_offset + depth_offset, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    if (any(ndc.xy < vec2(-1.0)) || ndc.z < 0.0 || any(ndc > vec3(1.0))) {
+        return 1.0;
+    }
+
+    let shadow_uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    let array_index = i32((*light).depth_texture_base_index + cascade_index);
+    return textureSampleCompareLevel(directional_shadow_textures, directional_shadow_sampler, shadow_uv, array_index, ndc.z);
+}
+
+// Share of a lit color that comes from the sun's diffuse term, assuming a diffuse surface lit by the sun and ambient
+// light only. The albedo cancels out, as both terms are scaled by it
+fn sun_diffuse_share(normal: vec3<f32>, to_light: vec3<f32>, shadow: f32) -> f32 {
+    let luminance = vec3(0.2126, 0.7152, 0.0722);
+    let sun = dot(lights.directional_lights[0u].color.rgb, luminance) * saturate(dot(normal, to_light)) * shadow / PI;
+    let ambient = dot(lights.ambient_color.rgb, luminance);
+    return sun / max(sun + ambient, 0.0001);
+}
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let color = textureLoad(screen_texture, vec2<i32>(in.position.xy), 0);
+    let depth = textureLoad(depth_texture, vec2<i32>(in.position.xy), 0);
+
+    // Geometric normal from the depth buffer, derivatives are taken before anything branches
+    let ndc = vec4((in.uv * 2.0 - 1.0) * vec2(1.0, -1.0), depth, 1.0);
+    let world = view.inverse_view_proj * ndc;
+    let world_position = world.xyz / world.w;
+    var normal = normalize(cross(dpdy(world_position), dpdx(world_position)));
+    normal = faceForward(normal, world_position - view.world_position, normal);
+
+    if (depth < 0.0001 || lights.n_directional_lights == 0u) {
+        return color;
+    }
+
+    let local_position = (surface.inverse_transform * vec4(world_position, 1.0)).xyz;
+
+    // Height of the displaced surface straight above, found by searching for the undisplaced point landing there
+    var point = local_position.xz;
+    var displacement = vec3(0.0);
+    for (var i = 0u; i < INVERSION_ITERATIONS; i++) {
+        displacement = sample_displacement(surface, displacement_textures, prev_displacement_textures, local_to_plane_uv(surface, point));
+        point = local_position.xz - displacement.xz;
+    }
+    let surface_uv = local_to_plane_uv(surface, point);
+    if (any(surface_uv < vec2(0.0)) || any(surface_uv > vec2(1.0))) {
+        return color;
+    }
+
+    // The ocean itself lands right on the surface, give it and anything barely wet a margin
+    let depth_below = displacement.y - local_position.y;
+    let submerged = smoothstep(0.1, 0.5, depth_below);
+    if (submerged <= 0.0) {
+        return color;
+    }
+
+    let to_light = normalize(lights.directional_lights[0u].direction_to_light);
+    let sun_dir = refracted_sun_dir(surface, to_light);
+    let intensity = caustic_intensity(surface, gradient_textures, prev_gradient_textures, world_position, depth_below, sun_dir, settings.focus);
+
+    let world_sun_dir = normalize((surface.transform * vec4(sun_dir, 0.0)).xyz);
+    let facing = saturate(dot(normal, world_sun_dir)) * saturate(to_light.y);
+    let fade = 1.0 - smoothstep(0.0, settings.max_depth, depth_below);
+
+    let share = sun_diffuse_share(normal, to_light, sun_shadow(world_position, normal));
+
+    let modulation = 1.0 + (intensity - 1.0) * settings.strength * submerged * facing * fade * share;
+    return vec4(color.rgb * max(modulation, 0.0), color.a);
+}
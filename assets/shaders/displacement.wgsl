@@ -2,14 +2,76 @@
 var<uniform> settings: OceanSettings;
 @group(0) @binding(1)
 var<storage, read_write> spectrums: array<OceanSpectrumSettings, 8>;
-@group(0) @binding(2)
-var displacement_textures: texture_storage_2d_array<rgba32float, read_write>;
 @group(0) @binding(3)
 var gradient_textures: texture_storage_2d_array<rg32float, write>;
+@group(0) @binding(6)
+var prev_displacement_textures: texture_2d_array<f32>;
+@group(0) @binding(11)
+var prev_foam_age_textures: texture_2d_array<f32>;
+@group(0) @binding(12)
+var foam_age_textures: texture_storage_2d_array<r32float, write>;
+@group(0) @binding(9)
+var velocity_textures: texture_storage_2d_array<rgba32float, write>;
+@group(0) @binding(10)
+var breaking_textures: texture_storage_2d_array<r32float, write>;
+
+#ifdef STORAGE_READ_WRITE
+@group(0) @binding(2)
+var displacement_textures: texture_storage_2d_array<rgba32float, read_write>;
 @group(0) @binding(4)
 var init_spectrum_textures: texture_storage_2d_array<rgba32float, read_write>;
 @group(0) @binding(5)
 var spectrum_textures: texture_storage_2d_array<rgba32float, read_write>;
+#else
+// Without read-write storage textures every pass reads from a sampled texture and writes to a different,
+// write-only one. The node swaps the spectrum and initial spectrum textures between passes
+@group(0) @binding(2)
+var displacement_textures: texture_storage_2d_array<rgba32float, write>;
+@group(0) @binding(4)
+var init_spectrum_textures: texture_storage_2d_array<rgba32float, write>;
+@group(0) @binding(5)
+var spectrum_textures: texture_storage_2d_array<rgba32float, write>;
+@group(0) @binding(7)
+var spectrum_input_textures: texture_2d_array<f32>;
+@group(0) @binding(8)
+var init_spectrum_input_textures: texture_2d_array<f32>;
+#endif
+
+fn load_init_spectrum(location: vec2<i32>, layer: u32) -> vec4<f32> {
+#ifdef STORAGE_READ_WRITE
+    return textureLoad(init_spectrum_textures, location, layer);
+#else
+    return textureLoad(init_spectrum_input_textures, location, layer, 0);
+#endif
+}
+
+fn load_spectrum(location: vec2<i32>, layer: u32) -> vec4<f32> {
+#ifdef STORAGE_READ_WRITE
+    return textureLoad(spectrum_textures, location, layer);
+#else
+    return textureLoad(spectrum_input_textures, location, layer, 0);
+#endif
+}
+
+// Foam intensity and age from the previous step, which has been copied to prev_displacement_textures and
+// prev_foam_age_textures before assemble_maps runs. Reads between texels, wrapping around the tile
+fn load_prev_foam(position: vec2<f32>, layer: u32) -> vec2<f32> {
+    let size = i32(settings.n);
+    let base = vec2<i32>(floor(position));
+    let t = fract(position);
+
+    let x0 = (base.x % size + size) % size;
+    let y0 = (base.y % size + size) % size;
+    let x1 = (x0 + 1) % size;
+    let y1 = (y0 + 1) % size;
+
+    let foam_00 = vec2(textureLoad(prev_displacement_textures, vec2(x0, y0), layer, 0).a, textureLoad(prev_foam_age_textures, vec2(x0, y0), layer, 0).r);
+    let foam_10 = vec2(textureLoad(prev_displacement_textures, vec2(x1, y0), layer, 0).a, textureLoad(prev_foam_age_textures, vec2(x1, y0), layer, 0).r);
+    let foam_01 = vec2(textureLoad(prev_displacement_textures, vec2(x0, y1), layer, 0).a, textureLoad(prev_foam_age_textures, vec2(x0, y1), layer, 0).r);
+    let foam_11 = vec2(textureLoad(prev_displacement_textures, vec2(x1, y1), layer, 0).a, textureLoad(prev_foam_age_textures, vec2(x1, y1), layer, 0).r);
+
+    return mix(mix(foam_00, foam_10, t.x), mix(foam_01, foam_11, t.x), t.y);
+}
 
 struct OceanSettings {
     lambda: vec2<f32>,
@@ -24,6 +86,12 @@ struct OceanSettings {
     length_scale_1: u32,
     length_scale_2: u32,
     length_scale_3: u32,
+    update_interval_0: u32,
+    update_interval_1: u32,
+    update_interval_2: u32,
+    update_interval_3: u32,
+    update_mask: u32,
+    suspended_time: f32,
     foam_threshold: f32,
     depth: f32,
     low_cutoff: f32,
@@ -31,6 +99,10 @@ struct OceanSettings {
     foam_bias: f32,
     foam_decay_rate: f32,
     foam_add: f32,
+    foam_advection: f32,
+    time_scale: f32,
+    compute_velocities: u32,
+    cascade_delta_time: vec4<f32>,
 
 #ifdef SIXTEEN_BYTE_ALIGNMENT
     _webgl_padding: f32,
@@ -50,6 +122,29 @@ struct OceanSpectrumSettings {
 
 const PI: f32 = 3.1415927;
 const TAU: f32 = 6.2831853;
+// Furthest foam moves in one step, in texels. Steps that jump, like the first after the spectrum changes, would
+// otherwise smear it across the tile
+const MAX_FOAM_ADVECTION: f32 = 2.0;
+// Seconds, foam this old has long finished thinning out
+const MAX_FOAM_AGE: f32 = 600.0;
+
+// Cascades are only updated on the steps selected by the scheduler
+fn cascade_active(i: u32) -> bool {
+    return ((settings.update_mask >> i) & 1u) != 0u;
+}
+
+// Each cascade has three spectrum layers: displacement, slope and orbital velocity
+const SPECTRUM_LAYERS_PER_CASCADE: u32 = 3u;
+
+fn spectrum_layer_active(layer: u32) -> bool {
+    let is_velocity = layer % SPECTRUM_LAYERS_PER_CASCADE == 2u;
+    return cascade_active(layer / SPECTRUM_LAYERS_PER_CASCADE) && (!is_velocity || settings.compute_velocities != 0u);
+}
+
+// Simulated time since the cascade was last updated, however many steps that took
+fn cascade_delta_time(i: u32) -> f32 {
+    return settings.cascade_delta_time[i];
+}
 
 fn complex_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
     return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
@@ -208,9 +303,9 @@ fn pack_spectrum_conjugates(@builtin(global_invocation_id) id: vec3<u32>) {
     let loc = vec2<i32>(id.xy);
     let n = i32(settings.n);
     for (var i = 0u; i < settings.compute_layers; i++) {
-        let h0 = textureLoad(init_spectrum_textures, id.xy, i).xy;
+        let h0 = load_init_spectrum(loc, i).xy;
         let conj_pos = vec2((n - loc.x) % n, (n - loc.y) % n);
-        let conj = textureLoad(init_spectrum_textures, conj_pos, i).xy;
+        let conj = load_init_spectrum(conj_pos, i).xy;
 
         // storageBarrier();
         textureStore(init_spectrum_textures, id.xy, i, vec4(h0, conj.x, -conj.y));
@@ -224,7 +319,11 @@ fn update_spectrum(@builtin(global_invocation_id) id: vec3<u32>) {
     let location = vec2<f32>(id.xy);
 
     for (var i = 0u; i < settings.compute_layers; i++) {
-        let init_signal = textureLoad(init_spectrum_textures, id.xy, i);
+        if (!cascade_active(i)) {
+            continue;
+        }
+
+        let init_signal = load_init_spectrum(vec2<i32>(id.xy), i);
         let h0 = init_signal.xy;
         let h0_conj = init_signal.zw;
 
@@ -237,11 +336,14 @@ fn update_spectrum(@builtin(global_invocation_id) id: vec3<u32>) {
         }
 
         let w_0 = TAU / settings.repeat_time;
-        let dispersion = floor(sqrt(settings.gravity * k_mag) / w_0) * w_0 * settings.frame_time;
+        let omega = floor(sqrt(settings.gravity * k_mag) / w_0) * w_0;
+        let dispersion = omega * settings.frame_time;
 
         let exponent = euler_formula(dispersion);
 
-        let h_tilde = complex_mul(h0, exponent) + complex_mul(h0_conj, vec2(exponent.x, -exponent.y));
+        let wave = complex_mul(h0, exponent);
+        let wave_conj = complex_mul(h0_conj, vec2(exponent.x, -exponent.y));
+        let h_tilde = wave + wave_conj;
         let ih = vec2(-h_tilde.y, h_tilde.x);
 
         let displacement_x = ih * k.x * k_mag_rcp;
@@ -262,15 +364,28 @@ fn update_spectrum(@builtin(global_invocation_id) id: vec3<u32>) {
         let h_tilde_grad_z = vec2(displacement_x_dx.x - displacement_z_dz.y, displacement_x_dx.y + displacement_z_dz.x);
 
         // storageBarrier();
-        textureStore(spectrum_textures, id.xy, i * 2u, vec4(h_tilde_displacement_x, h_tilde_displacement_z));
-        textureStore(spectrum_textures, id.xy, i * 2u + 1u, vec4(h_tilde_grad_x, h_tilde_grad_z));
+        let layer = i * SPECTRUM_LAYERS_PER_CASCADE;
+        textureStore(spectrum_textures, id.xy, layer, vec4(h_tilde_displacement_x, h_tilde_displacement_z));
+        textureStore(spectrum_textures, id.xy, layer + 1u, vec4(h_tilde_grad_x, h_tilde_grad_z));
+
+        if (settings.compute_velocities != 0u) {
+            // Time derivative of h_tilde per second of real time, i * omega * (wave - wave_conj)
+            let omega_wave = settings.time_scale * omega * (wave - wave_conj);
+            let velocity_y = vec2(-omega_wave.y, omega_wave.x);
+
+            // The horizontal velocities are i * k / |k| times the vertical one, same as the displacements
+            let velocity_x = -omega_wave * k.x * k_mag_rcp;
+            let velocity_z = -omega_wave * k.y * k_mag_rcp;
+
+            let h_tilde_velocity_xz = vec2(velocity_x.x - velocity_z.y, velocity_x.y + velocity_z.x);
+            textureStore(spectrum_textures, id.xy, layer + 2u, vec4(h_tilde_velocity_xz, velocity_y));
+        }
     }
 }
 
-const SIZE: u32 = 256u;
-const LOG_SIZE: u32 = 8u;
-// const SIZE: u32 = 1024u;
-// const LOG_SIZE: u32 = 10u;
+// Specialized per fft resolution by the pipeline
+const SIZE: u32 = #{FFT_SIZE}u;
+const LOG_SIZE: u32 = #{LOG_FFT_SIZE}u;
 
 fn twiddle_factor_and_input_indices(id: vec2<u32>) -> vec4<f32> {
     let b = settings.n >> (id.x + 1u);
@@ -322,20 +437,26 @@ fn fft(thread_idx: u32, input: vec4<f32>) -> vec4<f32> {
     return fft_group_buffer[flag][thread_idx];
 }
 
-@compute @workgroup_size(256, 1, 1)
-// @compute @workgroup_size(1024, 1, 1)
+@compute @workgroup_size(#{FFT_SIZE}, 1, 1)
 fn horizontal_fft(@builtin(global_invocation_id) id: vec3<u32>) {
-    for (var i = 0u; i < settings.compute_layers * 2u; i++) {
-        let old = textureLoad(spectrum_textures, id.xy, i);
+    for (var i = 0u; i < settings.compute_layers * SPECTRUM_LAYERS_PER_CASCADE; i++) {
+        if (!spectrum_layer_active(i)) {
+            continue;
+        }
+
+        let old = load_spectrum(vec2<i32>(id.xy), i);
         textureStore(spectrum_textures, id.xy, i, fft(id.x, old));
     }
 }
 
-@compute @workgroup_size(256, 1, 1)
-// @compute @workgroup_size(1024, 1, 1)
+@compute @workgroup_size(#{FFT_SIZE}, 1, 1)
 fn vertical_fft(@builtin(global_invocation_id) id: vec3<u32>) {
-    for (var i = 0u; i < settings.compute_layers * 2u; i++) {
-        let old = textureLoad(spectrum_textures, id.yx, i);
+    for (var i = 0u; i < settings.compute_layers * SPECTRUM_LAYERS_PER_CASCADE; i++) {
+        if (!spectrum_layer_active(i)) {
+            continue;
+        }
+
+        let old = load_spectrum(vec2<i32>(id.yx), i);
         textureStore(spectrum_textures, id.yx, i, fft(id.x, old));
     }
 }
@@ -347,9 +468,16 @@ fn permute(data: vec4<f32>, id: vec2<f32>) -> vec4<f32> {
 
 @compute @workgroup_size(8, 8, 1)
 fn assemble_maps(@builtin(global_invocation_id) id: vec3<u32>) {
+    let length_scales = vec4<u32>(settings.length_scale_0, settings.length_scale_1, settings.length_scale_2, settings.length_scale_3);
+
     for (var i = 0u; i < settings.compute_layers; i++) {
-        let h_tilde_displacement = permute(textureLoad(spectrum_textures, id.xy, i * 2u), vec2<f32>(id.xy));
-        let h_tilde_slope = permute(textureLoad(spectrum_textures, id.xy, i * 2u + 1u), vec2<f32>(id.xy));
+        if (!cascade_active(i)) {
+            continue;
+        }
+
+        let layer = i * SPECTRUM_LAYERS_PER_CASCADE;
+        let h_tilde_displacement = permute(load_spectrum(vec2<i32>(id.xy), layer), vec2<f32>(id.xy));
+        let h_tilde_slope = permute(load_spectrum(vec2<i32>(id.xy), layer + 1u), vec2<f32>(id.xy));
 
         let dxdz = h_tilde_displacement.xy;
         let dydxz = h_tilde_displacement.zw;
@@ -363,18 +491,56 @@ fn assemble_maps(@builtin(global_invocation_id) id: vec3<u32>) {
         let gradients = dyxdyz.xy / (1.0 + abs(dxxdzz * settings.lambda));
         let covariance = gradients.x * gradients.y;
 
-        var foam = textureLoad(displacement_textures, id.xy, i).a;
-        foam *= exp(-settings.foam_decay_rate);
+        // foam_decay_rate and foam_add are per second, scaled by the time since this cascade was last updated.
+        // Foam keeps decaying and ageing while the simulation is suspended, but isn't added for that time
+        let delta_time = cascade_delta_time(i);
+
+        // Horizontal orbital velocity of the water, from the velocity spectrum when it's computed. Otherwise from how
+        // far the horizontal displacement moved since the last step, unless that step is from before a suspension
+        var velocity = vec3(0.0);
+        if (settings.compute_velocities != 0u) {
+            let h_tilde_velocity = permute(load_spectrum(vec2<i32>(id.xy), layer + 2u), vec2<f32>(id.xy));
+            velocity = vec3(settings.lambda.x * h_tilde_velocity.x, h_tilde_velocity.z, settings.lambda.y * h_tilde_velocity.y);
+        } else if (settings.suspended_time == 0.0 && delta_time > 0.0) {
+            let prev_displacement = textureLoad(prev_displacement_textures, vec2<i32>(id.xy), i, 0).xyz;
+            velocity = vec3(displacement.x - prev_displacement.x, 0.0, displacement.z - prev_displacement.z) / delta_time;
+        }
+
+        // Foam is carried with the water under it, so it's picked up from where the orbital velocity has brought
+        // that water from since the last step
+        var advection = velocity.xz * delta_time * settings.foam_advection * f32(settings.n) / f32(length_scales[i]);
+        advection = clamp(advection, vec2(-MAX_FOAM_ADVECTION), vec2(MAX_FOAM_ADVECTION));
+        let prev_foam = load_prev_foam(vec2<f32>(id.xy) - advection, i);
+
+        var foam = prev_foam.x;
+        foam *= exp(-settings.foam_decay_rate * (delta_time + settings.suspended_time));
         foam = saturate(foam);
+        var foam_age = prev_foam.y + delta_time + settings.suspended_time;
 
         let biased_jacobian = max(0.0, -(jacobian - settings.foam_bias));
 
-        if (biased_jacobian > settings.foam_threshold) {
-            foam += settings.foam_add * biased_jacobian;
+        let breaking = biased_jacobian > settings.foam_threshold;
+        if (breaking) {
+            // Fresh foam has no age, mixing it in makes the foam here younger by the share it adds
+            let added = settings.foam_add * biased_jacobian * delta_time;
+            foam_age *= foam / max(foam + added, 0.0001);
+            foam += added;
         }
+        // Faded out foam leaves nothing to age
+        foam_age = select(0.0, min(foam_age, MAX_FOAM_AGE), foam > 0.0001);
 
         // storageBarrier();
         textureStore(displacement_textures, id.xy, i, vec4(displacement, foam));
         textureStore(gradient_textures, id.xy, i, vec4(gradients, 0.0, 0.0));
+        textureStore(foam_age_textures, id.xy, i, vec4(foam_age, 0.0, 0.0, 0.0));
+        textureStore(breaking_textures, id.xy, i, vec4(select(0.0, biased_jacobian - settings.foam_threshold, breaking), 0.0, 0.0, 0.0));
+
+        if (settings.compute_velocities != 0u) {
+            // The surface moves horizontally too, so the height at a fixed point changes by less than the
+            // vertical velocity of the water there
+            let height_rate = velocity.y - dot(velocity.xz, gradients);
+
+            textureStore(velocity_textures, id.xy, i, vec4(velocity, height_rate));
+        }
     }
 }
\ No newline at end of file
@@ -4,11 +4,18 @@
 #import bevy_pbr::skinning
 #import bevy_pbr::morph
 #import bevy_pbr::mesh_bindings       mesh
-#import bevy_pbr::mesh_vertex_output  MeshVertexOutput
 #import bevy_pbr::mesh_view_bindings as view_bindings
 #import bevy_pbr::mesh_view_types
+#import bevy_pbr::mesh_types
+#import bevy_pbr::clustered_forward as clustering
+#import bevy_pbr::shadows as shadows
 #import bevy_pbr::prepass_utils as prepass_utils
 #import ocean::sky SkySettings
+#import ocean::sky fog_visibility
+#import ocean::sky underwater_fog
+#import ocean::sky underwater_inscatter
+#import ocean::surface SurfaceSettings
+#import ocean::surface water_level
 
 struct Vertex {
 #ifdef VERTEX_POSITIONS
@@ -51,6 +58,48 @@ var gradient_sampler: sampler;
 var skybox_texture: texture_cube<f32>;
 @group(1) @binding(7)
 var skybox_sampler: sampler;
+@group(1) @binding(8)
+var prev_displacement_textures: texture_2d_array<f32>;
+@group(1) @binding(9)
+var prev_displacement_sampler: sampler;
+@group(1) @binding(10)
+var prev_gradient_textures: texture_2d_array<f32>;
+@group(1) @binding(11)
+var prev_gradient_sampler: sampler;
+@group(1) @binding(12)
+var ripple_texture: texture_2d<f32>;
+@group(1) @binding(13)
+var ripple_sampler: sampler;
+@group(1) @binding(14)
+var wake_texture: texture_2d<f32>;
+@group(1) @binding(15)
+var wake_sampler: sampler;
+@group(1) @binding(16)
+var scene_color_texture: texture_2d<f32>;
+@group(1) @binding(17)
+var scene_color_sampler: sampler;
+@group(1) @binding(18)
+var planar_reflection_texture: texture_2d<f32>;
+@group(1) @binding(19)
+var planar_reflection_sampler: sampler;
+@group(1) @binding(20)
+var opaque_color_texture: texture_2d<f32>;
+@group(1) @binding(21)
+var opaque_color_sampler: sampler;
+@group(1) @binding(22)
+var foam_albedo_texture: texture_2d<f32>;
+@group(1) @binding(23)
+var foam_albedo_sampler: sampler;
+@group(1) @binding(24)
+var foam_normal_texture: texture_2d<f32>;
+@group(1) @binding(25)
+var foam_normal_sampler: sampler;
+@group(1) @binding(26)
+var foam_age_textures: texture_2d_array<f32>;
+@group(1) @binding(27)
+var foam_age_sampler: sampler;
+@group(1) @binding(28)
+var<uniform> surface_settings: SurfaceSettings;
 
 
 struct OceanSettings {
@@ -63,7 +112,6 @@ struct OceanSettings {
     roughness: f32,
     foam_roughness: f32,
 
-    sun_power: f32,
     scatter_color: vec3<f32>,
     bubble_color: vec3<f32>,
     foam_color: vec3<f32>,
@@ -74,36 +122,156 @@ struct OceanSettings {
     scatter_strength: f32,
     scatter_shadow_strength: f32,
     environment_light_strength: f32,
+    shadow_filter_radius: f32,
 
     foam_subtract: f32,
+    simulation_blend: vec4<f32>,
+    active_layers: u32,
 
     tile_layers: vec4<f32>,
     contribute_layers: vec4<f32>,
+
+    ripple_origin: vec2<f32>,
+    ripple_size: f32,
+    wake_origin: vec2<f32>,
+    wake_size: f32,
+
+    ssr_max_distance: f32,
+    ssr_thickness: f32,
+    planar_distortion: f32,
+    prev_view_proj: mat4x4<f32>,
+    planar_view_proj: mat4x4<f32>,
+    refraction_distortion: f32,
+    absorption: vec3<f32>,
+
+    contact_foam_width: f32,
+    contact_foam_noise: f32,
+    contact_foam_noise_scale: f32,
+    contact_foam_speed: f32,
+
+    foam_tiling: vec4<f32>,
+    foam_distortion: f32,
+    foam_lace_age: f32,
+    foam_normal_strength: f32,
+}
+
+struct OceanVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    // Unwrapped cascade uvs, two per vector, wrapped per fragment so they interpolate across tile borders
+    @location(3) cascade_uv_12: vec4<f32>,
+    @location(4) cascade_uv_34: vec4<f32>,
+    @location(5) foam: f32,
+    // Vertical displacement from the cascades and local waves
+    @location(6) height: f32,
 }
 
 // struct SkySettings {
-//     sun_color: vec3<f32>,
+//     sun_strength: f32,
 //     sun_falloff: f32,
 // }
 
 
+// Same offsets as the cascade uvs the compute passes read
+const LAYER_OFFSETS: vec4<f32> = vec4(0.0, 0.5, 1.125, 1.25);
+
+fn cascade_uv(uv: vec2<f32>, layer: u32) -> vec2<f32> {
+    return (uv - LAYER_OFFSETS[layer]) * settings.tile_layers[layer];
+}
+
+// The simulation runs at a fixed rate, and slow cascades less often than that, so blend between the last two steps
+fn sample_displacement(uv: vec2<f32>, layer: i32) -> vec4<f32> {
+    let prev = textureSampleLevel(prev_displacement_textures, prev_displacement_sampler, uv, layer, 0.0);
+    let current = textureSampleLevel(displacement_textures, displacement_sampler, uv, layer, 0.0);
+    return mix(prev, current, settings.simulation_blend[layer]);
+}
+
+// Cascades the compute pass isn't producing hold stale data, so they don't contribute
+fn layer_contribution(layer: u32) -> f32 {
+    return settings.contribute_layers[layer] * f32(layer < settings.active_layers);
+}
+
+fn sample_gradient(uv: vec2<f32>, layer: i32) -> vec4<f32> {
+    let prev = textureSampleLevel(prev_gradient_textures, prev_gradient_sampler, uv, layer, 0.0);
+    let current = textureSampleLevel(gradient_textures, gradient_sampler, uv, layer, 0.0);
+    return mix(prev, current, settings.simulation_blend[layer]);
+}
+
+// Height, gradient and foam of a world space heightfield covering a square at `origin`, faded out towards its edges
+fn sample_local_heightfield(
+    heightfield: texture_2d<f32>,
+    heightfield_sampler: sampler,
+    origin: vec2<f32>,
+    size: f32,
+    world_xz: vec2<f32>,
+) -> vec4<f32> {
+    if (size <= 0.0) {
+        return vec4(0.0);
+    }
+
+    let uv = (world_xz - origin) / size;
+    let edge = min(min(uv.x, uv.y), min(1.0 - uv.x, 1.0 - uv.y));
+    let fade = smoothstep(0.0, 0.1, edge);
+    if (fade <= 0.0) {
+        return vec4(0.0);
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(heightfield));
+    let state = textureSampleLevel(heightfield, heightfield_sampler, uv, 0.0);
+    let right = textureSampleLevel(heightfield, heightfield_sampler, uv + vec2(texel.x, 0.0), 0.0).x;
+    let left = textureSampleLevel(heightfield, heightfield_sampler, uv - vec2(texel.x, 0.0), 0.0).x;
+    let up = textureSampleLevel(heightfield, heightfield_sampler, uv + vec2(0.0, texel.y), 0.0).x;
+    let down = textureSampleLevel(heightfield, heightfield_sampler, uv - vec2(0.0, texel.y), 0.0).x;
+
+    let gradient = vec2(right - left, up - down) / (2.0 * texel * size);
+    return vec4(state.x, gradient, state.z) * fade;
+}
+
+// Ripples from the local simulation and ship wakes, added on top of the cascades
+fn sample_local_waves(world_xz: vec2<f32>) -> vec4<f32> {
+    let ripples = sample_local_heightfield(ripple_texture, ripple_sampler, settings.ripple_origin, settings.ripple_size, world_xz);
+    let wakes = sample_local_heightfield(wake_texture, wake_sampler, settings.wake_origin, settings.wake_size, world_xz);
+    return vec4(ripples.xyz + wakes.xyz, max(ripples.w, wakes.w));
+}
+
+// Slope of the surface summed over the cascades, before any normal strength is applied
+fn sample_cascade_gradient(cascade_uv_12: vec4<f32>, cascade_uv_34: vec4<f32>) -> vec2<f32> {
+    let gradient_1 = sample_gradient(fract(cascade_uv_12.xy), 0) * layer_contribution(0u);
+    let gradient_2 = sample_gradient(fract(cascade_uv_12.zw), 1) * layer_contribution(1u);
+    let gradient_3 = sample_gradient(fract(cascade_uv_34.xy), 2) * layer_contribution(2u);
+    let gradient_4 = sample_gradient(fract(cascade_uv_34.zw), 3) * layer_contribution(3u);
+    return gradient_1.xy + gradient_2.xy + gradient_3.xy + gradient_4.xy;
+}
+
+fn gradient_to_normal(gradient: vec2<f32>) -> vec3<f32> {
+    return normalize(vec3(-gradient.x, 1.0, -gradient.y));
+}
+
+// World space slope of the surface. The cascades are in the ocean's local space, so their normal is taken through the
+// model matrix before the local waves, which are already in world space, are added. Same as the CPU sampler
+fn world_gradient(cascade_gradient: vec2<f32>, local_waves_gradient: vec2<f32>) -> vec2<f32> {
+    let normal = mesh_functions::mesh_normal_local_to_world(gradient_to_normal(cascade_gradient));
+    return -normal.xz / max(normal.y, 0.0001) + local_waves_gradient;
+}
+
+
 @vertex
-fn vertex(vertex: Vertex) -> MeshVertexOutput {
+fn vertex(vertex: Vertex) -> OceanVertexOutput {
     let uv = vertex.uv;
 
-    let uv1 = fract(uv * settings.tile_layers.x);
-    let uv2 = fract((uv - 0.5) * settings.tile_layers.y);
-    let uv3 = fract((uv - 1.125) * settings.tile_layers.z);
-    let uv4 = fract((uv - 1.25) * settings.tile_layers.w);
-
-    var displacement_1 = textureSampleLevel(displacement_textures, displacement_sampler, uv1, 0, 0.0); 
-    var displacement_2 = textureSampleLevel(displacement_textures, displacement_sampler, uv2, 1, 0.0); 
-    var displacement_3 = textureSampleLevel(displacement_textures, displacement_sampler, uv3, 2, 0.0); 
-    var displacement_4 = textureSampleLevel(displacement_textures, displacement_sampler, uv4, 3, 0.0); 
-    displacement_1 = vec4(displacement_1.rgb * settings.contribute_layers.x, displacement_1.a);
-    displacement_2 = vec4(displacement_2.rgb * settings.contribute_layers.y, displacement_2.a);
-    displacement_3 = vec4(displacement_3.rgb * settings.contribute_layers.z, displacement_3.a);
-    displacement_4 = vec4(displacement_4.rgb * settings.contribute_layers.w, displacement_4.a);
+    let cascade_uv_12 = vec4(cascade_uv(uv, 0u), cascade_uv(uv, 1u));
+    let cascade_uv_34 = vec4(cascade_uv(uv, 2u), cascade_uv(uv, 3u));
+
+    var displacement_1 = sample_displacement(fract(cascade_uv_12.xy), 0); 
+    var displacement_2 = sample_displacement(fract(cascade_uv_12.zw), 1); 
+    var displacement_3 = sample_displacement(fract(cascade_uv_34.xy), 2); 
+    var displacement_4 = sample_displacement(fract(cascade_uv_34.zw), 3); 
+    displacement_1 = vec4(displacement_1.rgb * layer_contribution(0u), displacement_1.a);
+    displacement_2 = vec4(displacement_2.rgb * layer_contribution(1u), displacement_2.a);
+    displacement_3 = vec4(displacement_3.rgb * layer_contribution(2u), displacement_3.a);
+    displacement_4 = vec4(displacement_4.rgb * layer_contribution(3u), displacement_4.a);
     var displacement = displacement_1 + displacement_2 + displacement_3 + displacement_4;
     displacement.a += settings.foam_subtract;
 
@@ -115,13 +283,22 @@ fn vertex(vertex: Vertex) -> MeshVertexOutput {
 
     let position = vertex.position + displacement.xyz;
 
-    var out: MeshVertexOutput;
+    var out: OceanVertexOutput;
 
     out.world_position = mesh_functions::mesh_position_local_to_world(model, vec4<f32>(position, 1.0));
+
+    let local_waves = sample_local_waves(out.world_position.xz);
+    out.world_position.y += local_waves.x;
     out.position = mesh_functions::mesh_position_world_to_clip(out.world_position);
 
+    let gradient = world_gradient(sample_cascade_gradient(cascade_uv_12, cascade_uv_34), local_waves.yz);
+    out.world_normal = gradient_to_normal(gradient);
+
     out.uv = vertex.uv;
-    out.world_normal = vec3(displacement.a, displacement.y, 0.0);
+    out.cascade_uv_12 = cascade_uv_12;
+    out.cascade_uv_34 = cascade_uv_34;
+    out.foam = displacement.a + local_waves.w;
+    out.height = displacement.y + local_waves.x;
 
     return out;
 }
@@ -133,9 +310,9 @@ fn linearize_depth(depth: f32) -> f32 {
     return mix(1.0, view_bindings::view.projection[3][2] / depth / far_plane, f32(depth > 0.0001));
 }
 
-fn get_sky_color(dir: vec3<f32>, sun_dir: vec3<f32>) -> vec3<f32> {
+fn get_sky_color(dir: vec3<f32>, sun_dir: vec3<f32>, sun_radiance: vec3<f32>) -> vec3<f32> {
     let sky = textureSample(skybox_texture, skybox_sampler, dir).xyz;
-    let sun = sky_settings.sun_color * pow(saturate(dot(dir, sun_dir)), sky_settings.sun_falloff);
+    let sun = sun_radiance * sky_settings.sun_strength * pow(saturate(dot(dir, sun_dir)), sky_settings.sun_falloff);
     return sky + sun;
 }
 
@@ -155,82 +332,642 @@ fn beckmann(n_dot_h: f32, roughness: f32) -> f32 {
     return exp(expo) / (PI * roughness * roughness * n_dot_h * n_dot_h * n_dot_h * n_dot_h);
 }
 
+fn view_space_z(world_position: vec3<f32>) -> f32 {
+    return dot(vec4(
+        view_bindings::view.inverse_view[0].z,
+        view_bindings::view.inverse_view[1].z,
+        view_bindings::view.inverse_view[2].z,
+        view_bindings::view.inverse_view[3].z
+    ), vec4(world_position, 1.0));
+}
+
+fn receives_shadows() -> bool {
+    return (mesh.flags & bevy_pbr::mesh_types::MESH_FLAGS_SHADOW_RECEIVER_BIT) != 0u;
+}
+
+fn sample_directional_shadow_map(uv: vec2<f32>, layer: u32, depth: f32) -> f32 {
+#ifdef NO_ARRAY_TEXTURES_SUPPORT
+    return textureSampleCompareLevel(
+        view_bindings::directional_shadow_textures,
+        view_bindings::directional_shadow_textures_sampler,
+        uv,
+        depth
+    );
+#else
+    return textureSampleCompareLevel(
+        view_bindings::directional_shadow_textures,
+        view_bindings::directional_shadow_textures_sampler,
+        uv,
+        i32(layer),
+        depth
+    );
+#endif
+}
+
+const SHADOW_FILTER_TAPS: u32 = 8u;
+
+// Bevy's cascade lookup, with a rotated spiral of hardware PCF taps around the receiver instead of a single one
+fn sample_shadow_cascade(light_id: u32, cascade_index: u32, world_position: vec3<f32>, normal: vec3<f32>, rotation: f32) -> f32 {
+    let light = &view_bindings::lights.directional_lights[light_id];
+    let cascade = &(*light).cascades[cascade_index];
+
+    let normal_offset = (*light).shadow_normal_bias * (*cascade).texel_size * normal;
+    let depth_offset = (*light).shadow_depth_bias * (*light).direction_to_light;
+    let offset_position_clip = (*cascade).view_projection * vec4(world_position + normal_offset + depth_offset, 1.0);
+    if (offset_position_clip.w <= 0.0) {
+        return 1.0;
+    }
+    let offset_position_ndc = offset_position_clip.xyz / offset_position_clip.w;
+    if (any(offset_position_ndc.xy < vec2(-1.0)) || offset_position_ndc.z < 0.0 || any(offset_position_ndc > vec3(1.0))) {
+        return 1.0;
+    }
+
+    let light_local = offset_position_ndc.xy * vec2(0.5, -0.5) + vec2(0.5);
+    let layer = (*light).depth_texture_base_index + cascade_index;
+    let radius = settings.shadow_filter_radius / vec2<f32>(textureDimensions(view_bindings::directional_shadow_textures));
+
+    var shadow = 0.0;
+    for (var i = 0u; i < SHADOW_FILTER_TAPS; i++) {
+        let distance = sqrt((f32(i) + 0.5) / f32(SHADOW_FILTER_TAPS));
+        let angle = f32(i) * 2.3999632 + rotation;
+        let offset = vec2(cos(angle), sin(angle)) * distance * radius;
+        shadow += sample_directional_shadow_map(light_local + offset, layer, offset_position_ndc.z);
+    }
+    return shadow / f32(SHADOW_FILTER_TAPS);
+}
+
+// Shadowing of the first directional light at the displaced surface, blending between cascades like Bevy does
+fn directional_shadow(world_position: vec3<f32>, normal: vec3<f32>, frag_coord: vec2<f32>) -> f32 {
+    let light = &view_bindings::lights.directional_lights[0u];
+    if (view_bindings::lights.n_directional_lights == 0u || !receives_shadows()
+            || ((*light).flags & bevy_pbr::mesh_view_types::DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) == 0u) {
+        return 1.0;
+    }
+
+    let view_z = view_space_z(world_position);
+
+    var cascade_index = (*light).num_cascades;
+    for (var i = 0u; i < (*light).num_cascades; i++) {
+        if (-view_z < (*light).cascades[i].far_bound) {
+            cascade_index = i;
+            break;
+        }
+    }
+    if (cascade_index >= (*light).num_cascades) {
+        return 1.0;
+    }
+
+    // Interleaved gradient noise, so neighbouring pixels rotate the filter differently
+    let rotation = 2.0 * PI * fract(52.982919 * fract(dot(frag_coord, vec2(0.06711056, 0.00583715))));
+
+    var shadow = sample_shadow_cascade(0u, cascade_index, world_position, normal, rotation);
+
+    let next_cascade_index = cascade_index + 1u;
+    if (next_cascade_index < (*light).num_cascades) {
+        let this_far_bound = (*light).cascades[cascade_index].far_bound;
+        let next_near_bound = (1.0 - (*light).cascades_overlap_proportion) * this_far_bound;
+        if (-view_z >= next_near_bound) {
+            let next_shadow = sample_shadow_cascade(0u, next_cascade_index, world_position, normal, rotation);
+            shadow = mix(shadow, next_shadow, (-view_z - next_near_bound) / (this_far_bound - next_near_bound));
+        }
+    }
+    return shadow;
+}
+
+fn clip_to_uv(clip: vec4<f32>) -> vec2<f32> {
+    return clip.xy / clip.w * vec2(0.5, -0.5) + 0.5;
+}
+
+fn outside_screen(uv: vec2<f32>) -> bool {
+    return any(uv < vec2(0.0)) || any(uv > vec2(1.0));
+}
+
+#ifdef OCEAN_SSR
+const SSR_STEPS: u32 = 32u;
+const SSR_REFINE_STEPS: u32 = 5u;
+
+// How far behind the depth prepass a point is along its view ray in x, and in y whether it's on screen at all
+fn ssr_depth_behind(world_position: vec3<f32>) -> vec2<f32> {
+    let clip = view_bindings::view.view_proj * vec4(world_position, 1.0);
+    if (clip.w <= 0.0) {
+        return vec2(0.0);
+    }
+    let uv = clip_to_uv(clip);
+    if (outside_screen(uv)) {
+        return vec2(0.0);
+    }
+
+    let frag_coord = view_bindings::view.viewport.xy + uv * view_bindings::view.viewport.zw;
+    let scene_depth = prepass_utils::prepass_depth(vec4(frag_coord, 0.0, 0.0), 0u);
+    // Reverse z with an infinite far plane, distance is near over depth
+    let scene_distance = view_bindings::view.projection[3][2] / max(scene_depth, 0.0000001);
+    return vec2(clip.w - scene_distance, 1.0);
+}
+
+// Marches the reflected ray through the depth prepass and looks up last frame's color where it hits, alpha is how
+// much the hit can be trusted
+fn screen_space_reflection(origin: vec3<f32>, dir: vec3<f32>) -> vec4<f32> {
+    var near_t = 0.0;
+    var far_t = 0.0;
+    var hit = false;
+    for (var i = 1u; i <= SSR_STEPS; i++) {
+        // Steps grow with distance, detail close to the surface matters most
+        let step = f32(i) / f32(SSR_STEPS);
+        near_t = far_t;
+        far_t = step * step * settings.ssr_max_distance;
+
+        let behind = ssr_depth_behind(origin + dir * far_t);
+        if (behind.y == 0.0) {
+            break;
+        }
+        if (behind.x > 0.0 && behind.x < settings.ssr_thickness) {
+            hit = true;
+            break;
+        }
+    }
+    if (!hit) {
+        return vec4(0.0);
+    }
+
+    for (var i = 0u; i < SSR_REFINE_STEPS; i++) {
+        let mid_t = (near_t + far_t) * 0.5;
+        if (ssr_depth_behind(origin + dir * mid_t).x > 0.0) {
+            far_t = mid_t;
+        } else {
+            near_t = mid_t;
+        }
+    }
+
+    let prev_clip = settings.prev_view_proj * vec4(origin + dir * far_t, 1.0);
+    if (prev_clip.w <= 0.0) {
+        return vec4(0.0);
+    }
+    let prev_uv = clip_to_uv(prev_clip);
+    if (outside_screen(prev_uv)) {
+        return vec4(0.0);
+    }
+
+    let edge = min(min(prev_uv.x, prev_uv.y), min(1.0 - prev_uv.x, 1.0 - prev_uv.y));
+    let confidence = smoothstep(0.0, 0.1, edge) * (1.0 - smoothstep(0.7, 1.0, far_t / settings.ssr_max_distance));
+    let color = textureSampleLevel(scene_color_texture, scene_color_sampler, prev_uv, 0.0).rgb;
+    return vec4(color * confidence, confidence);
+}
+#endif
+
+#ifdef OCEAN_PLANAR_REFLECTION
+// What the mirrored camera saw above this point of the mean water level, premultiplied and transparent where it saw
+// nothing. The normal shifts the lookup, standing in for the rays a flat mirror doesn't send
+fn planar_reflection(world_position: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+    let water_level = mesh.model[3].y;
+    let position = vec3(world_position.x, water_level, world_position.z) + vec3(normal.x, 0.0, normal.z) * settings.planar_distortion;
+
+    let clip = settings.planar_view_proj * vec4(position, 1.0);
+    if (clip.w <= 0.0) {
+        return vec4(0.0);
+    }
+    let uv = clip_to_uv(clip);
+    if (outside_screen(uv)) {
+        return vec4(0.0);
+    }
+    return textureSampleLevel(planar_reflection_texture, planar_reflection_sampler, uv, 0.0);
+}
+#endif
+
+#ifdef OCEAN_TRANSPARENT
+// The depth prepass without the water in it, what's under the surface at a screen uv
+fn scene_depth(uv: vec2<f32>) -> f32 {
+    let viewport_size = view_bindings::view.viewport.zw;
+    let frag_coord = view_bindings::view.viewport.xy + min(saturate(uv) * viewport_size, viewport_size - 1.0);
+    return prepass_utils::prepass_depth(vec4(frag_coord, 0.0, 0.0), 0u);
+}
+
+// The sky pass fogs the scene and adds the sun's glow before transparent geometry is drawn, so the ocean has to do it
+// itself
+fn sky_pass_composite(color: vec3<f32>, world_position: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>, sun_irradiance: vec3<f32>) -> vec3<f32> {
+    let view_dist = -view_space_z(world_position);
+    let sun = sun_irradiance * sky_settings.sun_strength * pow(saturate(dot(-view_dir, light_dir)), sky_settings.sun_falloff);
+    return mix(sky_settings.fog_color, color, fog_visibility(sky_settings, world_position.y, view_dist)) + sun;
+}
+#endif
+
+#ifdef OCEAN_REFRACTION
+struct OceanRefraction {
+    // Opaque scene color behind the surface
+    color: vec3<f32>,
+    // Fraction of it making it through the water column, per channel
+    transmittance: vec3<f32>,
+}
+
+// Looks up what's under the water, bent by the normal, and how much of the water between it and the surface it
+// shines through
+fn refraction(world_position: vec3<f32>, normal: vec3<f32>) -> OceanRefraction {
+    let near = view_bindings::view.projection[3][2];
+    let surface_clip = view_bindings::view.view_proj * vec4(world_position, 1.0);
+
+    let distorted = world_position + vec3(normal.x, 0.0, normal.z) * settings.refraction_distortion;
+    var uv = clip_to_uv(view_bindings::view.view_proj * vec4(distorted, 1.0));
+    var scene_depth = scene_depth(uv);
+    // Anything above the water at the bent lookup would bleed into it, those pixels look straight through instead
+    if (outside_screen(uv) || near / max(scene_depth, 0.0000001) < surface_clip.w) {
+        uv = clip_to_uv(surface_clip);
+        scene_depth = scene_depth(uv);
+    }
+
+    var out: OceanRefraction;
+    out.color = textureSampleLevel(opaque_color_texture, opaque_color_sampler, uv, 0.0).rgb;
+
+    // Nothing was drawn behind the surface, the water goes on forever
+    if (scene_depth < 0.0001) {
+        out.transmittance = vec3(0.0);
+        return out;
+    }
+
+    // Depth difference along the view axis, stretched to the length of the view ray through the water
+    let scene_z = near / scene_depth;
+    let ray_scale = distance(view_bindings::view.world_position.xyz, world_position) / surface_clip.w;
+    let thickness = max(0.0, scene_z - surface_clip.w) * ray_scale;
+    out.transmittance = exp(-settings.absorption * thickness);
+    return out;
+}
+#endif
+
+#ifdef OCEAN_CONTACT_FOAM
+fn hash_2d(p: vec2<f32>) -> f32 {
+    let q = fract(p * vec2(123.34, 456.21));
+    let r = q + dot(q, q + 45.32);
+    return fract(r.x * r.y);
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let cell = floor(p);
+    let t = fract(p);
+    let s = t * t * (3.0 - 2.0 * t);
+    let bottom = mix(hash_2d(cell), hash_2d(cell + vec2(1.0, 0.0)), s.x);
+    let top = mix(hash_2d(cell + vec2(0.0, 1.0)), hash_2d(cell + vec2(1.0, 1.0)), s.x);
+    return mix(bottom, top, s.y);
+}
+
+// Foam where the water meets geometry, fading out over `contact_foam_width` meters of water below the surface.
+// Bands wash out from the contact line and noise tears them up, both drifting with time
+fn contact_foam(world_position: vec3<f32>, frag_coord: vec2<f32>) -> f32 {
+    let depth = prepass_utils::prepass_depth(vec4(frag_coord, 0.0, 0.0), 0u);
+    if (depth < 0.0001 || settings.contact_foam_width <= 0.0) {
+        return 0.0;
+    }
+
+    // Along the view ray to whatever is behind the surface, then how far below the surface that is
+    let camera = view_bindings::view.world_position.xyz;
+    let scene_position = camera + (world_position - camera) * (view_bindings::view.projection[3][2] / depth) / -view_space_z(world_position);
+    let water_depth = max(0.0, world_position.y - scene_position.y);
+
+    let edge = saturate(water_depth / settings.contact_foam_width);
+    if (edge >= 1.0) {
+        return 0.0;
+    }
+
+    let time = view_bindings::globals.time;
+    let noise_uv = world_position.xz / settings.contact_foam_noise_scale;
+    let noise = value_noise(noise_uv + vec2(time * 0.1, 0.0)) * 0.6 + value_noise(noise_uv * 2.3 - vec2(0.0, time * 0.13)) * 0.4;
+
+    let bands = 0.5 + 0.5 * cos((edge * 2.0 - time * settings.contact_foam_speed) * 2.0 * PI);
+    let pattern = mix(bands, bands * noise * 2.0, settings.contact_foam_noise);
+    // Solid right at the contact line
+    return saturate(max(pattern, 1.0 - edge * 4.0) * (1.0 - edge));
+}
+#endif
+
+struct OceanFoam {
+    coverage: f32,
+    albedo: vec3<f32>,
+    // Added to the surface slope where the foam covers it
+    gradient: vec2<f32>,
+}
+
+// Plain foam_color wherever there is foam
+fn flat_foam(foam: f32) -> OceanFoam {
+    var out: OceanFoam;
+    out.coverage = foam;
+    out.albedo = settings.foam_color;
+    out.gradient = vec2(0.0);
+    return out;
+}
+
+#ifdef OCEAN_FOAM_TEXTURES
+// Lacework the foam textures average out to, what's left of them once their texels are smaller than a pixel
+const FOAM_LACE_MEAN: f32 = 0.35;
+// How much of the lace's range the edge of aged foam blurs over
+const FOAM_LACE_SOFTNESS: f32 = 0.2;
+// Brightness of foam that has fully thinned out, against fresh foam
+const FOAM_AGED_BRIGHTNESS: f32 = 0.75;
+
+// Foam textures tiled over each cascade and weighted by that cascade's share of the foam, so the age and pattern
+// follow whichever cascade made the foam. The lookup is shifted by the cascade's horizontal displacement, which keeps
+// the tiling from lining up with the grid the waves are simulated on.
+//
+// Fresh foam is a bright, bubbly sheet. As it ages it drains out of the bubbles, leaving only the lace between them
+fn textured_foam(cascade_uv_12: vec4<f32>, cascade_uv_34: vec4<f32>, foam: f32) -> OceanFoam {
+    var uvs = array<vec2<f32>, 4>(cascade_uv_12.xy, cascade_uv_12.zw, cascade_uv_34.xy, cascade_uv_34.zw);
+    let texture_size = vec2<f32>(textureDimensions(foam_albedo_texture));
+
+    var weight = 0.0;
+    var age = 0.0;
+    var lace = 0.0;
+    var albedo = vec3(0.0);
+    var gradient = vec2(0.0);
+    for (var layer = 0; layer < 4; layer++) {
+        let uv = fract(uvs[layer]);
+        let displacement = sample_displacement(uv, layer);
+        let share = saturate(displacement.a) * f32(u32(layer) < settings.active_layers);
+
+        let foam_uv = uvs[layer] * settings.foam_tiling[layer] + displacement.xz * settings.foam_distortion;
+        // No mipmaps, so the detail fades out where it would alias
+        let footprint = max(length(dpdx(foam_uv) * texture_size), length(dpdy(foam_uv) * texture_size));
+        let detail = 1.0 - smoothstep(1.0, 4.0, footprint);
+
+        let foam_albedo = textureSample(foam_albedo_texture, foam_albedo_sampler, foam_uv);
+        let foam_slope = textureSample(foam_normal_texture, foam_normal_sampler, foam_uv).xy * 2.0 - 1.0;
+
+        weight += share;
+        age += textureSampleLevel(foam_age_textures, foam_age_sampler, uv, layer, 0.0).r * share;
+        lace += mix(FOAM_LACE_MEAN, foam_albedo.a, detail) * share;
+        albedo += mix(vec3(1.0), foam_albedo.rgb, detail) * share;
+        gradient += foam_slope * detail * share;
+    }
+
+    // Foam from the local waves alone has no cascade to take an age from, and counts as fresh
+    var out = flat_foam(foam);
+    if (weight <= 0.0001) {
+        return out;
+    }
+    let freshness = 1.0 - saturate(age / weight / max(settings.foam_lace_age, 0.0001));
+    lace /= weight;
+
+    // Thinner foam only keeps the thickest lace
+    let threshold = 1.0 - foam;
+    let aged_coverage = smoothstep(threshold, threshold + FOAM_LACE_SOFTNESS, lace);
+
+    out.coverage = mix(aged_coverage, foam, freshness);
+    out.albedo = settings.foam_color * albedo / weight * mix(FOAM_AGED_BRIGHTNESS, 1.0, freshness);
+    out.gradient = gradient / weight * settings.foam_normal_strength * mix(0.3, 1.0, freshness);
+    return out;
+}
+#endif
+
+// Looking up at the surface from below: the sky shows through Snell's window, outside of it the surface mirrors the
+// water underneath. The normal faces down, towards the eye
+fn ocean_underside(world_position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>, sun_irradiance: vec3<f32>) -> vec3<f32> {
+    let eta = 1.33;
+    let water_color = underwater_inscatter(sky_settings, settings.absorption, sun_irradiance, water_level(surface_settings) - world_position.y);
+
+    let transmitted = refract(-view_dir, normal, eta);
+    // Total internal reflection
+    if (all(transmitted == vec3(0.0))) {
+        return water_color;
+    }
+
+    // Fresnel on the air side, so reflection takes over towards the edge of the window
+    let r = ((eta - 1.0) * (eta - 1.0)) / ((eta + 1.0) * (eta + 1.0));
+    let f = r + (1.0 - r) * pow(1.0 - saturate(dot(-normal, transmitted)), 5.0);
+    return mix(get_sky_color(transmitted, light_dir, sun_irradiance), water_color, f);
+}
+
+// Everything about the shaded point that doesn't depend on the light
+struct OceanSurface {
+    world_position: vec4<f32>,
+    // Geometric normal, for shadow lookups
+    world_normal: vec3<f32>,
+    normal: vec3<f32>,
+    specular_normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    roughness: f32,
+    fresnel: f32,
+    height: f32,
+}
+
+// Light reflected off the surface, and scattered back out of the water column
+struct OceanLight {
+    specular: vec3<f32>,
+    scatter: vec3<f32>,
+}
+
+fn add_ocean_light(a: OceanLight, b: OceanLight) -> OceanLight {
+    return OceanLight(a.specular + b.specular, a.scatter + b.scatter);
+}
+
+// Beckmann specular and subsurface scatter from a single light
+fn ocean_light(surface: OceanSurface, light_dir: vec3<f32>, irradiance: vec3<f32>, shadow: f32) -> OceanLight {
+    let macro_normal = vec3(0.0, 1.0, 0.0);
+    let normal = surface.normal;
+    let view_dir = surface.view_dir;
+    let half_dir = normalize(light_dir + view_dir);
+    let a = surface.roughness;
+
+    let n_dot_l = saturate(dot(normal, light_dir));
+
+    let view_mask = smith_masking_beckmann(half_dir, view_dir, a);
+    let light_mask = smith_masking_beckmann(half_dir, light_dir, a);
+
+    let g = 1.0 / (1.0 + view_mask + light_mask);
+
+    var specular = irradiance * surface.fresnel * g * beckmann(max(0.0001, dot(surface.specular_normal, half_dir)), a);
+    specular /= 4.0 * max(0.001, saturate(dot(macro_normal, light_dir)));
+    specular *= n_dot_l * shadow;
+
+#ifdef OCEAN_PEAK_SCATTER
+    let k1 = settings.wave_peak_scatter_strength * surface.height * pow(saturate(dot(light_dir, -view_dir)), 4.0) * pow(0.5 - 0.5 * dot(light_dir, normal), 3.0);
+#else
+    let k1 = 0.0;
+#endif
+    let k2 = settings.scatter_strength * pow(saturate(dot(view_dir, normal)), 2.0);
+    let k3 = settings.scatter_shadow_strength * n_dot_l * shadow;
+    let k4 = settings.bubble_density;
+
+    var scatter = (k1 + k2) * settings.scatter_color * irradiance / (1.0 + light_mask);
+    scatter += k3 * settings.scatter_color * irradiance + k4 * settings.bubble_color * irradiance;
+
+    return OceanLight(specular, scatter);
+}
+
+// Same range falloff as Bevy's point lights
+fn point_light_attenuation(distance_squared: f32, inverse_range_squared: f32) -> f32 {
+    let factor = distance_squared * inverse_range_squared;
+    let smooth_factor = saturate(1.0 - factor * factor);
+    return smooth_factor * smooth_factor / max(distance_squared, 0.0001);
+}
+
+fn point_light_irradiance(light_id: u32, world_position: vec3<f32>) -> vec3<f32> {
+    let light = &view_bindings::point_lights.data[light_id];
+    let light_to_frag = (*light).position_radius.xyz - world_position;
+    let distance_squared = dot(light_to_frag, light_to_frag);
+    let attenuation = point_light_attenuation(distance_squared, (*light).color_inverse_square_range.w);
+    return (*light).color_inverse_square_range.rgb * attenuation;
+}
+
+fn spot_light_attenuation(light_id: u32, world_position: vec3<f32>) -> f32 {
+    let light = &view_bindings::point_lights.data[light_id];
+
+    var spot_dir = vec3((*light).light_custom_data.x, 0.0, (*light).light_custom_data.y);
+    spot_dir.y = sqrt(max(0.0, 1.0 - spot_dir.x * spot_dir.x - spot_dir.z * spot_dir.z));
+    if (((*light).flags & bevy_pbr::mesh_view_types::POINT_LIGHT_FLAGS_SPOT_LIGHT_Y_NEGATIVE) != 0u) {
+        spot_dir.y = -spot_dir.y;
+    }
+
+    let light_to_frag = (*light).position_radius.xyz - world_position;
+    let cd = dot(-spot_dir, normalize(light_to_frag));
+    let attenuation = saturate(cd * (*light).light_custom_data.z + (*light).light_custom_data.w);
+    return attenuation * attenuation;
+}
+
+fn point_light_shadows_enabled(light_id: u32) -> bool {
+    return receives_shadows()
+        && (view_bindings::point_lights.data[light_id].flags & bevy_pbr::mesh_view_types::POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u;
+}
+
+// Point and spot lights from the cluster the fragment falls in
+fn clustered_lights(surface: OceanSurface, frag_coord: vec2<f32>) -> OceanLight {
+    let world_position = surface.world_position.xyz;
+    let is_orthographic = view_bindings::view.projection[3].w == 1.0;
+    let cluster_index = clustering::fragment_cluster_index(frag_coord, view_space_z(world_position), is_orthographic);
+    let offset_and_counts = clustering::unpack_offset_and_counts(cluster_index);
+
+    var lighting = OceanLight(vec3(0.0), vec3(0.0));
+
+    let point_end = offset_and_counts[0] + offset_and_counts[1];
+    for (var i = offset_and_counts[0]; i < point_end; i++) {
+        let light_id = clustering::get_light_id(i);
+        let light_dir = normalize(view_bindings::point_lights.data[light_id].position_radius.xyz - world_position);
+
+        var shadow = 1.0;
+        if (point_light_shadows_enabled(light_id)) {
+            shadow = shadows::fetch_point_shadow(light_id, surface.world_position, surface.world_normal);
+        }
+        lighting = add_ocean_light(lighting, ocean_light(surface, light_dir, point_light_irradiance(light_id, world_position), shadow));
+    }
+
+    let spot_end = point_end + offset_and_counts[2];
+    for (var i = point_end; i < spot_end; i++) {
+        let light_id = clustering::get_light_id(i);
+        let light_dir = normalize(view_bindings::point_lights.data[light_id].position_radius.xyz - world_position);
+
+        var shadow = 1.0;
+        if (point_light_shadows_enabled(light_id)) {
+            shadow = shadows::fetch_spot_shadow(light_id, surface.world_position, surface.world_normal);
+        }
+        let irradiance = point_light_irradiance(light_id, world_position) * spot_light_attenuation(light_id, world_position);
+        lighting = add_ocean_light(lighting, ocean_light(surface, light_dir, irradiance, shadow));
+    }
+
+    return lighting;
+}
+
 @fragment
-fn fragment(in: MeshVertexOutput) -> @location(0) vec4<f32> {
-    let dimensions = vec2<f32>(textureDimensions(gradient_textures)) - 0.5;
-
-    let uv1 = fract(in.uv * settings.tile_layers.x);
-    let uv2 = fract((in.uv - 0.5) * settings.tile_layers.y);
-    let uv3 = fract((in.uv - 1.125) * settings.tile_layers.z);
-    let uv4 = fract((in.uv - 1.25) * settings.tile_layers.w);
-    
-    let gradient_1 = textureSampleLevel(gradient_textures, gradient_sampler, uv1, 0, 0.0) * settings.contribute_layers.x; 
-    let gradient_2 = textureSampleLevel(gradient_textures, gradient_sampler, uv2, 1, 0.0) * settings.contribute_layers.y; 
-    let gradient_3 = textureSampleLevel(gradient_textures, gradient_sampler, uv3, 2, 0.0) * settings.contribute_layers.z; 
-    let gradient_4 = textureSampleLevel(gradient_textures, gradient_sampler, uv4, 3, 0.0) * settings.contribute_layers.w; 
-
-    var gradient = gradient_1.xyz + gradient_2.xyz + gradient_3.xyz + gradient_4.xyz;
-    let specular_gradient = gradient * settings.specular_normal_strength;
+fn fragment(in: OceanVertexOutput, @builtin(front_facing) is_front: bool) -> @location(0) vec4<f32> {
+    let depth = linearize_depth(in.position.z);
+
+    var foam = in.foam;
+    foam = mix(0.0, saturate(foam), pow(depth, settings.foam_depth_attenuation));
+#ifdef OCEAN_CONTACT_FOAM
+    foam = max(foam, contact_foam(in.world_position.xyz, in.position.xy));
+#endif
+#ifdef OCEAN_FOAM_TEXTURES
+    let surface_foam = textured_foam(in.cascade_uv_12, in.cascade_uv_34, saturate(foam));
+#else
+    let surface_foam = flat_foam(saturate(foam));
+#endif
+
+    var gradient = world_gradient(sample_cascade_gradient(in.cascade_uv_12, in.cascade_uv_34), sample_local_waves(in.world_position.xz).yz);
+#ifdef OCEAN_SPECULAR_NORMAL
+    var specular_gradient = gradient * settings.specular_normal_strength;
+#else
+    var specular_gradient = gradient * settings.normal_strength;
+#endif
     gradient *= settings.normal_strength;
 
-    let sun_irradiance = sky_settings.sun_color * settings.sun_power;
+    // Bubbles on top of the water, the normal strengths are only meant for the waves
+    gradient += surface_foam.gradient * surface_foam.coverage;
+    specular_gradient += surface_foam.gradient * surface_foam.coverage;
+
+    // Bevy has already turned the light's illuminance into exposed radiance
     let directional_light = view_bindings::lights.directional_lights[0u];
+    let sun_irradiance = directional_light.color.rgb * f32(view_bindings::lights.n_directional_lights > 0u);
 
     let light_dir = normalize(directional_light.direction_to_light);
     let view_dir = normalize(view_bindings::view.world_position.xyz - in.world_position.xyz);
-    let half_dir = normalize(light_dir + view_dir);
-
-    let depth = linearize_depth(in.position.z);
-    let l_dot_h = saturate(dot(light_dir, half_dir));
-    let v_dot_h = saturate(dot(view_dir, half_dir));
 
     let macro_normal = vec3(0.0, 1.0, 0.0);
-    var normal = normalize(vec3(-gradient.x, 1.0, -gradient.y));
-    var specular_normal = normalize(vec3(-specular_gradient.x, 1.0, -specular_gradient.y));
+    var normal = gradient_to_normal(gradient.xy);
+    var specular_normal = gradient_to_normal(specular_gradient.xy);
     normal = normalize(mix(macro_normal, normal, pow(saturate(depth), settings.normal_depth_attenuation)));
     specular_normal = normalize(mix(macro_normal, specular_normal, pow(saturate(depth), settings.normal_depth_attenuation)));
 
-    var foam = in.world_normal.x;
-    foam = mix(0.0, saturate(foam), pow(depth, settings.foam_depth_attenuation));
-
-    let n_dot_l = saturate(dot(normal, light_dir));
-
-    let a = settings.roughness + saturate(foam) * settings.foam_roughness;
-    let n_dot_h = max(0.0001, dot(normal, half_dir));
-    
-    let view_mask = smith_masking_beckmann(half_dir, view_dir, a);
-    let light_mask = smith_masking_beckmann(half_dir, light_dir, a);
+    if (!is_front) {
+        // Foam floating on top hides the way out
+        var output = ocean_underside(in.world_position.xyz, -normal, view_dir, light_dir, sun_irradiance);
+        output = mix(output, underwater_inscatter(sky_settings, settings.absorption, sun_irradiance, 0.0), surface_foam.coverage);
+#ifdef OCEAN_TRANSPARENT
+        let view_dist = distance(view_bindings::view.world_position.xyz, in.world_position.xyz);
+        output = underwater_fog(sky_settings, surface_settings, gradient_textures, prev_gradient_textures, output, view_bindings::view.world_position.xyz, -view_dir, view_dist, light_dir, sun_irradiance);
+#endif
+        return vec4(output, 1.0);
+    }
 
-    let g = 1.0 / (1.0 + view_mask + light_mask);
+    let a = settings.roughness + surface_foam.coverage * settings.foam_roughness;
 
     let eta = 1.33;
     let r = ((eta - 1.0) * (eta - 1.0)) / ((eta + 1.0) * (eta + 1.0));
-    let theta_v = acos(view_dir.y);
 
     let numerator = pow(1.0 - dot(normal, view_dir), 5.0 * exp(-2.69 * a));
     var f = r + (1.0 - r) * numerator / (1.0 + 22.7 * pow(a, 1.5));
     f = saturate(f);
 
-    var specular = sun_irradiance * f * g * beckmann(max(0.0001, dot(specular_normal, half_dir)), a);
-    specular /= 4.0 * max(0.001, saturate(dot(macro_normal, light_dir)));
-    specular *= saturate(dot(normal, light_dir));
-
-    var env_reflection = get_sky_color(reflect(-view_dir, normal), light_dir);
+    var surface: OceanSurface;
+    surface.world_position = in.world_position;
+    surface.world_normal = normalize(in.world_normal);
+    surface.normal = normal;
+    surface.specular_normal = specular_normal;
+    surface.view_dir = view_dir;
+    surface.roughness = a;
+    surface.fresnel = f;
+    surface.height = max(0.0, in.height) * settings.height_modifier;
+
+    // Looked up at the displaced surface, offset along the geometric wave normal
+    let shadow = directional_shadow(in.world_position.xyz, surface.world_normal, in.position.xy);
+
+    let reflect_dir = reflect(-view_dir, normal);
+#ifdef OCEAN_ENV_REFLECTION
+    var env_reflection = get_sky_color(reflect_dir, light_dir, sun_irradiance);
+#else
+    var env_reflection = sky_settings.fog_color;
+#endif
     env_reflection *= settings.environment_light_strength;
 
-    let h = max(0.0, in.world_normal.y) * settings.height_modifier;
-
-    let k1 = settings.wave_peak_scatter_strength * h * pow(saturate(dot(light_dir, -view_dir)), 4.0) * pow(0.5 - 0.5 * dot(light_dir, normal), 3.0);
-    let k2 = settings.scatter_strength * pow(saturate(dot(view_dir, normal)), 2.0);
-    let k3 = settings.scatter_shadow_strength * n_dot_l;
-    let k4 = settings.bubble_density;
+    // Scene reflections over the sky, screen space first since it's exact where it hits
+    var scene_reflection = vec4(0.0);
+#ifdef OCEAN_SSR
+    scene_reflection = screen_space_reflection(in.world_position.xyz, reflect_dir);
+#endif
+#ifdef OCEAN_PLANAR_REFLECTION
+    scene_reflection += planar_reflection(in.world_position.xyz, normal) * (1.0 - scene_reflection.a);
+#endif
+    env_reflection = env_reflection * (1.0 - scene_reflection.a) + scene_reflection.rgb;
 
-    var scatter = (k1 + k2) * settings.scatter_color * sun_irradiance / (1.0 + light_mask);
-    scatter += k3 * settings.scatter_color * sun_irradiance + k4 * settings.bubble_color * sun_irradiance;
+    let lighting = add_ocean_light(ocean_light(surface, light_dir, sun_irradiance, shadow), clustered_lights(surface, in.position.xy));
 
-    var output = /* (1.0 - f) * */scatter + specular + f * env_reflection;
+#ifdef OCEAN_REFRACTION
+    // What's under the surface shows through as far as the water column lets it, the column's own scatter makes up
+    // the rest
+    let refracted = refraction(in.world_position.xyz, normal);
+    var output = (1.0 - f) * (refracted.color * refracted.transmittance + lighting.scatter * (1.0 - refracted.transmittance));
+#else
+    var output = /* (1.0 - f) * */lighting.scatter;
+#endif
+    output += lighting.specular + f * env_reflection;
     output = max(vec3(0.0), output);
-    output = mix(output, settings.foam_color, saturate(foam));
+    output = mix(output, surface_foam.albedo, surface_foam.coverage);
+
+#ifdef OCEAN_TRANSPARENT
+    output = sky_pass_composite(output, in.world_position.xyz, view_dir, light_dir, sun_irradiance);
+#endif
 
     return vec4(output, 1.0);
-}
\ No newline at end of file
+}
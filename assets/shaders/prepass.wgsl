@@ -1,6 +1,11 @@
-#import ocean::main displacement_textures
-#import ocean::main displacement_sampler
 #import ocean::main settings
+#import ocean::main sample_displacement
+#import ocean::main layer_contribution
+#import ocean::main sample_local_waves
+#import ocean::main cascade_uv
+#import ocean::main sample_cascade_gradient
+#import ocean::main gradient_to_normal
+#import ocean::main world_gradient
 #import bevy_pbr::prepass_bindings
 #import bevy_pbr::mesh_functions
 #import bevy_pbr::skinning
@@ -68,23 +73,30 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     let uv = vertex.uv;
 
-    let uv1 = fract(uv * settings.tile_layers.x);
-    let uv2 = fract((uv - 0.5) * settings.tile_layers.y);
-    let uv3 = fract((uv - 1.125) * settings.tile_layers.z);
-    let uv4 = fract((uv - 1.25) * settings.tile_layers.w);
+    let cascade_uv_12 = vec4(cascade_uv(uv, 0u), cascade_uv(uv, 1u));
+    let cascade_uv_34 = vec4(cascade_uv(uv, 2u), cascade_uv(uv, 3u));
 
-    let displacement_1 = textureSampleLevel(displacement_textures, displacement_sampler, uv1, 0, 0.0) * settings.contribute_layers.x; 
-    let displacement_2 = textureSampleLevel(displacement_textures, displacement_sampler, uv2, 1, 0.0) * settings.contribute_layers.y; 
-    let displacement_3 = textureSampleLevel(displacement_textures, displacement_sampler, uv3, 2, 0.0) * settings.contribute_layers.z; 
-    let displacement_4 = textureSampleLevel(displacement_textures, displacement_sampler, uv4, 3, 0.0) * settings.contribute_layers.w; 
+    let displacement_1 = sample_displacement(fract(cascade_uv_12.xy), 0) * layer_contribution(0u); 
+    let displacement_2 = sample_displacement(fract(cascade_uv_12.zw), 1) * layer_contribution(1u); 
+    let displacement_3 = sample_displacement(fract(cascade_uv_34.xy), 2) * layer_contribution(2u); 
+    let displacement_4 = sample_displacement(fract(cascade_uv_34.zw), 3) * layer_contribution(3u); 
     let displacement = displacement_1.xyz + displacement_2.xyz + displacement_3.xyz + displacement_4.xyz;
 
     let position = vertex.position + displacement;
 
-    out.clip_position = bevy_pbr::mesh_functions::mesh_position_local_to_clip(model, vec4(position, 1.0));
+    var world_position = bevy_pbr::mesh_functions::mesh_position_local_to_world(model, vec4(position, 1.0));
+    let local_waves = sample_local_waves(world_position.xz);
+    world_position.y += local_waves.x;
+
+    out.clip_position = bevy_pbr::mesh_functions::mesh_position_world_to_clip(world_position);
     out.clip_position_unclamped = out.clip_position;
     out.clip_position.z = min(out.clip_position.z, 1.0);
 
+#ifdef NORMAL_PREPASS
+    let gradient = world_gradient(sample_cascade_gradient(cascade_uv_12, cascade_uv_34), local_waves.yz);
+    out.world_normal = gradient_to_normal(gradient);
+#endif // NORMAL_PREPASS
+
     return out;
 }
 
@@ -126,6 +138,10 @@ fn fragment(in: FragmentInput) -> FragmentOutput {
     var out: FragmentOutput;
 
     out.frag_depth = in.clip_position_unclamped.z;
+
+#ifdef NORMAL_PREPASS
+    out.normal = vec4(normalize(in.world_normal) * 0.5 + vec3(0.5), 1.0);
+#endif // NORMAL_PREPASS
     
     return out;
 }
\ No newline at end of file
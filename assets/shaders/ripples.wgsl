@@ -0,0 +1,66 @@
+struct RippleDisturbance {
+    position: vec2<f32>,
+    radius: f32,
+    strength: f32,
+}
+
+struct RippleSettings {
+    disturbances: array<RippleDisturbance, 32>,
+    shift: vec2<i32>,
+    texel_size: f32,
+    delta_time: f32,
+    wave_speed: f32,
+    damping: f32,
+    foam_add: f32,
+    foam_decay_rate: f32,
+    disturbance_count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: RippleSettings;
+@group(0) @binding(1)
+var input_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var output_texture: texture_storage_2d<rgba32float, write>;
+
+
+// Height, vertical velocity and foam. Water that scrolled in from outside the simulated area is at rest
+fn load_state(location: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(input_texture));
+    let source = location + settings.shift;
+    if (any(source < vec2(0)) || any(source >= size)) {
+        return vec4(0.0);
+    }
+    return textureLoad(input_texture, source, 0);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn simulate_ripples(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(id.xy);
+    let state = load_state(location);
+
+    var height = state.x;
+    var velocity = state.y;
+    var foam = state.z;
+
+    let neighbours = load_state(location + vec2(1, 0)).x + load_state(location - vec2(1, 0)).x
+        + load_state(location + vec2(0, 1)).x + load_state(location - vec2(0, 1)).x;
+    let laplacian = (neighbours - 4.0 * height) / (settings.texel_size * settings.texel_size);
+
+    velocity += settings.wave_speed * settings.wave_speed * laplacian * settings.delta_time;
+    velocity *= exp(-settings.damping * settings.delta_time);
+
+    let position = (vec2<f32>(location) + 0.5) * settings.texel_size;
+    for (var i = 0u; i < settings.disturbance_count; i++) {
+        let disturbance = settings.disturbances[i];
+        let falloff = saturate(1.0 - distance(position, disturbance.position) / max(disturbance.radius, 0.001));
+        velocity -= disturbance.strength * falloff * falloff;
+    }
+
+    height += velocity * settings.delta_time;
+
+    foam *= exp(-settings.foam_decay_rate * settings.delta_time);
+    foam += settings.foam_add * abs(velocity) * settings.delta_time;
+
+    textureStore(output_texture, id.xy, vec4(height, velocity, saturate(foam), 0.0));
+}
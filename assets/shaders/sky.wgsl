@@ -3,6 +3,11 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader FullscreenVertexOutput
 #import bevy_pbr::mesh_view_types as pbr_types
 #import bevy_render::view View
+#import ocean::surface SurfaceSettings
+#import ocean::surface water_level
+#import ocean::surface surface_height
+#import ocean::surface refracted_sun_dir
+#import ocean::surface caustic_intensity
 
 @group(0) @binding(0)
 var screen_texture: texture_2d<f32>;
@@ -18,11 +23,28 @@ var<uniform> lights: pbr_types::Lights;
 var skybox_texture: texture_cube<f32>;
 @group(0) @binding(6)
 var depth_texture: texture_depth_2d;
+@group(0) @binding(7)
+var<uniform> surface: SurfaceSettings;
+@group(0) @binding(8)
+var displacement_textures: texture_2d_array<f32>;
+@group(0) @binding(9)
+var prev_displacement_textures: texture_2d_array<f32>;
+@group(0) @binding(10)
+var gradient_textures: texture_2d_array<f32>;
+@group(0) @binding(11)
+var prev_gradient_textures: texture_2d_array<f32>;
+@group(0) @binding(12)
+var ripple_texture: texture_2d<f32>;
+@group(0) @binding(13)
+var wake_texture: texture_2d<f32>;
 
 const PI: f32 = 3.1415927;
+const SHAFT_STEPS: u32 = 16u;
+// Shafts are only marched this far, the water has swallowed them by then
+const SHAFT_DISTANCE: f32 = 40.0;
 
 struct SkySettings {
-    sun_color: vec3<f32>,
+    sun_strength: f32,
     sun_falloff: f32,
 
     fog_color: vec3<f32>,
@@ -30,12 +52,77 @@ struct SkySettings {
     fog_offset: f32,
     fog_height: f32,
     fog_attenuation: f32,
+
+    underwater_color: vec3<f32>,
+    light_shaft_strength: f32,
 }
 
 fn linearize_depth(depth: f32) -> f32 {
     return view.projection[3][2] / depth;
 }
 
+// How much of what's at the given height and distance makes it through the fog, shared with the ocean so it can
+// fog itself when drawn after this pass
+fn fog_visibility(fog: SkySettings, world_height: f32, view_dist: f32) -> f32 {
+    var height = min(fog.fog_height, world_height) / fog.fog_height;
+    height = pow(saturate(height), 1.0 / fog.fog_attenuation);
+
+    var fog_factor = (fog.fog_density / sqrt(log(2.0))) * max(0.0, view_dist - fog.fog_offset);
+    fog_factor = exp2(-fog_factor * fog_factor);
+
+    return saturate(height + fog_factor);
+}
+
+// Light the water scatters towards the eye at some depth, lit by the sun from above
+fn underwater_inscatter(sky: SkySettings, absorption: vec3<f32>, sun_irradiance: vec3<f32>, depth: f32) -> vec3<f32> {
+    return sky.underwater_color * sun_irradiance * exp(-absorption * max(0.0, depth));
+}
+
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+// Absorption, in-scattering and light shafts along a view ray running `dist` meters under water from `origin`.
+// Shafts are the light the waves above focus, from the same cascades the ocean is drawn with. Shared with the ocean,
+// which has to do this itself for its underside when drawn after this pass
+fn underwater_fog(
+    sky: SkySettings,
+    surface: SurfaceSettings,
+    gradients: texture_2d_array<f32>,
+    prev_gradients: texture_2d_array<f32>,
+    color: vec3<f32>,
+    origin: vec3<f32>,
+    dir: vec3<f32>,
+    dist: f32,
+    to_light: vec3<f32>,
+    sun_irradiance: vec3<f32>
+) -> vec3<f32> {
+    let absorption = surface.absorption;
+    let level = water_level(surface);
+    let transmittance = exp(-absorption * dist);
+    let ambient = underwater_inscatter(sky, absorption, sun_irradiance, level - origin.y) * (1.0 - transmittance);
+
+    let local_sun_dir = refracted_sun_dir(surface, to_light);
+    let sun_dir = normalize((surface.transform * vec4(local_sun_dir, 0.0)).xyz);
+
+    let step = min(dist, SHAFT_DISTANCE) / f32(SHAFT_STEPS);
+    var shafts = vec3(0.0);
+    for (var i = 0u; i < SHAFT_STEPS; i++) {
+        let t = (f32(i) + 0.5) * step;
+        let p = origin + dir * t;
+        let depth = max(0.0, level - p.y);
+        // Only the light focused past what a flat surface lets through stands out as a shaft
+        let focused = caustic_intensity(surface, gradients, prev_gradients, p, depth, local_sun_dir, 1.0) - 1.0;
+        let path = t + depth / max(sun_dir.y, 0.1);
+        shafts += max(focused, 0.0) * exp(-absorption * path) * step;
+    }
+    let phase = henyey_greenstein(dot(dir, sun_dir), 0.6) * saturate(to_light.y);
+    shafts *= sky.underwater_color * sun_irradiance * phase * sky.light_shaft_strength;
+
+    return color * transmittance + ambient + shafts;
+}
+
 fn get_sky_color(dir: vec3<f32>, sun_dir: vec3<f32>) -> vec3<f32> {
     let sky = textureSample(skybox_texture, texture_sampler, dir).xyz;
     return sky;
@@ -63,15 +150,26 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     }
     
     let world_pos = view.world_position.xyz + view_dir * view_dist;
-    var height = min(settings.fog_height, world_pos.y) / settings.fog_height;
-    height = pow(saturate(height), 1.0 / settings.fog_attenuation);
 
-    var fog_factor = (settings.fog_density / sqrt(log(2.0))) * max(0.0, view_dist - settings.fog_offset);
-    fog_factor = exp2(-fog_factor * fog_factor);
+    // Where the view ray starts, on the near plane, and how far that is from the water surface above it
+    let near_clip = view.inverse_view_proj * vec4((in.uv * 2.0 - 1.0) * vec2(1.0, -1.0), 1.0, 1.0);
+    let near_point = near_clip.xyz / near_clip.w;
+    let waterline = surface_height(surface, displacement_textures, prev_displacement_textures, ripple_texture, wake_texture, near_point);
+    let waterline_offset = near_point.y - waterline;
+    // A few pixels wide dark band where the surface cuts the lens
+    let meniscus = 1.0 - 0.6 * (1.0 - smoothstep(0.0, fwidth(waterline_offset) * 3.0, abs(waterline_offset)));
+
+    if (waterline_offset < 0.0) {
+        // View depth is along the camera axis, the fog needs the distance along the ray
+        let forward = -view.view[2].xyz;
+        let ray_dist = view_dist / max(dot(view_dir, forward), 0.0001);
+        let output = underwater_fog(settings, surface, gradient_textures, prev_gradient_textures, color.rgb, near_point, view_dir, ray_dist, to_light, directional_light.color.rgb);
+        return vec4(output * meniscus, 1.0);
+    }
 
-    let sun = settings.sun_color * pow(saturate(dot(view_dir, to_light)), settings.sun_falloff);
+    let sun = directional_light.color.rgb * settings.sun_strength * pow(saturate(dot(view_dir, to_light)), settings.sun_falloff);
     
-    let output = mix(settings.fog_color, color.rgb, saturate(height + fog_factor));
+    let output = mix(settings.fog_color, color.rgb, fog_visibility(settings, world_pos.y, view_dist));
 
-    return vec4(output + sun, 1.0);
+    return vec4((output + sun) * meniscus, 1.0);
 }
\ No newline at end of file
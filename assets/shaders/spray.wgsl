@@ -0,0 +1,116 @@
+#import bevy_pbr::mesh_view_types as pbr_types
+#import bevy_render::view View
+#import ocean::sky SkySettings
+
+struct SpraySettings {
+    transform: mat4x4<f32>,
+    tile_layers: vec4<f32>,
+    contribute_layers: vec4<f32>,
+    wind: vec2<f32>,
+    plane_length: f32,
+    delta_time: f32,
+    gravity: f32,
+    seed: u32,
+    emitter_size: u32,
+    emission_rate: f32,
+    min_size: f32,
+    max_size: f32,
+    min_lifetime: f32,
+    max_lifetime: f32,
+    ejection_speed: f32,
+    drag: f32,
+    has_velocities: u32,
+    color: vec3<f32>,
+    opacity: f32,
+    environment_light_strength: f32,
+}
+
+struct SprayParticle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+    size: f32,
+    surface_height: f32,
+}
+
+@group(0) @binding(0)
+var<storage, read> particles: array<SprayParticle>;
+@group(0) @binding(1)
+var<uniform> settings: SpraySettings;
+@group(0) @binding(2)
+var<uniform> view: View;
+@group(0) @binding(3)
+var<uniform> lights: pbr_types::Lights;
+@group(0) @binding(4)
+var<uniform> sky_settings: SkySettings;
+@group(0) @binding(5)
+var skybox_texture: texture_cube<f32>;
+@group(0) @binding(6)
+var skybox_sampler: sampler;
+
+const PI: f32 = 3.1415927;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) opacity: f32,
+}
+
+@vertex
+fn vertex(@builtin(vertex_index) vertex_index: u32, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let particle = particles[instance_index];
+    if (particle.age >= particle.lifetime) {
+        // Outside the clip volume, so dead particles produce no fragments
+        out.position = vec4(0.0, 0.0, -1.0, 1.0);
+        return out;
+    }
+
+    var corners = array<vec2<f32>, 6>(
+        vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    // Droplets spread out into a mist as they age
+    let life = particle.age / particle.lifetime;
+    let size = particle.size * (1.0 + life);
+
+    let right = view.view[0].xyz;
+    let up = view.view[1].xyz;
+    out.world_position = particle.position + (right * corner.x + up * corner.y) * size;
+    out.position = view.view_proj * vec4(out.world_position, 1.0);
+    out.uv = corner;
+    out.opacity = smoothstep(0.0, 0.1, life) * (1.0 - smoothstep(0.5, 1.0, life));
+
+    return out;
+}
+
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let g2 = g * g;
+    return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let alpha = (1.0 - smoothstep(0.3, 1.0, length(in.uv))) * in.opacity * settings.opacity;
+    if (alpha <= 0.001) {
+        discard;
+    }
+
+    let directional_light = lights.directional_lights[0u];
+    let light_dir = normalize(directional_light.direction_to_light);
+    let view_dir = normalize(view.world_position.xyz - in.world_position);
+
+    // Water droplets scatter mostly forwards, spray lights up when looking towards the sun
+    let phase = mix(1.0 / (4.0 * PI), henyey_greenstein(dot(-light_dir, view_dir), 0.7), 0.7);
+    let sun_irradiance = directional_light.color.rgb * f32(lights.n_directional_lights > 0u);
+    let ambient = textureSample(skybox_texture, skybox_sampler, normalize(view_dir + vec3(0.0, 1.0, 0.0))).rgb;
+
+    let color = settings.color * (sun_irradiance * phase + ambient * settings.environment_light_strength);
+
+    return vec4(color * alpha, alpha);
+}
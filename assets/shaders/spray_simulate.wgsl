@@ -0,0 +1,156 @@
+struct SpraySettings {
+    transform: mat4x4<f32>,
+    tile_layers: vec4<f32>,
+    contribute_layers: vec4<f32>,
+    wind: vec2<f32>,
+    plane_length: f32,
+    delta_time: f32,
+    gravity: f32,
+    seed: u32,
+    emitter_size: u32,
+    emission_rate: f32,
+    min_size: f32,
+    max_size: f32,
+    min_lifetime: f32,
+    max_lifetime: f32,
+    ejection_speed: f32,
+    drag: f32,
+    has_velocities: u32,
+    color: vec3<f32>,
+    opacity: f32,
+    environment_light_strength: f32,
+}
+
+struct SprayParticle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+    size: f32,
+    surface_height: f32,
+}
+
+struct SprayCounter {
+    next_particle: atomic<u32>,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: SpraySettings;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<SprayParticle>;
+@group(0) @binding(2)
+var<storage, read_write> counter: SprayCounter;
+@group(0) @binding(3)
+var displacement_textures: texture_2d_array<f32>;
+@group(0) @binding(4)
+var velocity_textures: texture_2d_array<f32>;
+@group(0) @binding(5)
+var breaking_textures: texture_2d_array<f32>;
+
+// Most particles a single emitter cell spawns in one frame
+const MAX_EMITTED_PER_CELL: u32 = 4u;
+// Same offsets as the cascade uvs in ocean.wgsl
+const LAYER_OFFSETS: vec4<f32> = vec4(0.0, 0.5, 1.125, 1.25);
+
+
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = hash(*state);
+    return f32(*state) / 4294967295.0;
+}
+
+fn load_cascade(textures: texture_2d_array<f32>, uv: vec2<f32>, layer: u32) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(textures));
+    let location = clamp(vec2<i32>(uv * vec2<f32>(size)), vec2(0), size - 1);
+    return textureLoad(textures, location, layer, 0);
+}
+
+
+@compute @workgroup_size(64, 1, 1)
+fn update_spray(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= arrayLength(&particles)) {
+        return;
+    }
+
+    var particle = particles[id.x];
+    if (particle.age >= particle.lifetime) {
+        return;
+    }
+
+    let dt = settings.delta_time;
+    particle.age += dt;
+
+    // Drag pulls the droplets along with the wind, which is what turns spray into spindrift
+    let wind = vec3(settings.wind.x, 0.0, settings.wind.y);
+    particle.velocity -= (particle.velocity - wind) * (1.0 - exp(-settings.drag * dt));
+    particle.velocity.y -= settings.gravity * dt;
+    particle.position += particle.velocity * dt;
+
+    // Fell back into the sea
+    if (particle.velocity.y < 0.0 && particle.position.y < particle.surface_height - particle.size) {
+        particle.age = particle.lifetime;
+    }
+
+    particles[id.x] = particle;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn emit_spray(@builtin(global_invocation_id) id: vec3<u32>) {
+    var rng = hash(id.x + id.y * settings.emitter_size + hash(settings.seed));
+
+    let uv = (vec2<f32>(id.xy) + vec2(random(&rng), random(&rng))) / f32(settings.emitter_size);
+
+    // Breaking intensity, displacement and surface velocity summed over the cascades, as the ocean shader does
+    var intensity = 0.0;
+    var displacement = vec3(0.0);
+    var velocity = vec3(0.0);
+    for (var layer = 0u; layer < 4u; layer++) {
+        let contribution = settings.contribute_layers[layer];
+        if (contribution == 0.0) {
+            continue;
+        }
+
+        let layer_uv = fract((uv - LAYER_OFFSETS[layer]) * settings.tile_layers[layer]);
+        intensity += load_cascade(breaking_textures, layer_uv, layer).x * contribution;
+        displacement += load_cascade(displacement_textures, layer_uv, layer).xyz * contribution;
+        if (settings.has_velocities != 0u) {
+            velocity += load_cascade(velocity_textures, layer_uv, layer).xyz * contribution;
+        }
+    }
+
+    if (intensity <= 0.0) {
+        return;
+    }
+
+    let cell_size = settings.plane_length / f32(settings.emitter_size);
+    let expected = settings.emission_rate * intensity * cell_size * cell_size * settings.delta_time;
+    let count = min(u32(expected + random(&rng)), MAX_EMITTED_PER_CELL);
+    if (count == 0u) {
+        return;
+    }
+
+    let local_position = vec3(uv.x - 0.5, 0.0, uv.y - 0.5) * settings.plane_length + displacement;
+    let position = (settings.transform * vec4(local_position, 1.0)).xyz;
+    let surface_velocity = (settings.transform * vec4(velocity, 0.0)).xyz;
+
+    let strength = saturate(intensity);
+    let first = atomicAdd(&counter.next_particle, count);
+    for (var i = 0u; i < count; i++) {
+        let spread = vec3(random(&rng) - 0.5, random(&rng), random(&rng) - 0.5) * vec3(0.5, 1.0, 0.5);
+
+        var particle: SprayParticle;
+        particle.position = position + vec3(random(&rng) - 0.5, 0.0, random(&rng) - 0.5) * cell_size;
+        particle.age = 0.0;
+        particle.velocity = surface_velocity + spread * settings.ejection_speed * (0.5 + strength);
+        particle.lifetime = mix(settings.min_lifetime, settings.max_lifetime, strength) * (0.75 + 0.5 * random(&rng));
+        particle.size = mix(settings.min_size, settings.max_size, strength) * (0.5 + random(&rng));
+        particle.surface_height = position.y;
+
+        particles[(first + i) % arrayLength(&particles)] = particle;
+    }
+}
@@ -0,0 +1,214 @@
+#define_import_path ocean::surface
+
+// Where the ocean is and how its cascades are combined, for passes sampling its surface outside of its material.
+// Textures are passed in, so the material and the fullscreen passes can share these with their own bindings
+struct SurfaceSettings {
+    transform: mat4x4<f32>,
+    inverse_transform: mat4x4<f32>,
+    tile_layers: vec4<f32>,
+    // Zero for cascades that aren't simulated
+    contribute_layers: vec4<f32>,
+    simulation_blend: vec4<f32>,
+    absorption: vec3<f32>,
+    // Zero without an ocean to sample
+    plane_length: f32,
+    ripple_origin: vec2<f32>,
+    wake_origin: vec2<f32>,
+    ripple_size: f32,
+    wake_size: f32,
+}
+
+// Same offsets as the cascade uvs in ocean.wgsl
+const LAYER_OFFSETS: vec4<f32> = vec4(0.0, 0.5, 1.125, 1.25);
+// Fixed-point iterations finding the undisplaced point above a position, as the CPU queries do
+const INVERSION_ITERATIONS: u32 = 3u;
+const WATER_ETA: f32 = 1.33;
+// Brightest a point can get, where the focus is perfect the pattern would go to infinity
+const MAX_INTENSITY: f32 = 8.0;
+
+
+// The cascade textures are 32 bit float, which isn't filterable everywhere
+fn load_bilinear(textures: texture_2d_array<f32>, uv: vec2<f32>, layer: u32) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(textures));
+    let texel = fract(uv) * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(texel));
+    let t = fract(texel);
+
+    // Cascades tile, so neighbours wrap around
+    let x0 = (base.x % size.x + size.x) % size.x;
+    let y0 = (base.y % size.y + size.y) % size.y;
+    let x1 = (x0 + 1) % size.x;
+    let y1 = (y0 + 1) % size.y;
+
+    let top = mix(textureLoad(textures, vec2(x0, y0), layer, 0), textureLoad(textures, vec2(x1, y0), layer, 0), t.x);
+    let bottom = mix(textureLoad(textures, vec2(x0, y1), layer, 0), textureLoad(textures, vec2(x1, y1), layer, 0), t.x);
+    return mix(top, bottom, t.y);
+}
+
+// Same for the ripple and wake textures, which clamp to their edges instead
+fn load_bilinear_clamped(heightfield: texture_2d<f32>, uv: vec2<f32>) -> vec4<f32> {
+    let size = vec2<i32>(textureDimensions(heightfield));
+    let texel = uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(texel));
+    let t = fract(texel);
+
+    let p0 = clamp(base, vec2(0), size - 1);
+    let p1 = clamp(base + 1, vec2(0), size - 1);
+
+    let top = mix(textureLoad(heightfield, p0, 0), textureLoad(heightfield, vec2(p1.x, p0.y), 0), t.x);
+    let bottom = mix(textureLoad(heightfield, vec2(p0.x, p1.y), 0), textureLoad(heightfield, p1, 0), t.x);
+    return mix(top, bottom, t.y);
+}
+
+fn layer_uv(surface: SurfaceSettings, plane_uv: vec2<f32>, layer: u32) -> vec2<f32> {
+    return (plane_uv - LAYER_OFFSETS[layer]) * surface.tile_layers[layer];
+}
+
+fn local_to_plane_uv(surface: SurfaceSettings, local_xz: vec2<f32>) -> vec2<f32> {
+    return (local_xz + surface.plane_length * 0.5) / surface.plane_length;
+}
+
+fn water_level(surface: SurfaceSettings) -> f32 {
+    return surface.transform[3].y;
+}
+
+fn sample_displacement(
+    surface: SurfaceSettings,
+    displacements: texture_2d_array<f32>,
+    prev_displacements: texture_2d_array<f32>,
+    plane_uv: vec2<f32>,
+) -> vec3<f32> {
+    var displacement = vec3(0.0);
+    for (var layer = 0u; layer < 4u; layer++) {
+        let contribution = surface.contribute_layers[layer];
+        if (contribution == 0.0) {
+            continue;
+        }
+        let uv = layer_uv(surface, plane_uv, layer);
+        let prev = load_bilinear(prev_displacements, uv, layer).xyz;
+        let current = load_bilinear(displacements, uv, layer).xyz;
+        displacement += mix(prev, current, surface.simulation_blend[layer]) * contribution;
+    }
+    return displacement;
+}
+
+fn sample_gradient(
+    surface: SurfaceSettings,
+    gradients: texture_2d_array<f32>,
+    prev_gradients: texture_2d_array<f32>,
+    uv: vec2<f32>,
+    layer: u32,
+) -> vec2<f32> {
+    let prev = load_bilinear(prev_gradients, uv, layer).xy;
+    let current = load_bilinear(gradients, uv, layer).xy;
+    return mix(prev, current, surface.simulation_blend[layer]);
+}
+
+// Height of a ripple or wake texture covering a square at `origin`, faded out towards its edges like the material does
+fn local_wave_height(heightfield: texture_2d<f32>, origin: vec2<f32>, size: f32, world_xz: vec2<f32>) -> f32 {
+    if (size <= 0.0) {
+        return 0.0;
+    }
+
+    let uv = (world_xz - origin) / size;
+    let edge = min(min(uv.x, uv.y), min(1.0 - uv.x, 1.0 - uv.y));
+    let fade = smoothstep(0.0, 0.1, edge);
+    if (fade <= 0.0) {
+        return 0.0;
+    }
+    return load_bilinear_clamped(heightfield, uv).x * fade;
+}
+
+// World space height of the displaced surface straight above or below a point, ripples and wakes included. Far below
+// anything outside the ocean plane, or without an ocean
+fn surface_height(
+    surface: SurfaceSettings,
+    displacements: texture_2d_array<f32>,
+    prev_displacements: texture_2d_array<f32>,
+    ripples: texture_2d<f32>,
+    wakes: texture_2d<f32>,
+    world_position: vec3<f32>,
+) -> f32 {
+    if (surface.plane_length <= 0.0) {
+        return -1e30;
+    }
+
+    let local_position = (surface.inverse_transform * vec4(world_position, 1.0)).xyz;
+
+    // Search for the undisplaced point that lands here
+    var point = local_position.xz;
+    var displacement = vec3(0.0);
+    for (var i = 0u; i < INVERSION_ITERATIONS; i++) {
+        displacement = sample_displacement(surface, displacements, prev_displacements, local_to_plane_uv(surface, point));
+        point = local_position.xz - displacement.xz;
+    }
+    let plane_uv = local_to_plane_uv(surface, point);
+    if (any(plane_uv < vec2(0.0)) || any(plane_uv > vec2(1.0))) {
+        return -1e30;
+    }
+
+    let cascades = surface.transform * vec4(vec3(point.x, 0.0, point.y) + displacement, 1.0);
+    let local_waves = local_wave_height(ripples, surface.ripple_origin, surface.ripple_size, world_position.xz)
+        + local_wave_height(wakes, surface.wake_origin, surface.wake_size, world_position.xz);
+    return cascades.y + local_waves;
+}
+
+// Sum of the surface's second derivatives, measured across `step` meters. Cascades whose waves are much shorter than
+// that average out, the light they focus has spread out again by the depth the step is picked for
+fn surface_laplacian(
+    surface: SurfaceSettings,
+    gradients: texture_2d_array<f32>,
+    prev_gradients: texture_2d_array<f32>,
+    plane_uv: vec2<f32>,
+    step: f32,
+) -> f32 {
+    var laplacian = 0.0;
+    for (var layer = 0u; layer < 4u; layer++) {
+        let tile_size = surface.plane_length / surface.tile_layers[layer];
+        let contribution = surface.contribute_layers[layer] * smoothstep(2.0 * step, 8.0 * step, tile_size);
+        if (contribution == 0.0) {
+            continue;
+        }
+
+        let uv = layer_uv(surface, plane_uv, layer);
+        let offset = step / tile_size;
+        let dx = sample_gradient(surface, gradients, prev_gradients, uv + vec2(offset, 0.0), layer).x
+            - sample_gradient(surface, gradients, prev_gradients, uv - vec2(offset, 0.0), layer).x;
+        let dz = sample_gradient(surface, gradients, prev_gradients, uv + vec2(0.0, offset), layer).y
+            - sample_gradient(surface, gradients, prev_gradients, uv - vec2(0.0, offset), layer).y;
+        laplacian += (dx + dz) / (2.0 * step) * contribution;
+    }
+    return laplacian;
+}
+
+// Direction sunlight travels in under the surface, in the ocean's local space. It bends towards the vertical on its
+// way into the water
+fn refracted_sun_dir(surface: SurfaceSettings, to_light: vec3<f32>) -> vec3<f32> {
+    let local_to_light = normalize((surface.inverse_transform * vec4(to_light, 0.0)).xyz);
+    return -refract(-local_to_light, vec3(0.0, 1.0, 0.0), 1.0 / WATER_ETA);
+}
+
+// Brightness of the sunlight reaching a point under the waves, relative to a flat surface. Each slope bends light by
+// about (1 - 1 / eta) of itself, so a curved patch of surface squeezes or spreads what passes through it, more so the
+// further down it lands. `sun_dir` is from `refracted_sun_dir`
+fn caustic_intensity(
+    surface: SurfaceSettings,
+    gradients: texture_2d_array<f32>,
+    prev_gradients: texture_2d_array<f32>,
+    world_position: vec3<f32>,
+    depth: f32,
+    sun_dir: vec3<f32>,
+    focus: f32,
+) -> f32 {
+    let local_position = (surface.inverse_transform * vec4(world_position, 1.0)).xyz;
+    let sun_slope = sun_dir.xz / max(sun_dir.y, 0.1);
+
+    // Where the sunlight reaching this point came through the surface
+    let entry = local_position.xz + sun_slope * depth;
+    let step = clamp(depth * 0.05, 0.02, 0.5);
+    let laplacian = surface_laplacian(surface, gradients, prev_gradients, local_to_plane_uv(surface, entry), step);
+
+    let bend = (1.0 - 1.0 / WATER_ETA) * focus;
+    let area = 1.0 + depth * bend * laplacian;
+    return min(1.0 / max(abs(area), 1.0 / MAX_INTENSITY), MAX_INTENSITY);
+}
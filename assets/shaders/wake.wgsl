@@ -0,0 +1,138 @@
+struct ShipWake {
+    first_point: u32,
+    point_count: u32,
+    hull_length: f32,
+    beam: f32,
+    amplitude: f32,
+    decay_length: f32,
+    foam_lifetime: f32,
+    cusp_foam: f32,
+}
+
+struct WakeSettings {
+    ships: array<ShipWake, 32>,
+    // World xz, age and speed along each ship's path, newest first
+    points: array<vec4<f32>, 512>,
+    origin: vec2<f32>,
+    size: f32,
+    gravity: f32,
+    ship_count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: WakeSettings;
+@group(0) @binding(1)
+var wake_texture: texture_storage_2d<rgba32float, write>;
+
+
+// a = y / x at the edge of the wedge, tan(19.47°)
+const KELVIN_EDGE: f32 = 0.35355339;
+
+// Distance along the path behind the ship, distance from it, and the age and speed of the ship when it passed there.
+// x is negative in front of the ship or past the end of the path
+fn path_coordinates(ship: ShipWake, position: vec2<f32>) -> vec4<f32> {
+    var closest = vec4(-1.0, 1e9, 0.0, 0.0);
+    var arc_length = 0.0;
+
+    for (var i = 0u; i + 1u < ship.point_count; i++) {
+        let newer = settings.points[ship.first_point + i];
+        let older = settings.points[ship.first_point + i + 1u];
+
+        let segment = older.xy - newer.xy;
+        let segment_length = length(segment);
+        if (segment_length < 0.0001) {
+            continue;
+        }
+
+        let direction = segment / segment_length;
+        let along = dot(position - newer.xy, direction);
+        let t = clamp(along, 0.0, segment_length);
+        let lateral = distance(position, newer.xy + direction * t);
+
+        let ahead = i == 0u && along < 0.0;
+        let past_end = i + 2u == ship.point_count && along > segment_length;
+        if (lateral < closest.y) {
+            let f = t / segment_length;
+            let x = select(arc_length + t, -1.0, ahead || past_end);
+            closest = vec4(x, lateral, mix(newer.zw, older.zw, f));
+        }
+
+        arc_length += segment_length;
+    }
+
+    return closest;
+}
+
+// Stationary phase approximation of the Kelvin wave pattern behind a point source moving at `speed`.
+// Inside the wedge there are two waves through every point, the transverse and the divergent one, meeting at the cusps
+fn kelvin_height(ship: ShipWake, x: f32, y: f32, speed: f32) -> f32 {
+    if (x <= 0.0 || speed < 0.1) {
+        return 0.0;
+    }
+
+    let a = y / x;
+    let wedge = 1.0 - smoothstep(KELVIN_EDGE * 0.9, KELVIN_EDGE * 1.1, a);
+    if (wedge <= 0.0) {
+        return 0.0;
+    }
+
+    let k0 = settings.gravity / (speed * speed);
+    let root = sqrt(max(1.0 - 8.0 * a * a, 0.0));
+    let t_transverse = 2.0 * a / (1.0 + root);
+    let t_divergent = (1.0 + root) / max(4.0 * a, 0.0001);
+
+    let phase_transverse = k0 * (x - y * t_transverse) * sqrt(1.0 + t_transverse * t_transverse);
+    let phase_divergent = k0 * (x - y * t_divergent) * sqrt(1.0 + t_divergent * t_divergent);
+
+    // The hull can't make waves much shorter than itself
+    let k_transverse = k0 * (1.0 + t_transverse * t_transverse);
+    let k_divergent = k0 * (1.0 + t_divergent * t_divergent);
+    let hull_filter_transverse = exp(-pow(k_transverse * ship.hull_length * 0.05, 2.0));
+    let hull_filter_divergent = exp(-pow(k_divergent * ship.hull_length * 0.05, 2.0));
+
+    // Both waves pile up at the cusps
+    let cusp = min(pow(max(root, 0.001), -0.25), 3.0);
+
+    let froude = speed / sqrt(settings.gravity * max(ship.hull_length, 0.1));
+    let envelope = ship.amplitude * saturate(froude / 0.4) * cusp * wedge
+        * exp(-x / max(ship.decay_length, 0.1))
+        * inverseSqrt(1.0 + x / max(ship.hull_length, 0.1));
+
+    return envelope * (cos(phase_transverse) * hull_filter_transverse + cos(phase_divergent) * hull_filter_divergent);
+}
+
+fn wake_foam(ship: ShipWake, x: f32, y: f32, age: f32, speed: f32) -> f32 {
+    if (x < 0.0) {
+        return 0.0;
+    }
+
+    let fade = exp(-age / max(ship.foam_lifetime, 0.001));
+    let froude = saturate(speed / sqrt(settings.gravity * max(ship.hull_length, 0.1)));
+
+    // Churned water spreading out behind the hull
+    let half_width = ship.beam * 0.5 + x * 0.05;
+    let trail = 1.0 - smoothstep(half_width * 0.5, half_width + 0.5, y);
+
+    let a = y / max(x, 0.0001);
+    let edge = exp(-pow((a - KELVIN_EDGE) / 0.03, 2.0)) * ship.cusp_foam;
+
+    return fade * froude * max(trail, edge);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn draw_wakes(@builtin(global_invocation_id) id: vec3<u32>) {
+    let texel_size = settings.size / f32(textureDimensions(wake_texture).x);
+    let position = settings.origin + (vec2<f32>(id.xy) + 0.5) * texel_size;
+
+    var height = 0.0;
+    var foam = 0.0;
+    for (var i = 0u; i < settings.ship_count; i++) {
+        let ship = settings.ships[i];
+        let path = path_coordinates(ship, position);
+
+        height += kelvin_height(ship, path.x, path.y, path.w);
+        foam = max(foam, wake_foam(ship, path.x, path.y, path.z, path.w));
+    }
+
+    textureStore(wake_texture, id.xy, vec4(height, 0.0, saturate(foam), 0.0));
+}
@@ -0,0 +1,249 @@
+use bevy::{prelude::*, math::Vec3Swizzles};
+
+use crate::{compute::uniforms::OceanComputeSettings, query::OceanQuery};
+
+
+/// Sea water, in kg/m³
+pub const WATER_DENSITY: f32 = 1025.0;
+
+
+/// Forces the ocean applied to a [`Buoyancy`] body during the last fixed step, in world space.
+///
+/// Torques are taken around the entity's origin, which is treated as the center of mass.
+#[derive(Debug, Clone, Copy, Default, Reflect)]
+pub struct BuoyancyForces {
+    pub submerged_volume: f32,
+    pub buoyancy: Vec3,
+    pub drag: Vec3,
+    pub torque: Vec3,
+    /// Whether any point found the surface, false before the ocean has been read back or off the ocean plane. The
+    /// built-in integrator holds the body still until it does, rather than letting it fall through the missing water
+    pub sampled: bool,
+}
+
+impl BuoyancyForces {
+    pub fn force(&self) -> Vec3 {
+        self.buoyancy + self.drag
+    }
+}
+
+/// Makes an entity float on the ocean.
+///
+/// The hull is approximated by `points`, each standing for an equal share of `volume` spread over
+/// `point_height` vertically. Every fixed step the forces on each point are summed into `forces`, which
+/// external physics engines can apply themselves. Add a [`FloatingBody`] to have them integrated into the
+/// entity's `Transform` instead.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct Buoyancy {
+    /// Sample points in local space
+    pub points: Vec<Vec3>,
+    /// Displaced volume when fully submerged, in m³
+    pub volume: f32,
+    pub point_height: f32,
+
+    /// Quadratic drag coefficient against the water's motion
+    pub drag: f32,
+    /// Damping of the angular velocity, scaled by the submerged volume
+    pub angular_drag: f32,
+
+    pub forces: BuoyancyForces,
+
+    // World positions of the points last step, to estimate their velocity without a FloatingBody
+    prev_points: Vec<Vec3>,
+}
+
+impl Buoyancy {
+    /// Evenly fills a box with `subdivisions`³ sample points
+    pub fn cuboid(half_extents: Vec3, subdivisions: u32) -> Self {
+        let subdivisions = subdivisions.max(1);
+        let cell = half_extents * 2.0 / subdivisions as f32;
+
+        let mut points = Vec::with_capacity(subdivisions.pow(3) as usize);
+        for x in 0..subdivisions {
+            for y in 0..subdivisions {
+                for z in 0..subdivisions {
+                    points.push(-half_extents + (Vec3::new(x as f32, y as f32, z as f32) + 0.5) * cell);
+                }
+            }
+        }
+
+        Self {
+            points,
+            volume: half_extents.x * half_extents.y * half_extents.z * 8.0,
+            point_height: cell.y,
+            ..default()
+        }
+    }
+}
+
+impl Default for Buoyancy {
+    fn default() -> Self {
+        Self {
+            points: vec![Vec3::ZERO],
+            volume: 1.0,
+            point_height: 1.0,
+
+            drag: 1.0,
+            angular_drag: 0.5,
+
+            forces: BuoyancyForces::default(),
+            prev_points: Vec::new(),
+        }
+    }
+}
+
+/// Rigid body state for the built-in integrator
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct FloatingBody {
+    /// In kg
+    pub mass: f32,
+    /// Principal moments of inertia in local space, in kg·m²
+    pub inertia: Vec3,
+
+    pub velocity: Vec3,
+    pub angular_velocity: Vec3,
+}
+
+impl FloatingBody {
+    /// Body with the inertia of a solid box of uniform density
+    pub fn cuboid(mass: f32, half_extents: Vec3) -> Self {
+        let size = half_extents * 2.0;
+        let size2 = size * size;
+
+        Self {
+            mass,
+            inertia: Vec3::new(size2.y + size2.z, size2.x + size2.z, size2.x + size2.y) * mass / 12.0,
+            ..default()
+        }
+    }
+}
+
+impl Default for FloatingBody {
+    fn default() -> Self {
+        Self {
+            mass: 500.0,
+            inertia: Vec3::splat(100.0),
+
+            velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+        }
+    }
+}
+
+
+pub fn apply_buoyancy(
+    mut bodies: Query<(&mut Buoyancy, &Transform, Option<&FloatingBody>)>,
+    ocean: OceanQuery,
+    compute_settings: Res<OceanComputeSettings>,
+    fixed_time: Res<FixedTime>,
+) {
+    // Asking for a sampler keeps the surface being read back, which costs a copy every step
+    if bodies.is_empty() {
+        return;
+    }
+
+    let dt = fixed_time.period.as_secs_f32();
+    let sampler = ocean.sampler();
+
+    for (mut buoyancy, transform, body) in bodies.iter_mut() {
+        let buoyancy = buoyancy.as_mut();
+        let mut forces = BuoyancyForces::default();
+
+        let Some(sampler) = sampler.as_ref() else {
+            buoyancy.forces = forces;
+            continue;
+        };
+
+        let point_volume = buoyancy.volume / buoyancy.points.len().max(1) as f32;
+        let point_height = buoyancy.point_height.max(0.001);
+        let center = transform.translation;
+
+        // Last step's positions are overwritten as this step's are found, so the buffer is only allocated once
+        let estimate_velocity = body.is_none() && buoyancy.prev_points.len() == buoyancy.points.len();
+        buoyancy.prev_points.resize(buoyancy.points.len(), Vec3::ZERO);
+
+        for i in 0..buoyancy.points.len() {
+            let point = transform.transform_point(buoyancy.points[i]);
+            let prev_point = std::mem::replace(&mut buoyancy.prev_points[i], point);
+
+            let Some(surface) = sampler.sample(point.xz()) else { continue };
+            forces.sampled = true;
+
+            let submerged = ((surface.position.y - point.y) / point_height + 0.5).clamp(0.0, 1.0);
+            if submerged <= 0.0 {
+                continue;
+            }
+            let submerged_volume = point_volume * submerged;
+
+            let point_velocity = match body {
+                Some(body) => body.velocity + body.angular_velocity.cross(point - center),
+                None if estimate_velocity => (point - prev_point) / dt,
+                None => Vec3::ZERO,
+            };
+
+            // Pressure pushes along the surface normal, so waves tilt and shove the body as well as lift it
+            let buoyant_force = surface.normal * WATER_DENSITY * compute_settings.gravity * submerged_volume;
+
+            // Drag acts on the cross section, which scales with volume^(2/3)
+            let relative_velocity = surface.velocity - point_velocity;
+            let drag_force = 0.5 * WATER_DENSITY * buoyancy.drag * submerged_volume.powf(2.0 / 3.0)
+                * relative_velocity.length() * relative_velocity;
+
+            forces.submerged_volume += submerged_volume;
+            forces.buoyancy += buoyant_force;
+            forces.drag += drag_force;
+            forces.torque += (point - center).cross(buoyant_force + drag_force);
+        }
+
+        if let Some(body) = body {
+            forces.torque -= body.angular_velocity * buoyancy.angular_drag * WATER_DENSITY * forces.submerged_volume;
+        }
+
+        buoyancy.forces = forces;
+    }
+}
+
+/// Semi-implicit Euler integration of [`FloatingBody`] entities under gravity and their buoyancy forces. Bodies whose
+/// [`Buoyancy`] found no surface are left where they are
+pub fn integrate_floating_bodies(
+    mut bodies: Query<(&mut FloatingBody, &mut Transform, Option<&Buoyancy>)>,
+    compute_settings: Res<OceanComputeSettings>,
+    fixed_time: Res<FixedTime>,
+) {
+    let dt = fixed_time.period.as_secs_f32();
+
+    for (mut body, mut transform, buoyancy) in bodies.iter_mut() {
+        if buoyancy.is_some_and(|buoyancy| !buoyancy.forces.sampled) {
+            continue;
+        }
+
+        let forces = buoyancy.map(|buoyancy| buoyancy.forces).unwrap_or_default();
+        let mass = body.mass.max(0.001);
+
+        let acceleration = forces.force() / mass - Vec3::Y * compute_settings.gravity;
+        body.velocity += acceleration * dt;
+
+        // The inertia tensor is diagonal in local space
+        let local_torque = transform.rotation.inverse() * forces.torque;
+        let angular_acceleration = transform.rotation * (local_torque / body.inertia.max(Vec3::splat(0.001)));
+        body.angular_velocity += angular_acceleration * dt;
+
+        transform.translation += body.velocity * dt;
+        transform.rotation = (Quat::from_scaled_axis(body.angular_velocity * dt) * transform.rotation).normalize();
+    }
+}
+
+
+pub struct BuoyancyPlugin;
+
+impl Plugin for BuoyancyPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<Buoyancy>()
+            .register_type::<BuoyancyForces>()
+            .register_type::<FloatingBody>()
+            .add_systems(FixedUpdate, (apply_buoyancy, integrate_floating_bodies).chain());
+    }
+}
@@ -0,0 +1,378 @@
+use bevy::{
+    prelude::*,
+    core_pipeline::{core_3d, fullscreen_vertex_shader::fullscreen_shader_vertex_state, prepass::ViewPrepassTextures},
+    ecs::query::QueryItem,
+    pbr::{GpuLights, LightMeta, ShadowSamplers, ViewLightsUniformOffset, ViewShadowBindings, MAX_CASCADES_PER_LIGHT, MAX_DIRECTIONAL_LIGHTS},
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{NodeRunError, RenderGraphApp, RenderGraphContext, ViewNode, ViewNodeRunner},
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingResource, BindingType, BufferBindingType, CachedRenderPipelineId, ColorTargetState, ColorWrites,
+            FragmentState, MultisampleState, Operations, PipelineCache, PrimitiveState, RenderPassColorAttachment,
+            RenderPassDescriptor, RenderPipelineDescriptor, SamplerBindingType, ShaderDefVal, ShaderStages, ShaderType,
+            TextureAspect, TextureFormat, TextureSampleType, TextureViewDescriptor, TextureViewDimension, UniformBuffer,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        view::{ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    compute::uniforms::OceanComputeTextures,
+    ocean::{OceanSurfaceFrame, OceanSurfaceUniform, OceanSurfaceUniforms},
+    sky::SkyPassPostProcessNode,
+};
+
+
+/// Light focused by the waves onto geometry under the surface.
+///
+/// Applied to whatever the depth prepass sees below the water, after the opaque pass has lit it, so every
+/// `StandardMaterial` picks them up without a material of its own. Caustics redistribute the direct sunlight already
+/// there, brightening it where the waves focus it and dimming it in between. Only the share of the lit color the sun's
+/// diffuse term accounts for is scaled, estimated from the sun and ambient light and masked by the sun's shadow, so
+/// shadowed and ambient lit surfaces keep their color.
+#[derive(Resource, ExtractResource, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct OceanCausticsSettings {
+    pub enabled: bool,
+    /// How far the lighting swings between the focused and spread out parts of the pattern
+    pub strength: f32,
+    /// Scales how quickly light focuses with depth, higher values give sharper caustics closer to the surface
+    pub focus: f32,
+    /// Depth in meters by which the pattern has blurred out completely
+    pub max_depth: f32,
+}
+
+impl Default for OceanCausticsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            strength: 0.8,
+            focus: 1.0,
+            max_depth: 25.0,
+        }
+    }
+}
+
+
+#[derive(Clone, Default, ShaderType)]
+pub struct CausticsUniform {
+    strength: f32,
+    focus: f32,
+    max_depth: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct CausticsUniforms {
+    buf: UniformBuffer<CausticsUniform>,
+}
+
+pub fn prepare_caustics_uniforms(
+    mut uniforms: ResMut<CausticsUniforms>,
+    settings: Res<OceanCausticsSettings>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    uniforms.buf.set(CausticsUniform {
+        strength: settings.strength,
+        focus: settings.focus,
+        max_depth: settings.max_depth,
+    });
+    uniforms.buf.write_buffer(&render_device, &render_queue);
+}
+
+
+#[derive(Resource)]
+pub struct CausticsPipeline {
+    layout: BindGroupLayout,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for CausticsPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let uniform_buffer = |binding, dynamic, min_binding_size| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: dynamic,
+                min_binding_size: Some(min_binding_size),
+            },
+            count: None,
+        };
+        let cascade_texture = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ocean_caustics_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                uniform_buffer(1, false, CausticsUniform::min_size()),
+                uniform_buffer(2, true, ViewUniform::min_size()),
+                uniform_buffer(3, true, GpuLights::min_size()),
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                cascade_texture(5),
+                cascade_texture(6),
+                cascade_texture(7),
+                cascade_texture(8),
+                BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                    count: None,
+                },
+                uniform_buffer(11, false, OceanSurfaceUniform::min_size()),
+            ],
+        });
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/caustics.wgsl");
+
+        let pipeline_id = world
+            .resource_mut::<PipelineCache>()
+            .queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some("ocean_caustics_pipeline".into()),
+                layout: vec![layout.clone()],
+                vertex: fullscreen_shader_vertex_state(),
+                fragment: Some(FragmentState {
+                    shader,
+                    // Sizes the light arrays in bevy's view types
+                    shader_defs: vec![
+                        ShaderDefVal::UInt("MAX_DIRECTIONAL_LIGHTS".into(), MAX_DIRECTIONAL_LIGHTS as u32),
+                        ShaderDefVal::UInt("MAX_CASCADES_PER_LIGHT".into(), MAX_CASCADES_PER_LIGHT as u32),
+                    ],
+                    entry_point: "fragment".into(),
+                    targets: vec![Some(ColorTargetState {
+                        format: TextureFormat::bevy_default(),
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                push_constant_ranges: vec![],
+            });
+
+        Self {
+            layout,
+            pipeline_id,
+        }
+    }
+}
+
+
+/// Relights the opaque scene below the water with the caustic pattern, before the sky pass fogs it
+#[derive(Default)]
+pub struct OceanCausticsNode;
+
+impl OceanCausticsNode {
+    pub const NAME: &str = "ocean_caustics";
+}
+
+impl ViewNode for OceanCausticsNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static ViewPrepassTextures,
+        &'static ViewShadowBindings,
+        bevy::ecs::system::lifetimeless::Read<ViewUniformOffset>,
+        bevy::ecs::system::lifetimeless::Read<ViewLightsUniformOffset>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, prepass_textures, shadow_bindings, view_offset, lights_offset): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        if !world.resource::<OceanCausticsSettings>().enabled || world.resource::<OceanSurfaceFrame>().surface.is_none() {
+            return Ok(());
+        }
+
+        let caustics_pipeline = world.resource::<CausticsPipeline>();
+        let Some(pipeline) = world.resource::<PipelineCache>().get_render_pipeline(caustics_pipeline.pipeline_id) else {
+            return Ok(());
+        };
+
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+        let textures = world.resource::<OceanComputeTextures>();
+        let (Some(displacements), Some(prev_displacements), Some(gradients), Some(prev_gradients)) = (
+            gpu_images.get(&textures.displacements),
+            gpu_images.get(&textures.prev_displacements),
+            gpu_images.get(&textures.gradients),
+            gpu_images.get(&textures.prev_gradients),
+        ) else {
+            return Ok(());
+        };
+
+        let Some(depth_view) = prepass_textures.depth.as_ref().map(|texture| texture.texture.create_view(&TextureViewDescriptor {
+            aspect: TextureAspect::DepthOnly,
+            ..default()
+        })) else { return Ok(()); };
+
+        let Some(view_binding) = world.resource::<ViewUniforms>().uniforms.binding() else {
+            return Ok(());
+        };
+        let Some(lights_binding) = world.resource::<LightMeta>().view_gpu_lights.binding() else {
+            return Ok(());
+        };
+
+        let uniforms = world.resource::<CausticsUniforms>();
+        let post_process = view_target.post_process_write();
+
+        let bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
+            label: Some("ocean_caustics_bind_group"),
+            layout: &caustics_pipeline.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(post_process.source),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: uniforms.buf.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: view_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: lights_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&depth_view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&displacements.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: BindingResource::TextureView(&prev_displacements.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: BindingResource::TextureView(&gradients.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 8,
+                    resource: BindingResource::TextureView(&prev_gradients.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 9,
+                    resource: BindingResource::TextureView(&shadow_bindings.directional_light_depth_texture_view),
+                },
+                BindGroupEntry {
+                    binding: 10,
+                    resource: BindingResource::Sampler(&world.resource::<ShadowSamplers>().directional_light_sampler),
+                },
+                BindGroupEntry {
+                    binding: 11,
+                    resource: world.resource::<OceanSurfaceUniforms>().buf.binding().unwrap(),
+                },
+            ],
+        });
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("ocean_caustics_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: post_process.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[view_offset.offset, lights_offset.offset]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+
+pub struct OceanCausticsPlugin;
+
+impl Plugin for OceanCausticsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<OceanCausticsSettings>()
+            .register_type::<OceanCausticsSettings>()
+            .add_plugins(ExtractResourcePlugin::<OceanCausticsSettings>::default());
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<CausticsUniforms>()
+            .add_systems(Render, prepare_caustics_uniforms.in_set(RenderSet::Prepare))
+            .add_render_graph_node::<ViewNodeRunner<OceanCausticsNode>>(
+                core_3d::graph::NAME,
+                OceanCausticsNode::NAME,
+            )
+            .add_render_graph_edges(
+                core_3d::graph::NAME,
+                &[
+                    core_3d::graph::node::MAIN_OPAQUE_PASS,
+                    OceanCausticsNode::NAME,
+                    SkyPassPostProcessNode::NAME,
+                ],
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<CausticsPipeline>();
+    }
+}
@@ -0,0 +1,271 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+use super::uniforms::OceanComputeSettings;
+
+
+// Largest amount of simulated time a single step may cover, so a long hitch doesn't dump foam all at once
+pub const MAX_STEP_DELTA: f32 = 0.25;
+pub const ALL_CASCADES: u32 = 0b1111;
+
+
+/// Drives the ocean simulation at a fixed rate, independent of the render frame rate.
+///
+/// The compute node only dispatches on frames where `stepped` is set, and only for the cascades in
+/// `cascade_mask`. In between, the ocean shader interpolates between the previous and current results
+/// of each cascade using `cascade_blend`.
+///
+/// While no ocean is visible and nothing reads the surface back the clock keeps running but the simulation is
+/// `suspended`. The first step after that updates every cascade and decays the foam by the time spent suspended.
+#[derive(Resource, ExtractResource, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct OceanSimulationClock {
+    /// Simulation steps per second
+    pub rate: f32,
+
+    pub elapsed: f32,
+    pub step_delta: f32,
+    pub blend: f32,
+    pub stepped: bool,
+    pub steps: u64,
+
+    pub cascade_mask: u32,
+    pub cascade_blend: Vec4,
+    /// Simulated time each cascade in `cascade_mask` covers this step, the time since it was last updated
+    pub cascade_delta: Vec4,
+
+    pub suspended: bool,
+    pub resumed: bool,
+    pub suspended_time: f32,
+
+    accumulator: f32,
+    advance: f32,
+    // Elapsed time at each cascade's last update
+    cascade_updated: Vec4,
+}
+
+impl OceanSimulationClock {
+    pub fn step_length(&self) -> f32 {
+        1.0 / self.rate.max(1.0)
+    }
+
+    /// Must be called after visibility is computed and before extraction, every frame
+    pub fn set_visible(&mut self, visible: bool) {
+        if !visible {
+            self.suspended = true;
+            self.suspended_time += self.advance;
+            self.stepped = false;
+            self.cascade_mask = 0;
+            return;
+        }
+
+        if self.suspended {
+            self.suspended = false;
+            self.resumed = true;
+            self.stepped = true;
+            self.step_delta = self.step_length();
+            self.cascade_mask = ALL_CASCADES;
+            // The time spent suspended is covered by `suspended_time`
+            self.cascade_delta = Vec4::splat(self.step_delta);
+            self.cascade_updated = Vec4::splat(self.elapsed);
+        }
+    }
+}
+
+impl Default for OceanSimulationClock {
+    fn default() -> Self {
+        Self {
+            rate: 30.0,
+
+            elapsed: 0.0,
+            step_delta: 0.0,
+            blend: 0.0,
+            stepped: false,
+            steps: 0,
+
+            cascade_mask: 0,
+            cascade_blend: Vec4::ONE,
+            cascade_delta: Vec4::ZERO,
+
+            suspended: false,
+            resumed: false,
+            suspended_time: 0.0,
+
+            accumulator: 0.0,
+            advance: 0.0,
+            cascade_updated: Vec4::ZERO,
+        }
+    }
+}
+
+pub fn tick_simulation_clock(
+    mut clock: ResMut<OceanSimulationClock>,
+    settings: Res<OceanComputeSettings>,
+    time: Res<Time>,
+) {
+    let step_length = clock.step_length();
+    let previous_steps = clock.steps;
+
+    // The resumed step was dispatched last frame, so the time spent suspended has been accounted for
+    if clock.resumed {
+        clock.resumed = false;
+        clock.suspended_time = 0.0;
+    }
+
+    clock.accumulator += time.delta_seconds();
+    clock.stepped = false;
+    clock.advance = 0.0;
+
+    if clock.accumulator >= step_length {
+        let steps = (clock.accumulator / step_length).floor();
+        let advance = steps * step_length;
+
+        clock.elapsed += advance;
+        clock.accumulator -= advance;
+        clock.advance = advance;
+        clock.step_delta = advance.min(MAX_STEP_DELTA);
+        clock.steps += steps as u64;
+        clock.stepped = true;
+    }
+
+    clock.blend = (clock.accumulator / step_length).clamp(0.0, 1.0);
+
+    // Each cascade is offset by its index so cascades sharing an interval don't all land on the same step
+    let mut mask = 0;
+    for (i, interval) in settings.update_intervals().into_iter().enumerate() {
+        let interval = interval.max(1) as u64;
+        let phase = i as u64;
+
+        if clock.stepped && (clock.steps + phase) / interval > (previous_steps + phase) / interval {
+            mask |= 1 << i;
+
+            // A hitch is capped as for a single step, but not below the cascade's own interval
+            let max_delta = MAX_STEP_DELTA.max(interval as f32 * step_length);
+            clock.cascade_delta[i] = (clock.elapsed - clock.cascade_updated[i]).min(max_delta);
+            clock.cascade_updated[i] = clock.elapsed;
+        }
+
+        let steps_since_update = ((clock.steps + phase) % interval) as f32;
+        clock.cascade_blend[i] = ((steps_since_update + clock.blend) / interval as f32).min(1.0);
+    }
+    clock.cascade_mask = mask;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::utils::Instant;
+
+    use super::*;
+    use crate::{compute::readback::OceanReadbackRequest, ocean::{suspend_hidden_ocean, OceanMaterial}};
+
+    // Powers of two so the steps land exactly on the frames
+    const RATE: f32 = 8.0;
+    const FRAME: f32 = 0.125;
+
+    struct Frames {
+        world: World,
+        schedule: Schedule,
+        instant: Instant,
+    }
+
+    impl Frames {
+        fn new() -> Self {
+            let mut world = World::new();
+            world.insert_resource(OceanSimulationClock { rate: RATE, ..default() });
+            world.insert_resource(OceanComputeSettings {
+                update_interval_0: 1,
+                update_interval_1: 1,
+                update_interval_2: 4,
+                update_interval_3: 4,
+                ..default()
+            });
+
+            let instant = Instant::now();
+            let mut time = Time::default();
+            time.update_with_instant(instant);
+            world.insert_resource(time);
+
+            let mut schedule = Schedule::default();
+            schedule.add_systems(tick_simulation_clock);
+
+            Self { world, schedule, instant }
+        }
+
+        fn run(&mut self, visible: bool) {
+            self.instant += Duration::from_secs_f32(FRAME);
+            let instant = self.instant;
+            self.world.resource_mut::<Time>().update_with_instant(instant);
+            self.schedule.run(&mut self.world);
+            self.world.resource_mut::<OceanSimulationClock>().set_visible(visible);
+        }
+
+        fn clock(&self) -> &OceanSimulationClock {
+            self.world.resource::<OceanSimulationClock>()
+        }
+    }
+
+    // A visible `ComputedVisibility` can only be made by bevy's own visibility systems
+    fn suspended_with_hidden_ocean(readback: Option<OceanReadbackRequest>) -> bool {
+        let mut world = World::new();
+        world.insert_resource(OceanSimulationClock::default());
+        if let Some(readback) = readback {
+            world.insert_resource(readback);
+        }
+        world.spawn((Handle::<OceanMaterial>::default(), ComputedVisibility::HIDDEN));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems(suspend_hidden_ocean);
+        schedule.run(&mut world);
+
+        world.resource::<OceanSimulationClock>().suspended
+    }
+
+    #[test]
+    fn hidden_ocean_suspends_unless_read_back() {
+        assert!(suspended_with_hidden_ocean(None));
+        assert!(suspended_with_hidden_ocean(Some(OceanReadbackRequest::default())));
+        assert!(!suspended_with_hidden_ocean(Some(OceanReadbackRequest { active: true, ..default() })));
+    }
+
+    #[test]
+    fn suspended_time_is_covered_once() {
+        let mut frames = Frames::new();
+        for _ in 0..8 {
+            frames.run(true);
+        }
+
+        for _ in 0..16 {
+            frames.run(false);
+            assert!(frames.clock().suspended);
+            assert_eq!(frames.clock().cascade_mask, 0);
+        }
+        assert_eq!(frames.clock().suspended_time, 16.0 * FRAME);
+
+        // Resuming updates every cascade by a single step and leaves the rest to `suspended_time`
+        frames.run(true);
+        let clock = frames.clock();
+        assert!(!clock.suspended && clock.resumed && clock.stepped);
+        assert_eq!(clock.cascade_mask, ALL_CASCADES);
+        assert_eq!(clock.cascade_delta, Vec4::splat(FRAME));
+
+        // Slow cascades updated on the next steps only cover the time since the resumed step
+        let mut updated = 0;
+        for steps in 1..=4 {
+            frames.run(true);
+            let clock = frames.clock();
+            assert!(!clock.resumed);
+            assert_eq!(clock.suspended_time, 0.0);
+
+            for (i, interval) in [1, 1, 4, 4].into_iter().enumerate() {
+                if clock.cascade_mask & (1 << i) != 0 {
+                    let since_update = if interval == 1 { 1 } else { steps };
+                    assert_eq!(clock.cascade_delta[i], since_update as f32 * FRAME);
+                    updated |= 1 << i;
+                }
+            }
+        }
+        assert_eq!(updated, ALL_CASCADES);
+    }
+}
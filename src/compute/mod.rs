@@ -11,17 +11,31 @@ pub mod uniforms;
 pub mod pipeline;
 pub mod node;
 pub mod spectrums;
+pub mod clock;
+pub mod readback;
 
 use uniforms::*;
 use spectrums::*;
+use clock::*;
 
 use self::{node::{OceanComputeNode, OceanInitSpectrumStatus}, pipeline::OceanComputePipeline, spectrums::{OceanSpectrumsArray, OceanSpectrumStorage}};
 
-// Should match with workgroup size of horizontal fft and vertical fft and SIZE constant in compute shader
+// Default fft resolution, the compute shader is specialized for each of FFT_SIZES through the FFT_SIZE shader def
 pub const TEXTURE_SIZE: u32 = 256;
+pub const FFT_SIZES: [u32; 3] = [64, 128, 256];
 pub const WORKGROUP_SIZE: u32 = 8;
 
 
+/// Largest supported fft resolution that is not above `n`
+pub fn supported_fft_size(n: u32) -> u32 {
+    FFT_SIZES.into_iter().rev().find(|size| *size <= n).unwrap_or(FFT_SIZES[0])
+}
+
+
+/// Systems that change `OceanComputeSettings`, the textures and spectrum status follow them in the same frame
+#[derive(SystemSet, Debug, Hash, Eq, PartialEq, Clone)]
+pub struct OceanComputeSettingsSet;
+
 #[derive(States, Default, Debug, Hash, Eq, PartialEq, Clone)]
 pub enum SimulationState {
     #[default]
@@ -39,14 +53,18 @@ impl Plugin for OceanComputePlugin {
             .init_resource::<OceanSpectrumsArray>()
             .init_resource::<OceanSpectrumsDisplayArray>()
             .init_resource::<OceanInitSpectrumStatus>()
+            .init_resource::<OceanSimulationClock>()
+            .register_type::<OceanSimulationClock>()
             .add_systems(Startup, setup_textures)
-            .add_systems(Update, update_init_spectrum_status)
+            .add_systems(PreUpdate, tick_simulation_clock)
+            .add_systems(Update, (update_init_spectrum_status, resize_textures, update_spectrums_array).after(OceanComputeSettingsSet))
             .add_plugins((
                 ExtractResourcePlugin::<OceanComputeSettings>::default(),
                 ExtractResourcePlugin::<OceanSpectrumsArray>::default(),
                 ExtractResourcePlugin::<OceanSpectrumsDisplayArray>::default(),
                 ExtractResourcePlugin::<OceanComputeTextures>::default(),
                 ExtractResourcePlugin::<OceanInitSpectrumStatus>::default(),
+                ExtractResourcePlugin::<OceanSimulationClock>::default(),
             ));
 
         let render_app = app.sub_app_mut(RenderApp);
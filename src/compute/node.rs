@@ -1,6 +1,6 @@
-use bevy::{prelude::*, render::{render_graph, render_resource::{PipelineCache, ComputePassDescriptor, BindGroupDescriptor, BindGroupEntry, BindingResource}, renderer::RenderContext, render_asset::RenderAssets, extract_resource::ExtractResource}};
+use bevy::{prelude::*, render::{render_graph, render_resource::{PipelineCache, ComputePassDescriptor, BindGroupDescriptor, BindGroupEntry, BindingResource, Extent3d, Origin3d, ImageCopyTexture, CommandEncoder}, renderer::RenderContext, render_asset::RenderAssets, extract_resource::ExtractResource, texture::GpuImage}};
 
-use super::{pipeline::OceanComputePipeline, uniforms::{OceanComputeTextures, OceanComputeUniforms}, TEXTURE_SIZE, WORKGROUP_SIZE, spectrums::OceanSpectrumStorage};
+use super::{pipeline::OceanComputePipeline, uniforms::{OceanComputeTextures, OceanComputeUniforms}, WORKGROUP_SIZE, spectrums::OceanSpectrumStorage, clock::OceanSimulationClock};
 
 
 #[derive(Resource, ExtractResource, Default, Clone, Copy)]
@@ -29,6 +29,12 @@ impl Default for OceanComputeNode {
 
 impl render_graph::Node for OceanComputeNode {
     fn update(&mut self, world: &mut World) {
+        // Hold on to a pending init spectrum update until the simulation resumes
+        if world.resource::<OceanSimulationClock>().suspended {
+            self.update_init_spectrum = false;
+            return;
+        }
+
         let mut status = world.resource_mut::<OceanInitSpectrumStatus>();
         let trigger = match *status {
             OceanInitSpectrumStatus::Update => true,
@@ -55,6 +61,11 @@ impl render_graph::Node for OceanComputeNode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), render_graph::NodeRunError> {
+        let clock = world.resource::<OceanSimulationClock>();
+        if clock.suspended {
+            return Ok(());
+        }
+
         let pipeline_cache = world.resource::<PipelineCache>();
         let compute_pipelines = world.resource::<OceanComputePipeline>();
 
@@ -66,41 +77,111 @@ impl render_graph::Node for OceanComputeNode {
 
         let displacement_textures = &gpu_images[&ocean_textures.displacements];
         let gradient_textures = &gpu_images[&ocean_textures.gradients];
+        let prev_displacement_textures = &gpu_images[&ocean_textures.prev_displacements];
+        let prev_gradient_textures = &gpu_images[&ocean_textures.prev_gradients];
+        let foam_age_textures = &gpu_images[&ocean_textures.foam_ages];
+        let prev_foam_age_textures = &gpu_images[&ocean_textures.prev_foam_ages];
+        let velocity_textures = &gpu_images[&ocean_textures.velocities];
+        let breaking_textures = &gpu_images[&ocean_textures.breaking];
         let init_spectrum_textures = &gpu_images[&ocean_textures.init_spectrum_textures];
         let spectrum_textures = &gpu_images[&ocean_textures.spectrum_textures];
 
-        let bind_group = render_context
-            .render_device()
-            .create_bind_group(&BindGroupDescriptor {
-                label: Some("ocean_compute_pass_bind_group"),
-                layout: &compute_pipelines.layout,
-                entries: &[
-                    BindGroupEntry {
-                        binding: 0,
-                        resource: uniforms.buf.binding().unwrap(),
-                    },
-                    BindGroupEntry {
-                        binding: 1,
-                        resource: spectrums.buf.binding().unwrap(),
-                    },
-                    BindGroupEntry {
-                        binding: 2,
-                        resource: BindingResource::TextureView(&displacement_textures.texture_view),
-                    },
-                    BindGroupEntry {
-                        binding: 3,
-                        resource: BindingResource::TextureView(&gradient_textures.texture_view),
-                    },
+        // The textures are recreated when the resolution changes, so they are the source of truth for the dispatch size
+        let size = displacement_textures.size.x as u32;
+        let Some(sized_pipelines) = compute_pipelines.sized(size) else {
+            return Ok(());
+        };
+
+        let ping_pong = if compute_pipelines.storage_read_write {
+            None
+        } else {
+            let (Some(init_spectrum_ping_pong), Some(spectrum_ping_pong)) = (&ocean_textures.init_spectrum_ping_pong, &ocean_textures.spectrum_ping_pong) else {
+                return Ok(());
+            };
+            Some((&gpu_images[init_spectrum_ping_pong], &gpu_images[spectrum_ping_pong]))
+        };
+
+        let render_device = render_context.render_device().clone();
+        let create_bind_group = |init_spectrum: &GpuImage, spectrum: &GpuImage, inputs: Option<(&GpuImage, &GpuImage)>| {
+            let mut entries = vec![
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.buf.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: spectrums.buf.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&displacement_textures.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&gradient_textures.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&init_spectrum.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&spectrum.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 9,
+                    resource: BindingResource::TextureView(&velocity_textures.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 10,
+                    resource: BindingResource::TextureView(&breaking_textures.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: BindingResource::TextureView(&prev_displacement_textures.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 11,
+                    resource: BindingResource::TextureView(&prev_foam_age_textures.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 12,
+                    resource: BindingResource::TextureView(&foam_age_textures.texture_view),
+                },
+            ];
+
+            if let Some((init_spectrum_input, spectrum_input)) = inputs {
+                entries.extend([
                     BindGroupEntry {
-                        binding: 4,
-                        resource: BindingResource::TextureView(&init_spectrum_textures.texture_view),
+                        binding: 7,
+                        resource: BindingResource::TextureView(&spectrum_input.texture_view),
                     },
                     BindGroupEntry {
-                        binding: 5,
-                        resource: BindingResource::TextureView(&spectrum_textures.texture_view),
+                        binding: 8,
+                        resource: BindingResource::TextureView(&init_spectrum_input.texture_view),
                     },
-                ],
-            });
+                ]);
+            }
+
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: Some("ocean_compute_pass_bind_group"),
+                layout: &compute_pipelines.layout,
+                entries: &entries,
+            })
+        };
+
+        // Passes using the swapped bind group read what the previous pass wrote to the spectrum and initial spectrum
+        // textures, and write to their ping-pong counterparts. With read-write storage textures both are the same
+        let (bind_group, swapped_bind_group) = match ping_pong {
+            Some((init_spectrum_ping_pong, spectrum_ping_pong)) => (
+                create_bind_group(init_spectrum_textures, spectrum_textures, Some((init_spectrum_ping_pong, spectrum_ping_pong))),
+                create_bind_group(init_spectrum_ping_pong, spectrum_ping_pong, Some((init_spectrum_textures, spectrum_textures))),
+            ),
+            None => {
+                let bind_group = create_bind_group(init_spectrum_textures, spectrum_textures, None);
+                (bind_group.clone(), bind_group)
+            },
+        };
 
         let encoder = render_context.command_encoder();
 
@@ -110,70 +191,103 @@ impl render_graph::Node for OceanComputeNode {
 
                 pass.set_bind_group(0, &bind_group, &[]);
 
-                let Some(pipeline) = pipeline_cache.get_compute_pipeline(compute_pipelines.init_spectrum_pipeline) else {
+                let Some(pipeline) = pipeline_cache.get_compute_pipeline(sized_pipelines.init_spectrum_pipeline) else {
                     return Ok(());
                 };
                 pass.set_pipeline(pipeline);
-                pass.dispatch_workgroups(TEXTURE_SIZE / WORKGROUP_SIZE, TEXTURE_SIZE / WORKGROUP_SIZE, 1);                
+                pass.dispatch_workgroups(size / WORKGROUP_SIZE, size / WORKGROUP_SIZE, 1);                
             }
             {
                 let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
-                pass.set_bind_group(0, &bind_group, &[]);
+                pass.set_bind_group(0, &swapped_bind_group, &[]);
 
-                let Some(pipeline) = pipeline_cache.get_compute_pipeline(compute_pipelines.pack_spectrum_conj_pipeline) else {
+                let Some(pipeline) = pipeline_cache.get_compute_pipeline(sized_pipelines.pack_spectrum_conj_pipeline) else {
                     return Ok(());
                 };
                 pass.set_pipeline(pipeline);
-                pass.dispatch_workgroups(TEXTURE_SIZE / WORKGROUP_SIZE, TEXTURE_SIZE / WORKGROUP_SIZE, 1);                
+                pass.dispatch_workgroups(size / WORKGROUP_SIZE, size / WORKGROUP_SIZE, 1);                
             }
         }
 
+        if !clock.stepped || clock.cascade_mask == 0 {
+            return Ok(());
+        }
+
+        // Keep the last step of every cascade being updated around so the ocean shader can interpolate towards the new one,
+        // and assemble_maps can advect the foam from it
+        for layer in (0..4).filter(|layer| clock.cascade_mask & (1 << layer) != 0) {
+            copy_layer(encoder, displacement_textures, prev_displacement_textures, size, layer);
+            copy_layer(encoder, gradient_textures, prev_gradient_textures, size, layer);
+        }
+
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
             pass.set_bind_group(0, &bind_group, &[]);
 
-            let Some(pipeline) = pipeline_cache.get_compute_pipeline(compute_pipelines.update_spectrum_pipeline) else {
+            let Some(pipeline) = pipeline_cache.get_compute_pipeline(sized_pipelines.update_spectrum_pipeline) else {
                 return Ok(());
             };
             pass.set_pipeline(pipeline);
-            pass.dispatch_workgroups(TEXTURE_SIZE / WORKGROUP_SIZE, TEXTURE_SIZE / WORKGROUP_SIZE, 1);
+            pass.dispatch_workgroups(size / WORKGROUP_SIZE, size / WORKGROUP_SIZE, 1);
         }
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
-            pass.set_bind_group(0, &bind_group, &[]);
+            pass.set_bind_group(0, &swapped_bind_group, &[]);
 
-            let Some(pipeline) = pipeline_cache.get_compute_pipeline(compute_pipelines.horizontal_fft_pipeline) else {
+            let Some(pipeline) = pipeline_cache.get_compute_pipeline(sized_pipelines.horizontal_fft_pipeline) else {
                 return Ok(());
             };
             pass.set_pipeline(pipeline);
-            pass.dispatch_workgroups(1, TEXTURE_SIZE, 1);
+            pass.dispatch_workgroups(1, size, 1);
         }
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
             pass.set_bind_group(0, &bind_group, &[]);
 
-            let Some(pipeline) = pipeline_cache.get_compute_pipeline(compute_pipelines.vertical_fft_pipeline) else {
+            let Some(pipeline) = pipeline_cache.get_compute_pipeline(sized_pipelines.vertical_fft_pipeline) else {
                 return Ok(());
             };
             pass.set_pipeline(pipeline);
-            pass.dispatch_workgroups(1, TEXTURE_SIZE, 1);
+            pass.dispatch_workgroups(1, size, 1);
         }
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
-            pass.set_bind_group(0, &bind_group, &[]);
+            pass.set_bind_group(0, &swapped_bind_group, &[]);
 
-            let Some(pipeline) = pipeline_cache.get_compute_pipeline(compute_pipelines.assemble_maps_pipeline) else {
+            let Some(pipeline) = pipeline_cache.get_compute_pipeline(sized_pipelines.assemble_maps_pipeline) else {
                 return Ok(());
             };
             pass.set_pipeline(pipeline);
-            pass.dispatch_workgroups(TEXTURE_SIZE / WORKGROUP_SIZE, TEXTURE_SIZE / WORKGROUP_SIZE, 1);
+            pass.dispatch_workgroups(size / WORKGROUP_SIZE, size / WORKGROUP_SIZE, 1);
+        }
+
+        // The results from before the simulation was suspended are stale, so don't interpolate from them
+        if clock.resumed {
+            for layer in 0..4 {
+                copy_layer(encoder, displacement_textures, prev_displacement_textures, size, layer);
+                copy_layer(encoder, gradient_textures, prev_gradient_textures, size, layer);
+                copy_layer(encoder, foam_age_textures, prev_foam_age_textures, size, layer);
+            }
         }
 
         Ok(())
     }
+}
+
+fn copy_layer(encoder: &mut CommandEncoder, source: &GpuImage, destination: &GpuImage, size: u32, layer: u32) {
+    let origin = Origin3d { x: 0, y: 0, z: layer };
+    encoder.copy_texture_to_texture(
+        ImageCopyTexture { origin, ..source.texture.as_image_copy() },
+        ImageCopyTexture { origin, ..destination.texture.as_image_copy() },
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+    );
 }
\ No newline at end of file
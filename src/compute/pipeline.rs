@@ -4,18 +4,43 @@ use bevy::{
         render_resource::{
             BindGroupLayout, CachedComputePipelineId, BindGroupLayoutDescriptor, 
             BindGroupLayoutEntry, ShaderStages, BindingType, StorageTextureAccess, 
-            TextureFormat, TextureViewDimension, PipelineCache, ComputePipelineDescriptor, BufferBindingType, ShaderType
+            TextureFormat, TextureViewDimension, PipelineCache, ComputePipelineDescriptor, BufferBindingType, ShaderType, ShaderDefVal,
+            TextureSampleType, WgpuFeatures
         }, 
-        renderer::RenderDevice, 
+        renderer::{RenderDevice, RenderAdapter}, 
     }
 };
 
-use super::{uniforms::OceanComputeSettings, spectrums::OceanSpectrumsArray};
+use super::{uniforms::OceanComputeSettings, spectrums::OceanSpectrumsArray, FFT_SIZES};
 
 
+/// Whether the compute pass can use read-write `Rgba32Float` storage textures.
+/// If not, it falls back to ping-ponging between write-only storage textures and sampled textures
+pub fn supports_storage_read_write(render_adapter: &RenderAdapter, render_device: &RenderDevice) -> bool {
+    render_device.features().contains(WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        && render_adapter
+            .get_texture_format_features(TextureFormat::Rgba32Float)
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE)
+}
+
 #[derive(Resource)]
 pub struct OceanComputePipeline {
     pub layout: BindGroupLayout,
+    pub storage_read_write: bool,
+
+    // One set of pipelines for each of FFT_SIZES, since the fft workgroup size depends on the resolution
+    pub sized_pipelines: Vec<OceanComputePipelineIds>,
+}
+
+impl OceanComputePipeline {
+    pub fn sized(&self, size: u32) -> Option<&OceanComputePipelineIds> {
+        self.sized_pipelines.iter().find(|pipelines| pipelines.size == size)
+    }
+}
+
+pub struct OceanComputePipelineIds {
+    pub size: u32,
 
     pub init_spectrum_pipeline: CachedComputePipelineId,
     pub pack_spectrum_conj_pipeline: CachedComputePipelineId,
@@ -29,70 +54,138 @@ impl FromWorld for OceanComputePipeline {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
 
-        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: None,
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(OceanComputeSettings::min_size()),
-                    },
-                    count: None,
+        let storage_read_write = supports_storage_read_write(world.resource::<RenderAdapter>(), render_device);
+        if !storage_read_write {
+            warn!("Adapter doesn't support read-write Rgba32Float storage textures, using ping-pong textures for the ocean compute pass");
+        }
+        let storage_access = if storage_read_write {
+            StorageTextureAccess::ReadWrite
+        } else {
+            StorageTextureAccess::WriteOnly
+        };
+
+        let mut entries = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(OceanComputeSettings::min_size()),
                 },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(OceanSpectrumsArray::min_size()),
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(OceanSpectrumsArray::min_size()),
                 },
-                BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadWrite,
-                        format: TextureFormat::Rgba32Float,
-                        view_dimension: TextureViewDimension::D2Array,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: storage_access,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2Array,
                 },
-                BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::WriteOnly,
-                        format: TextureFormat::Rg32Float,
-                        view_dimension: TextureViewDimension::D2Array,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::Rg32Float,
+                    view_dimension: TextureViewDimension::D2Array,
                 },
-                BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadWrite,
-                        format: TextureFormat::Rgba32Float,
-                        view_dimension: TextureViewDimension::D2Array,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: storage_access,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2Array,
                 },
-                BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadWrite,
-                        format: TextureFormat::Rgba32Float,
-                        view_dimension: TextureViewDimension::D2Array,
-                    },
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: storage_access,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2Array,
                 },
-            ],
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 9,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::Rgba32Float,
+                    view_dimension: TextureViewDimension::D2Array,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 10,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::R32Float,
+                    view_dimension: TextureViewDimension::D2Array,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 12,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::R32Float,
+                    view_dimension: TextureViewDimension::D2Array,
+                },
+                count: None,
+            },
+        ];
+
+        // Previous displacements and foam ages, foam is advected from them
+        entries.extend([6, 11].map(|binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        }));
+
+        // Spectrum input and initial spectrum input
+        if !storage_read_write {
+            entries.extend((7..=8).map(|binding| BindGroupLayoutEntry {
+                binding,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            }));
+        }
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &entries,
         });
 
             
@@ -102,70 +195,96 @@ impl FromWorld for OceanComputePipeline {
 
         let pipeline_cache = world.resource::<PipelineCache>();
         
-
-        let init_spectrum_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: "initialize_spectrum".into(),
-        });
-        
-        let pack_spectrum_conj_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: "pack_spectrum_conjugates".into(),
-        });
-        
-        let update_spectrum_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: "update_spectrum".into(),
-        });
-        
-        let horizontal_fft_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: "horizontal_fft".into(),
-        });
-        
-        let vertical_fft_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: "vertical_fft".into(),
-        });
-        
-        let assemble_maps_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: "assemble_maps".into(),
-        });
+        let sized_pipelines = FFT_SIZES
+            .into_iter()
+            .map(|size| queue_sized_pipelines(pipeline_cache, &layout, &shader, size, storage_read_write))
+            .collect();
 
         OceanComputePipeline {
-            layout, 
-
-            init_spectrum_pipeline,
-            pack_spectrum_conj_pipeline,
-            update_spectrum_pipeline,
-            horizontal_fft_pipeline,
-            vertical_fft_pipeline,
-            assemble_maps_pipeline,
+            layout,
+            storage_read_write,
+            sized_pipelines,
         }
     }
-}
\ No newline at end of file
+}
+
+fn queue_sized_pipelines(
+    pipeline_cache: &PipelineCache,
+    layout: &BindGroupLayout,
+    shader: &Handle<Shader>,
+    size: u32,
+    storage_read_write: bool,
+) -> OceanComputePipelineIds {
+    let mut shader_defs = vec![
+        ShaderDefVal::UInt("FFT_SIZE".into(), size),
+        ShaderDefVal::UInt("LOG_FFT_SIZE".into(), size.ilog2()),
+    ];
+    if storage_read_write {
+        shader_defs.push("STORAGE_READ_WRITE".into());
+    }
+
+    let init_spectrum_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: shader.clone(),
+        shader_defs: shader_defs.clone(),
+        entry_point: "initialize_spectrum".into(),
+    });
+    
+    let pack_spectrum_conj_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: shader.clone(),
+        shader_defs: shader_defs.clone(),
+        entry_point: "pack_spectrum_conjugates".into(),
+    });
+    
+    let update_spectrum_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: shader.clone(),
+        shader_defs: shader_defs.clone(),
+        entry_point: "update_spectrum".into(),
+    });
+    
+    let horizontal_fft_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: shader.clone(),
+        shader_defs: shader_defs.clone(),
+        entry_point: "horizontal_fft".into(),
+    });
+    
+    let vertical_fft_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: shader.clone(),
+        shader_defs: shader_defs.clone(),
+        entry_point: "vertical_fft".into(),
+    });
+    
+    let assemble_maps_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: shader.clone(),
+        shader_defs: shader_defs.clone(),
+        entry_point: "assemble_maps".into(),
+    });
+
+    OceanComputePipelineIds {
+        size,
+
+        init_spectrum_pipeline,
+        pack_spectrum_conj_pipeline,
+        update_spectrum_pipeline,
+        horizontal_fft_pipeline,
+        vertical_fft_pipeline,
+        assemble_maps_pipeline,
+    }
+}
@@ -0,0 +1,561 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU8, Ordering}};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_graph,
+        render_resource::{Buffer, BufferDescriptor, BufferUsages, MapMode, Extent3d, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Origin3d},
+        renderer::{RenderContext, RenderDevice},
+        render_asset::RenderAssets,
+        texture::GpuImage,
+    },
+    tasks::AsyncComputeTaskPool,
+};
+
+use super::{uniforms::{OceanComputeTextures, OceanComputeSettings}, clock::OceanSimulationClock};
+
+
+const READBACK_SLOT_COUNT: usize = 3;
+
+const SLOT_FREE: u8 = 0;
+const SLOT_WRITTEN: u8 = 1;
+const SLOT_MAPPING: u8 = 2;
+const SLOT_READY: u8 = 3;
+
+// Bytes per texel of the displacements, gradients and velocities
+const DISPLACEMENT_TEXEL_SIZE: u32 = 16;
+const GRADIENT_TEXEL_SIZE: u32 = 8;
+const VELOCITY_TEXEL_SIZE: u32 = 16;
+// Ripples and wakes are Rgba32Float
+const LOCAL_WAVES_TEXEL_SIZE: u32 = 16;
+
+
+/// CPU copy of one cascade, as it was after simulation step `steps`
+#[derive(Default)]
+pub struct OceanCascadeSnapshot {
+    pub steps: u64,
+
+    // Rows of `size` texels each, empty until the cascade has been read back
+    pub displacements: Vec<Vec4>,
+    pub gradients: Vec<Vec2>,
+    // Empty unless the compute pass is producing velocities
+    pub velocities: Vec<Vec4>,
+
+    /// Largest vertical displacement, in either direction
+    pub max_height: f32,
+    /// Largest surface gradient
+    pub max_slope: f32,
+}
+
+impl OceanCascadeSnapshot {
+    pub fn is_empty(&self) -> bool {
+        self.displacements.is_empty()
+    }
+}
+
+/// The last two results of every cascade read back from the GPU. The readback only copies the cascades updated in a
+/// step, the rest are kept from earlier ones
+#[derive(Default)]
+pub struct OceanSurfaceSnapshot {
+    pub size: u32,
+    /// Latest step any cascade was read back after
+    pub steps: u64,
+
+    pub cascades: [OceanCascadeSnapshot; 4],
+    pub prev_cascades: [OceanCascadeSnapshot; 4],
+}
+
+impl OceanSurfaceSnapshot {
+    pub fn apply(&mut self, readback: OceanReadback) {
+        if readback.size != self.size {
+            *self = Self {
+                size: readback.size,
+                ..default()
+            };
+        }
+
+        for (layer, cascade) in readback.cascades {
+            let current = &mut self.cascades[layer];
+            if !current.is_empty() && current.steps >= cascade.steps {
+                continue;
+            }
+            self.prev_cascades[layer] = std::mem::replace(current, cascade);
+        }
+        self.steps = self.steps.max(readback.steps);
+    }
+
+    /// Mask of the cascades in `0..layers` that haven't been read back at resolution `size` yet
+    pub fn missing_layers(&self, size: u32, layers: u32) -> u32 {
+        (0..layers.min(4))
+            .filter(|&layer| self.size != size || self.cascades[layer as usize].is_empty())
+            .fold(0, |mask, layer| mask | (1 << layer))
+    }
+}
+
+/// Cascades copied after simulation step `steps`, on their way to the main world
+pub struct OceanReadback {
+    pub size: u32,
+    pub steps: u64,
+    pub cascades: Vec<(usize, OceanCascadeSnapshot)>,
+}
+
+/// Readbacks finished since the main world last looked, shared between the main and render world
+#[derive(Resource, Clone, Default)]
+pub struct OceanReadbackChannel(pub Arc<Mutex<Vec<OceanReadback>>>);
+
+/// CPU copy of a world space heightfield added on top of the cascades, the ripples or the wakes. Texels hold the
+/// height in r, vertical velocity in g and foam in b
+#[derive(Clone, Default)]
+pub struct OceanLocalWavesSnapshot {
+    /// World space xz of the covered square's corner
+    pub origin: Vec2,
+    /// Zero while the heightfield isn't drawn
+    pub world_size: f32,
+    pub size: u32,
+    pub texels: Vec<Vec4>,
+
+    /// Largest height, in either direction
+    pub max_height: f32,
+    /// Largest difference in height between neighbouring texels, over their distance
+    pub max_slope: f32,
+}
+
+/// The ripples and the wakes, as of frame `frame`
+#[derive(Default)]
+pub struct OceanLocalWavesReadback {
+    pub frame: u64,
+    pub local_waves: [OceanLocalWavesSnapshot; 2],
+}
+
+/// Latest local waves readback, shared between the main and render world
+#[derive(Resource, Clone, Default)]
+pub struct OceanLocalWavesChannel(pub Arc<Mutex<Option<OceanLocalWavesReadback>>>);
+
+
+/// Raised by [`OceanQuery`](crate::query::OceanQuery) whenever something samples the ocean on the CPU. The cascades
+/// are only read back while it keeps being raised
+#[derive(Resource, Default)]
+pub struct OceanReadbackDemand {
+    requested: AtomicBool,
+    pub last_request: Option<f32>,
+}
+
+impl OceanReadbackDemand {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `request` was called since the last time this was
+    pub fn take_requested(&mut self) -> bool {
+        std::mem::take(self.requested.get_mut())
+    }
+}
+
+/// A heightfield the ocean material samples, as it does
+#[derive(Clone)]
+pub struct OceanLocalWavesSource {
+    pub image: Handle<Image>,
+    pub origin: Vec2,
+    pub world_size: f32,
+}
+
+/// What the readback nodes should copy, extracted every frame
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct OceanReadbackRequest {
+    pub active: bool,
+    /// Cascades the CPU has no copy of yet, copied on the next step whether or not they were updated in it
+    pub missing_layers: u32,
+    /// The ripples and the wakes, `None` while the ocean doesn't draw them
+    pub local_waves: [Option<OceanLocalWavesSource>; 2],
+}
+
+#[derive(Default)]
+struct OceanReadbackContents {
+    steps: u64,
+    layers: u32,
+    has_velocities: bool,
+    len: u64,
+}
+
+#[derive(Default)]
+struct OceanLocalWavesContents {
+    frame: u64,
+    // Origin, world size and texture size of each heightfield copied
+    sources: [Option<(Vec2, f32, u32)>; 2],
+    len: u64,
+}
+
+struct OceanReadbackSlot<T> {
+    buffer: Buffer,
+    state: Arc<AtomicU8>,
+    contents: Mutex<T>,
+}
+
+impl<T: Default> OceanReadbackSlot<T> {
+    fn new(render_device: &RenderDevice, label: &'static str, size: u64) -> Self {
+        Self {
+            buffer: render_device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            state: Arc::new(AtomicU8::new(SLOT_FREE)),
+            contents: Mutex::default(),
+        }
+    }
+}
+
+fn free_slot<T>(slots: &[OceanReadbackSlot<T>]) -> Option<&OceanReadbackSlot<T>> {
+    slots.iter().find(|slot| slot.state.load(Ordering::Acquire) == SLOT_FREE)
+}
+
+/// Maps written slots, and hands the data of mapped ones to `receive` before freeing them
+fn poll_slots<T: Default>(
+    slots: &[OceanReadbackSlot<T>],
+    render_device: &RenderDevice,
+    len: impl Fn(&T) -> u64,
+    mut receive: impl FnMut(Vec<f32>, T),
+) {
+    for slot in slots.iter() {
+        match slot.state.load(Ordering::Acquire) {
+            SLOT_WRITTEN => {
+                slot.state.store(SLOT_MAPPING, Ordering::Release);
+                let state = slot.state.clone();
+                render_device.map_buffer(&slot.buffer.slice(..), MapMode::Read, move |result| {
+                    let next = if result.is_ok() { SLOT_READY } else { SLOT_FREE };
+                    state.store(next, Ordering::Release);
+                });
+            },
+            SLOT_READY => {
+                let contents = std::mem::take(&mut *slot.contents.lock().unwrap());
+                let floats: Vec<f32> = {
+                    let data = slot.buffer.slice(..len(&contents)).get_mapped_range();
+                    bytemuck::cast_slice(&data).to_vec()
+                };
+                slot.buffer.unmap();
+                slot.state.store(SLOT_FREE, Ordering::Release);
+                receive(floats, contents);
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Ring of mappable buffers the cascades are copied into after simulation steps
+#[derive(Resource, Default)]
+pub struct OceanReadbackBuffers {
+    size: u32,
+    slots: Vec<OceanReadbackSlot<OceanReadbackContents>>,
+}
+
+/// Ring of mappable buffers the ripples and wakes are copied into every frame
+#[derive(Resource, Default)]
+pub struct OceanLocalWavesReadbackBuffers {
+    frame: u64,
+    slots: Vec<OceanReadbackSlot<OceanLocalWavesContents>>,
+}
+
+fn layer_size(size: u32, texel_size: u32) -> u64 {
+    size as u64 * size as u64 * texel_size as u64
+}
+
+/// Recreates the readback buffers when the fft resolution changes
+pub fn prepare_readback_buffers(
+    mut buffers: ResMut<OceanReadbackBuffers>,
+    textures: Res<OceanComputeTextures>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(displacements) = gpu_images.get(&textures.displacements) else { return };
+    let size = displacements.size.x as u32;
+    if buffers.size == size && !buffers.slots.is_empty() {
+        return;
+    }
+
+    let cascade_size = layer_size(size, DISPLACEMENT_TEXEL_SIZE + GRADIENT_TEXEL_SIZE + VELOCITY_TEXEL_SIZE);
+
+    buffers.size = size;
+    buffers.slots = (0..READBACK_SLOT_COUNT)
+        .map(|_| OceanReadbackSlot::new(&render_device, "ocean_readback_buffer", 4 * cascade_size))
+        .collect();
+}
+
+/// Recreates the local waves readback buffers when the ripple or wake textures change size
+pub fn prepare_local_waves_readback_buffers(
+    mut buffers: ResMut<OceanLocalWavesReadbackBuffers>,
+    request: Res<OceanReadbackRequest>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+) {
+    let len = request.local_waves
+        .iter()
+        .flatten()
+        .filter_map(|source| gpu_images.get(&source.image))
+        .map(|image| layer_size(image.size.x as u32, LOCAL_WAVES_TEXEL_SIZE))
+        .sum::<u64>();
+    if len == 0 || buffers.slots.first().is_some_and(|slot| slot.buffer.size() >= len) {
+        return;
+    }
+
+    buffers.slots = (0..READBACK_SLOT_COUNT)
+        .map(|_| OceanReadbackSlot::new(&render_device, "ocean_local_waves_readback_buffer", len))
+        .collect();
+}
+
+/// Copies the cascades updated in a simulation step into a free readback buffer, while anything samples the ocean
+#[derive(Default)]
+pub struct OceanReadbackNode;
+
+impl OceanReadbackNode {
+    pub const NAME: &'static str = "ocean_readback_node";
+}
+
+impl render_graph::Node for OceanReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let clock = world.resource::<OceanSimulationClock>();
+        let request = world.resource::<OceanReadbackRequest>();
+        let layers = clock.cascade_mask | request.missing_layers;
+        if !clock.stepped || !request.active || layers == 0 {
+            return Ok(());
+        }
+
+        let buffers = world.resource::<OceanReadbackBuffers>();
+        let Some(slot) = free_slot(&buffers.slots) else {
+            return Ok(());
+        };
+
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+        let textures = world.resource::<OceanComputeTextures>();
+        let size = buffers.size;
+        if gpu_images[&textures.displacements].size.x as u32 != size {
+            return Ok(());
+        }
+
+        let has_velocities = world.resource::<OceanComputeSettings>().compute_velocities != 0;
+        let velocities = has_velocities.then_some((&textures.velocities, VELOCITY_TEXEL_SIZE));
+
+        let encoder = render_context.command_encoder();
+        let mut offset = 0;
+        for layer in (0..4).filter(|layer| layers & (1 << layer) != 0) {
+            for (handle, texel_size) in [
+                (&textures.displacements, DISPLACEMENT_TEXEL_SIZE),
+                (&textures.gradients, GRADIENT_TEXEL_SIZE),
+            ].into_iter().chain(velocities) {
+                let image: &GpuImage = &gpu_images[handle];
+                encoder.copy_texture_to_buffer(
+                    ImageCopyTexture {
+                        origin: Origin3d { x: 0, y: 0, z: layer },
+                        ..image.texture.as_image_copy()
+                    },
+                    ImageCopyBuffer {
+                        buffer: &slot.buffer,
+                        layout: ImageDataLayout {
+                            offset,
+                            bytes_per_row: Some(size * texel_size),
+                            rows_per_image: Some(size),
+                        },
+                    },
+                    Extent3d {
+                        width: size,
+                        height: size,
+                        depth_or_array_layers: 1,
+                    },
+                );
+                offset += layer_size(size, texel_size);
+            }
+        }
+
+        *slot.contents.lock().unwrap() = OceanReadbackContents {
+            steps: clock.steps,
+            layers,
+            has_velocities,
+            len: offset,
+        };
+        slot.state.store(SLOT_WRITTEN, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+/// Copies the ripples and wakes the ocean is drawn with into a free readback buffer, while anything samples the
+/// ocean. Runs every frame, since both change every frame
+#[derive(Default)]
+pub struct OceanLocalWavesReadbackNode;
+
+impl OceanLocalWavesReadbackNode {
+    pub const NAME: &'static str = "ocean_local_waves_readback_node";
+}
+
+impl render_graph::Node for OceanLocalWavesReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let request = world.resource::<OceanReadbackRequest>();
+        if !request.active {
+            return Ok(());
+        }
+
+        let buffers = world.resource::<OceanLocalWavesReadbackBuffers>();
+        let Some(slot) = free_slot(&buffers.slots) else {
+            return Ok(());
+        };
+
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+        let encoder = render_context.command_encoder();
+        let mut contents = OceanLocalWavesContents {
+            frame: buffers.frame,
+            ..default()
+        };
+        for (source, copied) in request.local_waves.iter().zip(contents.sources.iter_mut()) {
+            let Some(source) = source else { continue };
+            let Some(image) = gpu_images.get(&source.image) else { continue };
+            let size = image.size.x as u32;
+            if contents.len + layer_size(size, LOCAL_WAVES_TEXEL_SIZE) > slot.buffer.size() {
+                continue;
+            }
+
+            encoder.copy_texture_to_buffer(
+                image.texture.as_image_copy(),
+                ImageCopyBuffer {
+                    buffer: &slot.buffer,
+                    layout: ImageDataLayout {
+                        offset: contents.len,
+                        bytes_per_row: Some(size * LOCAL_WAVES_TEXEL_SIZE),
+                        rows_per_image: Some(size),
+                    },
+                },
+                Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+            contents.len += layer_size(size, LOCAL_WAVES_TEXEL_SIZE);
+            *copied = Some((source.origin, source.world_size, size));
+        }
+
+        *slot.contents.lock().unwrap() = contents;
+        slot.state.store(SLOT_WRITTEN, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+/// Maps written readback buffers, and hands the mapped ones to a task that unpacks them off the render thread
+pub fn read_back_surface(
+    buffers: Res<OceanReadbackBuffers>,
+    channel: Res<OceanReadbackChannel>,
+    render_device: Res<RenderDevice>,
+) {
+    let size = buffers.size;
+    poll_slots(&buffers.slots, &render_device, |contents| contents.len, |floats, contents| {
+        let channel = channel.0.clone();
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let readback = unpack_readback(&floats, size, &contents);
+                channel.lock().unwrap().push(readback);
+            })
+            .detach();
+    });
+
+    render_device.poll(wgpu::Maintain::Poll);
+}
+
+/// Same as [`read_back_surface`] for the ripples and wakes, keeping only the newest readback
+pub fn read_back_local_waves(
+    mut buffers: ResMut<OceanLocalWavesReadbackBuffers>,
+    channel: Res<OceanLocalWavesChannel>,
+    render_device: Res<RenderDevice>,
+) {
+    buffers.frame += 1;
+    poll_slots(&buffers.slots, &render_device, |contents| contents.len, |floats, contents| {
+        let channel = channel.0.clone();
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let readback = unpack_local_waves(&floats, &contents);
+                let mut latest = channel.lock().unwrap();
+                if latest.as_ref().map(|latest| latest.frame) < Some(readback.frame) {
+                    *latest = Some(readback);
+                }
+            })
+            .detach();
+    });
+
+    render_device.poll(wgpu::Maintain::Poll);
+}
+
+fn unpack_readback(floats: &[f32], size: u32, contents: &OceanReadbackContents) -> OceanReadback {
+    let layer_len = (size * size) as usize;
+    let mut rest = floats;
+    let mut take = |len: usize| {
+        let (taken, remaining) = rest.split_at(len);
+        rest = remaining;
+        taken
+    };
+
+    let cascades = (0..4)
+        .filter(|layer| contents.layers & (1 << layer) != 0)
+        .map(|layer| {
+            let displacements = take(layer_len * 4);
+            let gradients = take(layer_len * 2);
+            let velocities = if contents.has_velocities { take(layer_len * 4) } else { &[] };
+
+            let cascade = OceanCascadeSnapshot {
+                steps: contents.steps,
+                displacements: displacements.chunks_exact(4).map(Vec4::from_slice).collect(),
+                gradients: gradients.chunks_exact(2).map(Vec2::from_slice).collect(),
+                velocities: velocities.chunks_exact(4).map(Vec4::from_slice).collect(),
+                max_height: displacements.chunks_exact(4).fold(0.0, |max: f32, texel| max.max(texel[1].abs())),
+                max_slope: gradients.chunks_exact(2).fold(0.0, |max: f32, texel| max.max(Vec2::from_slice(texel).length())),
+            };
+            (layer as usize, cascade)
+        })
+        .collect();
+
+    OceanReadback {
+        size,
+        steps: contents.steps,
+        cascades,
+    }
+}
+
+fn unpack_local_waves(floats: &[f32], contents: &OceanLocalWavesContents) -> OceanLocalWavesReadback {
+    let mut rest = floats;
+    let local_waves = contents.sources.map(|source| {
+        let Some((origin, world_size, size)) = source else { return default() };
+        let (texels, remaining) = rest.split_at((size * size * 4) as usize);
+        rest = remaining;
+
+        let texels: Vec<Vec4> = texels.chunks_exact(4).map(Vec4::from_slice).collect();
+        let size_usize = size as usize;
+        let max_step = texels.iter().enumerate().fold(0.0, |max: f32, (i, texel)| {
+            let right = if (i + 1) % size_usize != 0 { (texels[i + 1].x - texel.x).abs() } else { 0.0 };
+            let down = texels.get(i + size_usize).map_or(0.0, |below| (below.x - texel.x).abs());
+            max.max(right).max(down)
+        });
+
+        OceanLocalWavesSnapshot {
+            origin,
+            world_size,
+            size,
+            max_height: texels.iter().fold(0.0, |max: f32, texel| max.max(texel.x.abs())),
+            max_slope: max_step * size as f32 / world_size,
+            texels,
+        }
+    });
+
+    OceanLocalWavesReadback {
+        frame: contents.frame,
+        local_waves,
+    }
+}
@@ -45,6 +45,26 @@ pub struct OceanSpectrumsArray {
     pub spectrums: [OceanSpectrumSettings; 8],
 }
 
+impl OceanSpectrumsArray {
+    /// Converts the user facing spectrum parameters into the ones the initial spectrum is computed from
+    pub fn from_display(display: &OceanSpectrumsDisplayArray, gravity: f32) -> Self {
+        let mut spectrums = Self::default();
+
+        for (spectrum, display_spec) in spectrums.spectrums.iter_mut().zip(display.spectrums.iter()) {
+            spectrum.scale = display_spec.scale;
+            spectrum.angle = display_spec.angle / 180.0 * std::f32::consts::PI;
+            spectrum.spread_blend = display_spec.spread_blend;
+            spectrum.swell = display_spec.swell.clamp(0.01, 1.0);
+            spectrum.alpha = jonswap_alpha(display_spec.fetch, display_spec.wind_speed, gravity);
+            spectrum.peak_omega = jonswap_peak_freq(display_spec.fetch, display_spec.wind_speed, gravity);
+            spectrum.gamma = display_spec.peak_enhancement;
+            spectrum.short_waves_fade = display_spec.short_waves_fade;
+        }
+
+        spectrums
+    }
+}
+
 #[derive(Resource, ShaderType, ExtractResource, Reflect, Clone)]
 #[reflect(Resource)]
 pub struct OceanSpectrumsDisplayArray {
@@ -153,6 +173,17 @@ fn jonswap_peak_freq(fetch: f32, wind_speed: f32, gravity: f32) -> f32 {
     return 22.0 * (wind_speed * fetch / gravity / gravity).powf(-0.33);
 }
 
+/// Keeps the main world copy of the spectrums in step with the display settings, for CPU side users
+pub fn update_spectrums_array(
+    mut spectrums: ResMut<OceanSpectrumsArray>,
+    display: Res<OceanSpectrumsDisplayArray>,
+    settings: Res<OceanComputeSettings>,
+) {
+    if display.is_changed() || settings.is_changed() {
+        *spectrums = OceanSpectrumsArray::from_display(&display, settings.gravity);
+    }
+}
+
 pub fn prepare_storage(
     mut storage: ResMut<OceanSpectrumStorage>,
     spectrums_arr: Res<OceanSpectrumsDisplayArray>,
@@ -160,19 +191,6 @@ pub fn prepare_storage(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
 ) {
-    let spectrums = storage.buf.get_mut();
-
-    for (i, spectrum) in spectrums.spectrums.iter_mut().enumerate() {
-        let display_spec = &spectrums_arr.spectrums[i];
-        spectrum.scale = display_spec.scale;
-        spectrum.angle = display_spec.angle / 180.0 * std::f32::consts::PI;
-        spectrum.spread_blend = display_spec.spread_blend;
-        spectrum.swell = display_spec.swell.clamp(0.01, 1.0);
-        spectrum.alpha = jonswap_alpha(display_spec.fetch, display_spec.wind_speed, spectrum_uniform.gravity);
-        spectrum.peak_omega = jonswap_peak_freq(display_spec.fetch, display_spec.wind_speed, spectrum_uniform.gravity);
-        spectrum.gamma = display_spec.peak_enhancement;
-        spectrum.short_waves_fade = display_spec.short_waves_fade;
-    }
-    
+    storage.buf.set(OceanSpectrumsArray::from_display(&spectrums_arr, spectrum_uniform.gravity));
     storage.buf.write_buffer(&render_device, &render_queue);
-}
\ No newline at end of file
+}
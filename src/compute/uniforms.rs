@@ -5,13 +5,17 @@ use bevy::{
             Extent3d, TextureDimension, TextureFormat, TextureUsages, ShaderType, UniformBuffer, SamplerDescriptor, FilterMode
         }, 
         renderer::{
-            RenderDevice, RenderQueue
+            RenderDevice, RenderQueue, RenderAdapter
         }, 
         extract_resource::ExtractResource, texture::ImageSampler
     }
 };
 
-use super::{TEXTURE_SIZE, node::OceanInitSpectrumStatus, spectrums::OceanSpectrumsDisplayArray};
+use super::{TEXTURE_SIZE, supported_fft_size, pipeline::supports_storage_read_write, node::OceanInitSpectrumStatus, spectrums::OceanSpectrumsDisplayArray, clock::OceanSimulationClock};
+
+
+// Displacement and slope, then the orbital velocity of each cascade
+pub const SPECTRUM_LAYERS_PER_CASCADE: u32 = 3;
 
 
 #[derive(Clone, Resource, ExtractResource, Reflect, ShaderType)]
@@ -19,6 +23,8 @@ use super::{TEXTURE_SIZE, node::OceanInitSpectrumStatus, spectrums::OceanSpectru
 pub struct OceanComputeSettings {
     pub lambda: Vec2,
     pub frame_time: f32,
+    // Written every frame from the clock, like the rest of the fields the inspector skips
+    #[reflect(ignore)]
     pub delta_time: f32,
     pub gravity: f32,
     pub repeat_time: f32,
@@ -29,6 +35,14 @@ pub struct OceanComputeSettings {
     pub length_scale_1: u32,
     pub length_scale_2: u32,
     pub length_scale_3: u32,
+    pub update_interval_0: u32,
+    pub update_interval_1: u32,
+    pub update_interval_2: u32,
+    pub update_interval_3: u32,
+    #[reflect(ignore)]
+    pub update_mask: u32,
+    #[reflect(ignore)]
+    pub suspended_time: f32,
     pub foam_threshold: f32,
     pub depth: f32,
     pub low_cutoff: f32,
@@ -36,6 +50,17 @@ pub struct OceanComputeSettings {
     pub foam_bias: f32,
     pub foam_decay_rate: f32,
     pub foam_add: f32,
+    /// How far foam is carried by the horizontal orbital velocity of the water each step, one follows it exactly
+    pub foam_advection: f32,
+    // Written every frame from frame_time
+    #[reflect(ignore)]
+    pub time_scale: f32,
+    // Non-zero to fill the velocities texture, which takes an extra fft per cascade. Off unless something needs it,
+    // spray turns it on
+    pub compute_velocities: u32,
+    // Simulated time since each cascade in `update_mask` was last updated
+    #[reflect(ignore)]
+    pub cascade_delta_time: Vec4,
 
     // #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
     // _webgl2_padding: f32,
@@ -55,18 +80,36 @@ impl Default for OceanComputeSettings {
             length_scale_1: 64,
             length_scale_2: 32,
             length_scale_3: 16,
+            // In simulation steps, the long wavelength cascades change slowly enough to be updated less often
+            update_interval_0: 4,
+            update_interval_1: 2,
+            update_interval_2: 1,
+            update_interval_3: 1,
+            update_mask: 0b1111,
+            suspended_time: 0.0,
             n: TEXTURE_SIZE,
             compute_layers: 4,
             delta_time: 0.0,
             seed: 0,
             foam_threshold: 0.1,
             foam_bias: 1.075,
-            foam_add: 0.05,
-            foam_decay_rate: 0.0075,
+            // Both are per second of simulated time
+            foam_add: 1.5,
+            foam_decay_rate: 0.225,
+            foam_advection: 1.0,
+            time_scale: 1.0,
+            compute_velocities: 0,
+            cascade_delta_time: Vec4::ZERO,
         }
     }
 }
 
+impl OceanComputeSettings {
+    pub fn update_intervals(&self) -> [u32; 4] {
+        [self.update_interval_0, self.update_interval_1, self.update_interval_2, self.update_interval_3]
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct OceanComputeUniforms {
     pub buf: UniformBuffer<OceanComputeSettings>,
@@ -78,14 +121,18 @@ pub fn prepare_uniforms(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
 
-    time: Res<Time>,
+    clock: Res<OceanSimulationClock>,
 ) {
     let general = uniforms.buf.get_mut();
     *general = general_settings.clone();
 
-    general.n = TEXTURE_SIZE;
-    general.frame_time = time.elapsed_seconds() * general_settings.frame_time;
-    general.delta_time = time.delta_seconds();
+    general.n = supported_fft_size(general_settings.n);
+    general.frame_time = clock.elapsed * general_settings.frame_time;
+    general.delta_time = clock.step_delta;
+    general.update_mask = clock.cascade_mask;
+    general.cascade_delta_time = clock.cascade_delta;
+    general.suspended_time = clock.suspended_time;
+    general.time_scale = general_settings.frame_time;
 
     uniforms.buf.write_buffer(&render_device, &render_queue);
 }
@@ -103,19 +150,106 @@ pub fn update_init_spectrum_status(
 
 #[derive(Resource, ExtractResource, Clone)]
 pub struct OceanComputeTextures {
+    // Displacement in xyz and foam intensity in w
     pub displacements: Handle<Image>,
     pub gradients: Handle<Image>,
+    pub prev_displacements: Handle<Image>,
+    pub prev_gradients: Handle<Image>,
+    // Orbital velocity of the surface in xyz and the rate of change of the height at a fixed point in w
+    pub velocities: Handle<Image>,
+    // How far past the foam threshold the jacobian is where the surface is breaking, zero elsewhere
+    pub breaking: Handle<Image>,
+    // Seconds since the foam was made, averaged over everything mixed into it
+    pub foam_ages: Handle<Image>,
+    pub prev_foam_ages: Handle<Image>,
     pub init_spectrum_textures: Handle<Image>,
     pub spectrum_textures: Handle<Image>,
+
+    // Only created when read-write storage textures aren't supported, the compute passes then alternate
+    // between these and their counterparts above
+    pub init_spectrum_ping_pong: Option<Handle<Image>>,
+    pub spectrum_ping_pong: Option<Handle<Image>>,
 }
 
 pub fn setup_textures(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
+    settings: Res<OceanComputeSettings>,
+    render_adapter: Res<RenderAdapter>,
+    render_device: Res<RenderDevice>,
+) {
+    let [
+        displacement_im,
+        gradient_im,
+        prev_displacement_im,
+        prev_gradient_im,
+        velocity_im,
+        breaking_im,
+        foam_age_im,
+        prev_foam_age_im,
+        init_spectrum_im,
+        spectrum_im,
+        init_spectrum_ping_pong_im,
+        spectrum_ping_pong_im,
+    ] = create_images(supported_fft_size(settings.n));
+
+    let ping_pong = !supports_storage_read_write(&render_adapter, &render_device);
+
+    commands.insert_resource(OceanComputeTextures {
+        displacements: images.add(displacement_im),
+        gradients: images.add(gradient_im),
+        prev_displacements: images.add(prev_displacement_im),
+        prev_gradients: images.add(prev_gradient_im),
+        velocities: images.add(velocity_im),
+        breaking: images.add(breaking_im),
+        foam_ages: images.add(foam_age_im),
+        prev_foam_ages: images.add(prev_foam_age_im),
+        init_spectrum_textures: images.add(init_spectrum_im),
+        spectrum_textures: images.add(spectrum_im),
+        init_spectrum_ping_pong: ping_pong.then(|| images.add(init_spectrum_ping_pong_im)),
+        spectrum_ping_pong: ping_pong.then(|| images.add(spectrum_ping_pong_im)),
+    });
+}
+
+/// Recreates the compute textures in place when the fft resolution changes, so material handles stay valid
+pub fn resize_textures(
+    settings: Res<OceanComputeSettings>,
+    textures: Res<OceanComputeTextures>,
+    mut images: ResMut<Assets<Image>>,
 ) {
+    let size = supported_fft_size(settings.n);
+    let Some(current) = images.get(&textures.displacements) else { return };
+    if current.texture_descriptor.size.width == size {
+        return;
+    }
+
+    let handles = [
+        Some(&textures.displacements),
+        Some(&textures.gradients),
+        Some(&textures.prev_displacements),
+        Some(&textures.prev_gradients),
+        Some(&textures.velocities),
+        Some(&textures.breaking),
+        Some(&textures.foam_ages),
+        Some(&textures.prev_foam_ages),
+        Some(&textures.init_spectrum_textures),
+        Some(&textures.spectrum_textures),
+        textures.init_spectrum_ping_pong.as_ref(),
+        textures.spectrum_ping_pong.as_ref(),
+    ];
+    for (handle, image) in handles.into_iter().zip(create_images(size)) {
+        if let Some(target) = handle.and_then(|handle| images.get_mut(handle)) {
+            *target = image;
+        }
+    }
+}
+
+/// Displacements, gradients, previous displacements, previous gradients, velocities, breaking, foam ages, previous
+/// foam ages, initial spectrum, spectrum and the initial spectrum and spectrum ping-pong textures
+fn create_images(size: u32) -> [Image; 12] {
     let extent = Extent3d {
-        width: TEXTURE_SIZE,
-        height: TEXTURE_SIZE,
+        width: size,
+        height: size,
         depth_or_array_layers: 4,
     };
     let mut empty_im_rgba = Image::new_fill(
@@ -130,21 +264,28 @@ pub fn setup_textures(
         &[0; 8],
         TextureFormat::Rg32Float,
     );
-    let mut empty_im_rgba_d8 = Image::new_fill(
+    let mut empty_im_r = Image::new_fill(
+        extent,
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::R32Float,
+    );
+    let mut empty_im_rgba_spectrum = Image::new_fill(
         Extent3d {
-            width: TEXTURE_SIZE,
-            height: TEXTURE_SIZE,
-            depth_or_array_layers: 8,
+            width: size,
+            height: size,
+            depth_or_array_layers: 4 * SPECTRUM_LAYERS_PER_CASCADE,
         },
         TextureDimension::D2,
         &[0; 16],
         TextureFormat::Rgba32Float,
     );
 
-    let usage = TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING; 
+    let usage = TextureUsages::COPY_SRC | TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING; 
     empty_im_rgba.texture_descriptor.usage = usage;
     empty_im_rg.texture_descriptor.usage = usage;
-    empty_im_rgba_d8.texture_descriptor.usage = usage;
+    empty_im_r.texture_descriptor.usage = usage;
+    empty_im_rgba_spectrum.texture_descriptor.usage = usage;
 
     let bilinear_sampler = ImageSampler::Descriptor(SamplerDescriptor {
         mag_filter: FilterMode::Linear,
@@ -154,19 +295,24 @@ pub fn setup_textures(
 
     let mut displacement_im = empty_im_rgba.clone();
     let mut gradient_im = empty_im_rg;
+    let mut foam_age_im = empty_im_r.clone();
 
     displacement_im.sampler_descriptor = bilinear_sampler.clone();
-    gradient_im.sampler_descriptor = bilinear_sampler;
-
-    let displacements = images.add(displacement_im);
-    let gradients = images.add(gradient_im);
-    let init_spectrum_textures = images.add(empty_im_rgba);
-    let spectrum_textures = images.add(empty_im_rgba_d8);
+    gradient_im.sampler_descriptor = bilinear_sampler.clone();
+    foam_age_im.sampler_descriptor = bilinear_sampler;
 
-    commands.insert_resource(OceanComputeTextures {
-        displacements,
-        gradients,
-        init_spectrum_textures,
-        spectrum_textures,
-    });
-}
\ No newline at end of file
+    [
+        displacement_im.clone(),
+        gradient_im.clone(),
+        displacement_im.clone(),
+        gradient_im,
+        displacement_im,
+        empty_im_r,
+        foam_age_im.clone(),
+        foam_age_im,
+        empty_im_rgba.clone(),
+        empty_im_rgba_spectrum.clone(),
+        empty_im_rgba,
+        empty_im_rgba_spectrum,
+    ]
+}
@@ -0,0 +1,166 @@
+use bevy::{prelude::*, math::Vec3Swizzles};
+
+use crate::{query::OceanQuery, ripples::{OceanSplash, update_ripple_frame}};
+
+
+/// Where an entity is relative to the surface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum OceanContactState {
+    #[default]
+    Above,
+    /// Crossing the surface
+    Touching,
+    Submerged,
+}
+
+/// Tracks an entity crossing the ocean surface and sends [`WaterEntered`], [`WaterExited`] and [`Submerged`]
+/// events when it does.
+///
+/// The entity is treated as a sphere of `radius` around its origin. It enters the water when the sphere
+/// first touches the surface, and is submerged once all of it is below.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct OceanContact {
+    pub radius: f32,
+    /// Strength of the [`OceanSplash`] sent on entering the water, per m/s of impact speed. Zero for none
+    pub splash_strength: f32,
+
+    pub state: OceanContactState,
+
+    prev_position: Option<Vec3>,
+}
+
+impl OceanContact {
+    pub fn new(radius: f32) -> Self {
+        Self {
+            radius,
+            splash_strength: 0.0,
+            state: OceanContactState::Above,
+            prev_position: None,
+        }
+    }
+
+    pub fn with_splash(mut self, splash_strength: f32) -> Self {
+        self.splash_strength = splash_strength;
+        self
+    }
+}
+
+impl Default for OceanContact {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+
+/// The entity touched the surface coming from above
+#[derive(Event, Debug, Clone, Copy)]
+pub struct WaterEntered {
+    pub entity: Entity,
+    /// Surface point below the entity
+    pub position: Vec3,
+    /// Velocity of the entity relative to the moving surface
+    pub impact_velocity: Vec3,
+}
+
+/// The entity left the water entirely
+#[derive(Event, Debug, Clone, Copy)]
+pub struct WaterExited {
+    pub entity: Entity,
+    pub position: Vec3,
+    pub impact_velocity: Vec3,
+}
+
+/// The entity went fully below the surface
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Submerged {
+    pub entity: Entity,
+    pub position: Vec3,
+    pub impact_velocity: Vec3,
+}
+
+
+pub fn detect_ocean_contacts(
+    mut contacts: Query<(Entity, &mut OceanContact, &GlobalTransform)>,
+    ocean: OceanQuery,
+    time: Res<Time>,
+    mut entered: EventWriter<WaterEntered>,
+    mut exited: EventWriter<WaterExited>,
+    mut submerged: EventWriter<Submerged>,
+    mut splashes: EventWriter<OceanSplash>,
+) {
+    // Asking for a sampler keeps the surface being read back, which costs a copy every step
+    if contacts.is_empty() {
+        return;
+    }
+
+    let Some(sampler) = ocean.sampler() else { return };
+    let delta = time.delta_seconds();
+
+    for (entity, mut contact, transform) in contacts.iter_mut() {
+        let position = transform.translation();
+        let prev_position = contact.prev_position.replace(position);
+        let Some(sample) = sampler.sample(position.xz()) else { continue };
+
+        let velocity = match prev_position {
+            Some(prev_position) if delta > 0.0 => (position - prev_position) / delta,
+            _ => Vec3::ZERO,
+        };
+        let impact_velocity = velocity - sample.velocity;
+
+        // Height of the entity's center above the surface
+        let height = position.y - sample.position.y;
+        let state = if height > contact.radius {
+            OceanContactState::Above
+        } else if height < -contact.radius {
+            OceanContactState::Submerged
+        } else {
+            OceanContactState::Touching
+        };
+
+        // Spawned in place, there was no crossing
+        if prev_position.is_none() {
+            contact.state = state;
+            continue;
+        }
+
+        let prev_state = contact.state;
+        if state == prev_state {
+            continue;
+        }
+        contact.state = state;
+
+        if prev_state == OceanContactState::Above {
+            entered.send(WaterEntered { entity, position: sample.position, impact_velocity });
+
+            if contact.splash_strength > 0.0 {
+                splashes.send(OceanSplash {
+                    position: sample.position,
+                    radius: contact.radius * 2.0,
+                    strength: contact.splash_strength * impact_velocity.length(),
+                });
+            }
+        }
+
+        match state {
+            OceanContactState::Above => exited.send(WaterExited { entity, position: sample.position, impact_velocity }),
+            OceanContactState::Submerged => submerged.send(Submerged { entity, position: sample.position, impact_velocity }),
+            OceanContactState::Touching => (),
+        }
+    }
+}
+
+
+pub struct OceanContactPlugin;
+
+impl Plugin for OceanContactPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<OceanContact>()
+            .add_event::<WaterEntered>()
+            .add_event::<WaterExited>()
+            .add_event::<Submerged>()
+            // Splashes reach the ripple simulation in the same frame
+            .add_systems(Update, detect_ocean_contacts.before(update_ripple_frame));
+    }
+}
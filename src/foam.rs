@@ -0,0 +1,156 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+};
+
+use crate::{math::{hash, smoothstep}, ocean::OceanMaterial};
+
+
+pub const FOAM_TEXTURE_SIZE: u32 = 256;
+// Bubbles across the foam textures, large ones with a layer of smaller ones crowding the lace between them
+const FOAM_CELLS: [u32; 2] = [8, 21];
+// Width of the walls between bubbles, in cells
+const FOAM_WALL_WIDTH: f32 = 0.12;
+
+
+/// Foam textures for oceans that don't bring their own, see [`OceanMaterial::foam_albedo`] and
+/// [`OceanMaterial::foam_normals`]. Bubbles are cells of tiling voronoi noise, the lace is the walls between them
+#[derive(Resource)]
+pub struct OceanFoamTextures {
+    pub albedo: Handle<Image>,
+    pub normals: Handle<Image>,
+}
+
+pub fn setup_foam_textures(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let size = FOAM_TEXTURE_SIZE as usize;
+
+    // Lace in x and the height of the bubbles in y
+    let texels: Vec<Vec2> = (0..size * size)
+        .map(|i| {
+            let uv = (Vec2::new((i % size) as f32, (i / size) as f32) + 0.5) / size as f32;
+            FOAM_CELLS.iter().enumerate().fold(Vec2::ZERO, |texel, (layer, &cells)| {
+                let weight = 0.6f32.powi(layer as i32);
+                let (nearest, second) = cell_distances(uv, cells, layer as u32);
+                let edge = second - nearest;
+
+                let lace = (1.0 - smoothstep(0.0, FOAM_WALL_WIDTH, edge)) * weight;
+                let height = smoothstep(0.0, 0.5, edge) * weight;
+                Vec2::new(texel.x.max(lace), texel.y + height)
+            })
+        })
+        .collect();
+
+    // The walls catch more light than the thin film over each bubble
+    let albedo = texels
+        .iter()
+        .flat_map(|texel| {
+            let brightness = to_unorm(0.8 + 0.2 * texel.x);
+            [brightness, brightness, brightness, to_unorm(texel.x)]
+        })
+        .collect();
+
+    // Slope per cell of the large bubbles
+    let height = |x: usize, y: usize| texels[(y % size) * size + x % size].y;
+    let scale = size as f32 / (2.0 * FOAM_CELLS[0] as f32);
+    let normals = (0..size * size)
+        .flat_map(|i| {
+            let (x, y) = (i % size, i / size);
+            let gradient = Vec2::new(
+                height(x + 1, y) - height(x + size - 1, y),
+                height(x, y + 1) - height(x, y + size - 1),
+            ) * scale;
+            [to_unorm(0.5 + 0.5 * gradient.x), to_unorm(0.5 + 0.5 * gradient.y), 0, 255]
+        })
+        .collect();
+
+    commands.insert_resource(OceanFoamTextures {
+        albedo: images.add(foam_image(albedo)),
+        normals: images.add(foam_image(normals)),
+    });
+}
+
+/// Gives every ocean using textured foam the default textures, unless it already has its own
+pub fn prepare_ocean_foam(
+    handles: Query<&Handle<OceanMaterial>>,
+    mut materials: ResMut<Assets<OceanMaterial>>,
+    foam_textures: Res<OceanFoamTextures>,
+) {
+    for handle in handles.iter() {
+        let Some(mat) = materials.get_mut(handle) else { continue };
+
+        if mat.textured_foam {
+            mat.foam_albedo.get_or_insert_with(|| foam_textures.albedo.clone());
+            mat.foam_normals.get_or_insert_with(|| foam_textures.normals.clone());
+        }
+    }
+}
+
+/// Distances from `uv` to the nearest and second nearest of one jittered point per cell, wrapping around so the
+/// pattern tiles
+fn cell_distances(uv: Vec2, cells: u32, seed: u32) -> (f32, f32) {
+    let p = uv * cells as f32;
+    let cell = p.floor();
+
+    let mut nearest = f32::MAX;
+    let mut second = f32::MAX;
+    for y in -1..=1 {
+        for x in -1..=1 {
+            let neighbour = cell + Vec2::new(x as f32, y as f32);
+            let wrapped_x = (neighbour.x as i32).rem_euclid(cells as i32) as u32;
+            let wrapped_y = (neighbour.y as i32).rem_euclid(cells as i32) as u32;
+            let id = (seed * cells + wrapped_y) * cells + wrapped_x;
+
+            let point = neighbour + Vec2::new(hash(2 * id), hash(2 * id + 1));
+            let distance = point.distance(p);
+            if distance < nearest {
+                second = nearest;
+                nearest = distance;
+            } else if distance < second {
+                second = distance;
+            }
+        }
+    }
+    (nearest, second)
+}
+
+fn foam_image(data: Vec<u8>) -> Image {
+    let mut image = Image::new(
+        Extent3d {
+            width: FOAM_TEXTURE_SIZE,
+            height: FOAM_TEXTURE_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+    );
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+    image
+}
+
+fn to_unorm(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+
+pub struct OceanFoamPlugin;
+
+impl Plugin for OceanFoamPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, setup_foam_textures)
+            .add_systems(Update, prepare_ocean_foam);
+    }
+}
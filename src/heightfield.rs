@@ -0,0 +1,133 @@
+use bevy::{prelude::*, math::Vec3Swizzles};
+
+use crate::query::OceanQuery;
+
+
+/// Grid of ocean heights around an entity, in a form physics engines take for heightfield colliders.
+///
+/// Samples are stored row by row, rows running along the world z axis and columns along x. The grid spans
+/// `size` and is centered on `transform`, heights are relative to its translation. Column-major engines
+/// such as rapier can use [`OceanHeightfield::heights_column_major`].
+#[derive(Debug, Clone, Default, Reflect)]
+pub struct OceanHeightfield {
+    pub rows: usize,
+    pub columns: usize,
+    pub size: Vec2,
+    pub heights: Vec<f32>,
+    pub transform: Transform,
+    /// Incremented on every update, to tell when a collider needs rebuilding
+    pub revision: u64,
+}
+
+impl OceanHeightfield {
+    pub fn height(&self, row: usize, column: usize) -> f32 {
+        self.heights[row * self.columns + column]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[f32]> {
+        self.heights.chunks_exact(self.columns.max(1))
+    }
+
+    pub fn heights_column_major(&self) -> Vec<f32> {
+        (0..self.columns)
+            .flat_map(|column| (0..self.rows).map(move |row| self.height(row, column)))
+            .collect()
+    }
+
+    /// Distance between neighbouring samples along x and z
+    pub fn cell_size(&self) -> Vec2 {
+        self.size / Vec2::new(self.columns.max(2) as f32 - 1.0, self.rows.max(2) as f32 - 1.0)
+    }
+}
+
+/// Keeps an [`OceanHeightfield`] of the displaced ocean around the entity up to date
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct OceanHeightfieldPatch {
+    /// Samples along x and z
+    pub resolution: UVec2,
+    /// World space size of the patch along x and z
+    pub extent: Vec2,
+    /// Seconds between updates, zero to update every frame
+    pub update_interval: f32,
+
+    pub heightfield: OceanHeightfield,
+
+    since_update: Option<f32>,
+}
+
+impl OceanHeightfieldPatch {
+    pub fn new(resolution: UVec2, extent: Vec2) -> Self {
+        Self {
+            resolution,
+            extent,
+            update_interval: 0.0,
+
+            heightfield: OceanHeightfield::default(),
+
+            since_update: None,
+        }
+    }
+}
+
+impl Default for OceanHeightfieldPatch {
+    fn default() -> Self {
+        Self::new(UVec2::splat(16), Vec2::splat(16.0))
+    }
+}
+
+
+pub fn update_heightfield_patches(
+    mut patches: Query<(&mut OceanHeightfieldPatch, &GlobalTransform)>,
+    ocean: OceanQuery,
+    time: Res<Time>,
+) {
+    // Taken once a patch is due, as asking for a sampler keeps the surface being read back
+    let mut sampler = None;
+
+    for (mut patch, transform) in patches.iter_mut() {
+        let since_update = patch.since_update.map_or(f32::INFINITY, |since_update| since_update + time.delta_seconds());
+        patch.since_update = Some(since_update);
+        if since_update < patch.update_interval {
+            continue;
+        }
+
+        // Without a surface yet the patch stays due
+        let Some(sampler) = sampler.get_or_insert_with(|| ocean.sampler()).as_ref() else { continue };
+        let plane_height = sampler.plane_height();
+        patch.since_update = Some(0.0);
+
+        let resolution = patch.resolution.max(UVec2::splat(2));
+        let (columns, rows) = (resolution.x as usize, resolution.y as usize);
+        let cell_size = patch.extent / (resolution - 1).as_vec2();
+
+        // Snapped to whole cells, so the samples stay put while the entity moves within one
+        let center = (transform.translation().xz() / cell_size).round() * cell_size;
+        let extent = patch.extent;
+        let corner = center - extent * 0.5;
+
+        let heightfield = &mut patch.heightfield;
+        heightfield.heights.clear();
+        heightfield.heights.extend((0..rows).flat_map(|row| (0..columns).map(move |column| (row, column))).map(|(row, column)| {
+            let position = corner + Vec2::new(column as f32, row as f32) * cell_size;
+            sampler.height_at(position).unwrap_or(plane_height) - plane_height
+        }));
+
+        heightfield.rows = rows;
+        heightfield.columns = columns;
+        heightfield.size = extent;
+        heightfield.transform = Transform::from_xyz(center.x, plane_height, center.y);
+        heightfield.revision += 1;
+    }
+}
+
+
+pub struct OceanHeightfieldPlugin;
+
+impl Plugin for OceanHeightfieldPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<OceanHeightfieldPatch>()
+            .add_systems(Update, update_heightfield_patches);
+    }
+}
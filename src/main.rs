@@ -6,12 +6,40 @@ pub mod scene;
 pub mod ocean;
 pub mod compute;
 pub mod sky;
+pub mod quality;
+pub mod query;
+pub mod buoyancy;
+pub mod raycast;
+pub mod ripples;
+pub mod wake;
+pub mod spray;
+pub mod contact;
+pub mod seakeeping;
+pub mod heightfield;
+pub mod reflection;
+pub mod refraction;
+pub mod caustics;
+pub mod foam;
+pub mod math;
 // pub mod lod;
 
 use scene::*;
 use ocean::*;
 use compute::{*, uniforms::OceanComputeSettings, spectrums::OceanSpectrumsDisplayArray};
 use sky::*;
+use quality::*;
+use query::*;
+use buoyancy::*;
+use ripples::*;
+use wake::*;
+use spray::*;
+use contact::*;
+use seakeeping::*;
+use heightfield::*;
+use reflection::*;
+use refraction::*;
+use caustics::*;
+use foam::*;
 
 
 fn main() {
@@ -22,9 +50,31 @@ fn main() {
             OceanMaterialPlugin,
             OceanComputePlugin,
             SkyPostProcessPlugin,
+            OceanQualityPlugin,
+            OceanQueryPlugin,
+            BuoyancyPlugin,
+            OceanRipplePlugin,
+            OceanWakePlugin,
+            OceanSprayPlugin,
+            OceanContactPlugin,
+            SeakeepingPlugin,
+            OceanHeightfieldPlugin,
+            OceanReflectionPlugin,
+        ))
+        .add_plugins((
+            OceanRefractionPlugin,
+            OceanCausticsPlugin,
+            OceanFoamPlugin,
+        ))
+        .add_plugins((
             AssetInspectorPlugin::<OceanMaterial>::default(),
             ResourceInspectorPlugin::<OceanComputeSettings>::default(),
             ResourceInspectorPlugin::<OceanSpectrumsDisplayArray>::default(),
+            ResourceInspectorPlugin::<OceanQualityController>::default(),
+            ResourceInspectorPlugin::<OceanRippleSettings>::default(),
+            ResourceInspectorPlugin::<OceanWakeSettings>::default(),
+            ResourceInspectorPlugin::<OceanSpraySettings>::default(),
+            ResourceInspectorPlugin::<OceanCausticsSettings>::default(),
             FilterQueryInspectorPlugin::<With<SkyPostProcessSettings>>::default(),
         ))
         .insert_resource(Msaa::Off)
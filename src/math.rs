@@ -0,0 +1,12 @@
+/// Same integer hash as `hash` in `displacement.wgsl`, mapped to `[0, 1]`
+pub fn hash(state: u32) -> f32 {
+    let n = (state << 13) ^ state;
+    let n = n.wrapping_mul(n.wrapping_mul(n).wrapping_mul(15731).wrapping_add(789221)).wrapping_add(1376312589);
+    (n & 0x7fffffff) as f32 / 0x7fffffff as f32
+}
+
+/// Same as WGSL's `smoothstep`
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -1,15 +1,28 @@
-use bevy::{prelude::*, reflect::TypeUuid, render::render_resource::{AsBindGroup, ShaderType}, asset::load_internal_asset};
+use bevy::{
+    prelude::*, reflect::TypeUuid, pbr::MeshPipelineKey, asset::load_internal_asset, transform::TransformSystem,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::{AsBindGroup, ShaderType, UniformBuffer},
+        renderer::{RenderDevice, RenderQueue},
+        view::VisibilitySystems,
+        Render, RenderApp, RenderSet,
+    },
+};
 
-use crate::{compute::uniforms::OceanComputeTextures, sky::{SkyPostProcessSettings, SkyboxCubemap}};
+use crate::{compute::{uniforms::{OceanComputeTextures, OceanComputeSettings}, clock::OceanSimulationClock, readback::OceanReadbackRequest}, scene::PLANE_LENGTH, sky::{SkyPostProcessSettings, SkyboxCubemap}};
 
 
 pub const OCEAN_MATERIAL_HANDLE: HandleUntyped = 
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x47c886145eab339e);
+/// `ocean::surface`, imported by the ocean, sky and caustics shaders
+pub const OCEAN_SURFACE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5d2e9a61c3f08b47);
 
 
 #[derive(AsBindGroup, Debug, Reflect, Clone, TypeUuid)]
 #[reflect(Debug, Default)]
 #[uuid = "a173c451-405b-48c4-ba15-cbfef5b082b5"]
+#[bind_group_data(OceanMaterialKey)]
 pub struct OceanMaterial {
     #[uniform(0, visibility(vertex, fragment))]
     pub settings: OceanSettings,
@@ -26,6 +39,121 @@ pub struct OceanMaterial {
     #[texture(6, dimension = "cube")]
     #[sampler(7)]
     pub skybox: Option<Handle<Image>>,
+
+    #[texture(8, visibility(vertex, fragment), dimension = "2d_array")]
+    #[sampler(9)]
+    pub prev_displacements: Option<Handle<Image>>,
+    #[texture(10, visibility(vertex, fragment), dimension = "2d_array")]
+    #[sampler(11)]
+    pub prev_gradients: Option<Handle<Image>>,
+
+    #[texture(12, visibility(vertex, fragment))]
+    #[sampler(13)]
+    pub ripples: Option<Handle<Image>>,
+    #[texture(14, visibility(vertex, fragment))]
+    #[sampler(15)]
+    pub wakes: Option<Handle<Image>>,
+
+    #[texture(16)]
+    #[sampler(17)]
+    pub scene_color: Option<Handle<Image>>,
+    #[texture(18)]
+    #[sampler(19)]
+    pub planar_reflection: Option<Handle<Image>>,
+    #[texture(20)]
+    #[sampler(21)]
+    pub opaque_color: Option<Handle<Image>>,
+    /// Foam color in rgb and the lace between its bubbles in a, tiled over every cascade
+    #[texture(22)]
+    #[sampler(23)]
+    pub foam_albedo: Option<Handle<Image>>,
+    /// Slope of the foam's bubbles, stored as `0.5 + 0.5 * gradient` in rg
+    #[texture(24)]
+    #[sampler(25)]
+    pub foam_normals: Option<Handle<Image>>,
+    #[texture(26, dimension = "2d_array")]
+    #[sampler(27)]
+    pub foam_ages: Option<Handle<Image>>,
+    // Written every frame, see `update_ocean_surface`
+    #[uniform(28)]
+    #[reflect(ignore)]
+    pub surface: OceanSurfaceUniform,
+
+    pub feature_level: OceanFeatureLevel,
+    pub reflection_mode: OceanReflectionMode,
+    /// Show the scene under the surface through the water. Moves the ocean into the transparent pass, see
+    /// [`OceanMaterial::transparent`]
+    pub refraction: bool,
+    /// Foam bands where the surface meets geometry. Also moves the ocean into the transparent pass
+    pub contact_foam: bool,
+    /// Shade foam with [`OceanMaterial::foam_albedo`] and [`OceanMaterial::foam_normals`], thinning out as it ages.
+    /// Oceans without their own textures get [`OceanFoamTextures`](crate::foam::OceanFoamTextures)
+    pub textured_foam: bool,
+}
+
+impl OceanMaterial {
+    /// Whether the ocean is drawn in the transparent pass, after the sky pass, so it applies the fog itself. It then
+    /// stays out of the depth prepass, which leaves it holding the scene under the water
+    pub fn transparent(&self) -> bool {
+        self.refraction || self.contact_foam
+    }
+}
+
+/// Which of the more expensive shading terms are compiled into the ocean shader
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum OceanFeatureLevel {
+    /// Single normal, sky reflections replaced by the fog color
+    Low,
+    /// Single normal and sky reflections
+    Medium,
+    /// Separate specular normal, sky reflections and wave peak scattering
+    #[default]
+    High,
+}
+
+/// What the water reflects besides the sky
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum OceanReflectionMode {
+    /// Only the skybox
+    #[default]
+    Sky,
+    /// Rays marched through the depth prepass, picking up last frame's color where they hit
+    ScreenSpace,
+    /// A mirrored camera rendering the scene above the mean water level
+    Planar,
+    /// Screen space where the rays hit, planar where they don't
+    Hybrid,
+}
+
+impl OceanReflectionMode {
+    pub fn screen_space(self) -> bool {
+        matches!(self, Self::ScreenSpace | Self::Hybrid)
+    }
+
+    pub fn planar(self) -> bool {
+        matches!(self, Self::Planar | Self::Hybrid)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct OceanMaterialKey {
+    feature_level: OceanFeatureLevel,
+    reflection_mode: OceanReflectionMode,
+    refraction: bool,
+    contact_foam: bool,
+    textured_foam: bool,
+}
+
+impl From<&OceanMaterial> for OceanMaterialKey {
+    fn from(material: &OceanMaterial) -> Self {
+        Self {
+            feature_level: material.feature_level,
+            reflection_mode: material.reflection_mode,
+            refraction: material.refraction,
+            contact_foam: material.contact_foam,
+            textured_foam: material.textured_foam && material.foam_albedo.is_some() && material.foam_normals.is_some(),
+        }
+    }
 }
 
 impl Material for OceanMaterial {
@@ -42,17 +170,57 @@ impl Material for OceanMaterial {
         "shaders/prepass.wgsl".into()
     }
     fn alpha_mode(&self) -> AlphaMode {
-        AlphaMode::Opaque
+        // Blended only to be drawn after the opaque color is copied, the shader writes full alpha
+        if self.transparent() { AlphaMode::Blend } else { AlphaMode::Opaque }
     }
     fn specialize(
             _pipeline: &bevy::pbr::MaterialPipeline<Self>,
             descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
             _layout: &bevy::render::mesh::MeshVertexBufferLayout,
-            _key: bevy::pbr::MaterialPipelineKey<Self>,
+            key: bevy::pbr::MaterialPipelineKey<Self>,
         ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
         descriptor.vertex.shader_defs.push("DEPTH_CLAMP_ORTHO".into());
+        // The underside is shaded too, for cameras below the surface
+        descriptor.primitive.cull_mode = None;
+        // Transparent pipelines leave depth alone, but whatever is drawn after the ocean still has to be hidden by it
+        if let Some(depth_stencil) = descriptor.depth_stencil.as_mut() {
+            depth_stencil.depth_write_enabled = true;
+        }
         if let Some(fragment) = descriptor.fragment.as_mut() {
             fragment.shader_defs.push("DEPTH_CLAMP_ORTHO".into());
+
+            let feature_level = key.bind_group_data.feature_level;
+            if feature_level != OceanFeatureLevel::Low {
+                fragment.shader_defs.push("OCEAN_ENV_REFLECTION".into());
+            }
+            if feature_level == OceanFeatureLevel::High {
+                fragment.shader_defs.push("OCEAN_SPECULAR_NORMAL".into());
+                fragment.shader_defs.push("OCEAN_PEAK_SCATTER".into());
+            }
+
+            // Prepass and shadow pipelines are specialized here too, and have no depth prepass to read
+            let reflection_mode = key.bind_group_data.reflection_mode;
+            if !key.mesh_key.contains(MeshPipelineKey::DEPTH_PREPASS) {
+                if reflection_mode.screen_space() {
+                    fragment.shader_defs.push("OCEAN_SSR".into());
+                }
+                if reflection_mode.planar() {
+                    fragment.shader_defs.push("OCEAN_PLANAR_REFLECTION".into());
+                }
+                let material = &key.bind_group_data;
+                if material.refraction || material.contact_foam {
+                    fragment.shader_defs.push("OCEAN_TRANSPARENT".into());
+                }
+                if material.refraction {
+                    fragment.shader_defs.push("OCEAN_REFRACTION".into());
+                }
+                if material.contact_foam {
+                    fragment.shader_defs.push("OCEAN_CONTACT_FOAM".into());
+                }
+                if material.textured_foam {
+                    fragment.shader_defs.push("OCEAN_FOAM_TEXTURES".into());
+                }
+            }
         }
         Ok(())
     }
@@ -66,6 +234,22 @@ impl Default for OceanMaterial {
             displacements: None,
             gradients: None,
             skybox: None,
+            prev_displacements: None,
+            prev_gradients: None,
+            ripples: None,
+            wakes: None,
+            scene_color: None,
+            planar_reflection: None,
+            opaque_color: None,
+            foam_albedo: None,
+            foam_normals: None,
+            foam_ages: None,
+            surface: OceanSurfaceUniform::default(),
+            feature_level: OceanFeatureLevel::default(),
+            reflection_mode: OceanReflectionMode::default(),
+            refraction: false,
+            contact_foam: false,
+            textured_foam: true,
         }
     }
 }
@@ -82,7 +266,6 @@ pub struct OceanSettings {
     pub roughness: f32,
     pub foam_roughness: f32,
 
-    pub sun_power: f32,
     pub scatter_color: Vec3,
     pub bubble_color: Vec3,
     pub foam_color: Vec3,
@@ -93,11 +276,56 @@ pub struct OceanSettings {
     pub scatter_strength: f32,
     pub scatter_shadow_strength: f32,
     pub environment_light_strength: f32,
+    /// Radius in shadow map texels of the filter softening directional shadows on the water
+    pub shadow_filter_radius: f32,
 
     pub foam_subtract: f32,
+    // Per cascade interpolation factor between the previous and current simulation step, written every frame
+    pub simulation_blend: Vec4,
+    // Number of cascades the compute pass is producing, written every frame
+    pub active_layers: u32,
     
     pub tile_layers: Vec4,
     pub contribute_layers: Vec4,
+
+    // World space xz of the ripple simulation's corner and the size of the area it covers, zero when disabled
+    pub ripple_origin: Vec2,
+    pub ripple_size: f32,
+    // Same for the ship wake texture, zero without any ships
+    pub wake_origin: Vec2,
+    pub wake_size: f32,
+
+    /// Furthest screen space reflection rays travel, in meters
+    pub ssr_max_distance: f32,
+    /// How far behind the depth buffer a ray may be and still count as hitting it
+    pub ssr_thickness: f32,
+    /// How far the surface normal shifts the planar reflection lookup, in meters
+    pub planar_distortion: f32,
+    // View projection of the frame the scene color was copied from, written every frame
+    pub prev_view_proj: Mat4,
+    // View projection of the planar reflection camera, zero while it isn't rendering
+    pub planar_view_proj: Mat4,
+    /// How far the surface normal shifts the refracted lookup, in meters
+    pub refraction_distortion: f32,
+    /// How quickly each color channel dies off through the water, light surviving `d` meters is `exp(-absorption * d)`
+    pub absorption: Vec3,
+
+    /// Depth of water in meters over which foam fades out from where the surface meets geometry
+    pub contact_foam_width: f32,
+    /// How much noise breaks up the contact foam bands, zero for clean bands
+    pub contact_foam_noise: f32,
+    /// Size in meters of the noise features
+    pub contact_foam_noise_scale: f32,
+    /// Bands washing out from the contact line per second
+    pub contact_foam_speed: f32,
+
+    /// Times the foam textures repeat across each cascade's tile
+    pub foam_tiling: Vec4,
+    /// How far each cascade's horizontal displacement shifts its foam texture lookup, in texture repeats per meter
+    pub foam_distortion: f32,
+    /// Seconds until foam has thinned out into lace
+    pub foam_lace_age: f32,
+    pub foam_normal_strength: f32,
 }
 
 impl Default for OceanSettings {
@@ -112,7 +340,6 @@ impl Default for OceanSettings {
             roughness: 0.075,
             foam_roughness: 1.0,
 
-            sun_power: 8.0,
             bubble_color: Vec3::new(0.01, 0.07, 0.2),
             scatter_color: Vec3::new(0.0, 0.03, 0.02),
             foam_color: Vec3::new(1.0, 1.0, 1.0),
@@ -123,11 +350,37 @@ impl Default for OceanSettings {
             scatter_strength: 0.5,
             scatter_shadow_strength: 0.5,
             environment_light_strength: 0.4,
+            shadow_filter_radius: 2.0,
 
             foam_subtract: -0.84,
+            simulation_blend: Vec4::ONE,
+            active_layers: 4,
 
             tile_layers: Vec4::new(4.0, 8.0, 64.0, 448.0),
             contribute_layers: Vec4::new(1.0, 1.0, 1.0, 1.0),
+
+            ripple_origin: Vec2::ZERO,
+            ripple_size: 0.0,
+            wake_origin: Vec2::ZERO,
+            wake_size: 0.0,
+
+            ssr_max_distance: 100.0,
+            ssr_thickness: 1.0,
+            planar_distortion: 0.5,
+            prev_view_proj: Mat4::IDENTITY,
+            planar_view_proj: Mat4::ZERO,
+            refraction_distortion: 0.3,
+            absorption: Vec3::new(0.45, 0.09, 0.06),
+
+            contact_foam_width: 0.6,
+            contact_foam_noise: 0.6,
+            contact_foam_noise_scale: 0.5,
+            contact_foam_speed: 0.3,
+
+            foam_tiling: Vec4::new(12.0, 6.0, 1.0, 1.0),
+            foam_distortion: 0.1,
+            foam_lace_age: 5.0,
+            foam_normal_strength: 0.5,
         }
     }
 }
@@ -140,6 +393,8 @@ pub fn prepare_ocean_material(
     sky_settings: Query<&SkyPostProcessSettings>,
 
     compute_textures: Res<OceanComputeTextures>,
+    clock: Res<OceanSimulationClock>,
+    compute_settings: Res<OceanComputeSettings>,
 ) {
     for handle in handles.iter() {
         let mat = materials.get_mut(handle).unwrap();
@@ -147,8 +402,14 @@ pub fn prepare_ocean_material(
         if mat.displacements.is_none() {
             mat.displacements = Some(compute_textures.displacements.clone());
             mat.gradients = Some(compute_textures.gradients.clone());
+            mat.prev_displacements = Some(compute_textures.prev_displacements.clone());
+            mat.prev_gradients = Some(compute_textures.prev_gradients.clone());
+            mat.foam_ages = Some(compute_textures.foam_ages.clone());
         }
 
+        mat.settings.simulation_blend = clock.cascade_blend;
+        mat.settings.active_layers = compute_settings.compute_layers;
+
         if mat.skybox.is_none() && skybox.is_loaded {
             mat.skybox = Some(skybox.skybox.clone());
         }
@@ -165,6 +426,99 @@ pub fn prepare_ocean_material(
 }
 
 
+/// Where an ocean is and how its cascades and local waves are combined, for shaders sampling its surface outside of
+/// its own vertex pass. Mirrors `SurfaceSettings` in `surface.wgsl`
+#[derive(Debug, Clone, Default, ShaderType)]
+pub struct OceanSurfaceUniform {
+    pub transform: Mat4,
+    pub inverse_transform: Mat4,
+    pub tile_layers: Vec4,
+    // Zero for cascades that aren't simulated
+    pub contribute_layers: Vec4,
+    pub simulation_blend: Vec4,
+    pub absorption: Vec3,
+    // Zero without an ocean, which the shaders take as nothing to sample
+    pub plane_length: f32,
+    pub ripple_origin: Vec2,
+    pub wake_origin: Vec2,
+    pub ripple_size: f32,
+    pub wake_size: f32,
+}
+
+impl OceanSurfaceUniform {
+    pub fn new(mat: &OceanMaterial, transform: &GlobalTransform) -> Self {
+        let transform = transform.compute_matrix();
+        Self {
+            transform,
+            inverse_transform: transform.inverse(),
+            tile_layers: mat.settings.tile_layers,
+            contribute_layers: Vec4::from_array(std::array::from_fn(|layer| {
+                if (layer as u32) < mat.settings.active_layers { mat.settings.contribute_layers[layer] } else { 0.0 }
+            })),
+            simulation_blend: mat.settings.simulation_blend,
+            absorption: mat.settings.absorption,
+            plane_length: PLANE_LENGTH,
+            ripple_origin: mat.settings.ripple_origin,
+            wake_origin: mat.settings.wake_origin,
+            ripple_size: mat.settings.ripple_size,
+            wake_size: mat.settings.wake_size,
+        }
+    }
+}
+
+/// Surface of the first ocean entity, for the fullscreen passes. `None` without an ocean
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct OceanSurfaceFrame {
+    pub surface: Option<OceanSurfaceUniform>,
+}
+
+/// Runs once transforms and this frame's settings are final, so the passes see the surface the ocean is drawn with
+pub fn update_ocean_surface(
+    mut frame: ResMut<OceanSurfaceFrame>,
+    oceans: Query<(&Handle<OceanMaterial>, &GlobalTransform)>,
+    mut materials: ResMut<Assets<OceanMaterial>>,
+) {
+    frame.surface = None;
+    for (handle, transform) in oceans.iter() {
+        let Some(mat) = materials.get_mut(handle) else {
+            continue;
+        };
+
+        mat.surface = OceanSurfaceUniform::new(mat, transform);
+        if frame.surface.is_none() {
+            frame.surface = Some(mat.surface.clone());
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct OceanSurfaceUniforms {
+    pub buf: UniformBuffer<OceanSurfaceUniform>,
+}
+
+pub fn prepare_ocean_surface_uniforms(
+    mut uniforms: ResMut<OceanSurfaceUniforms>,
+    frame: Res<OceanSurfaceFrame>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    uniforms.buf.set(frame.surface.clone().unwrap_or_default());
+    uniforms.buf.write_buffer(&render_device, &render_queue);
+}
+
+
+/// Suspends the ocean simulation while no ocean is visible in any view and nothing on the CPU is reading the surface
+/// back, so buoyancy, queries and raycasts keep seeing the waves move while the camera looks away
+pub fn suspend_hidden_ocean(
+    oceans: Query<&ComputedVisibility, With<Handle<OceanMaterial>>>,
+    readback: Option<Res<OceanReadbackRequest>>,
+    mut clock: ResMut<OceanSimulationClock>,
+) {
+    let visible = oceans.iter().any(|visibility| visibility.is_visible());
+    clock.set_visible(visible || readback.is_some_and(|readback| readback.active));
+}
+
+
 pub struct OceanMaterialPlugin;
 
 impl Plugin for OceanMaterialPlugin {
@@ -175,12 +529,34 @@ impl Plugin for OceanMaterialPlugin {
             concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shaders/ocean.wgsl"),
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            OCEAN_SURFACE_SHADER_HANDLE,
+            concat!(env!("CARGO_MANIFEST_DIR"), "/assets/shaders/surface.wgsl"),
+            Shader::from_wgsl
+        );
 
         app
             .add_plugins(MaterialPlugin::<OceanMaterial>::default())
             .add_systems(Update, prepare_ocean_material)
+            .init_resource::<OceanSurfaceFrame>()
+            .add_plugins(ExtractResourcePlugin::<OceanSurfaceFrame>::default())
+            .add_systems(PostUpdate, (
+                suspend_hidden_ocean.after(VisibilitySystems::CheckVisibility),
+                update_ocean_surface.after(TransformSystem::TransformPropagate),
+            ))
             .register_type::<OceanMaterial>()
+            .register_type::<OceanFeatureLevel>()
+            .register_type::<OceanReflectionMode>()
             .register_asset_reflect::<OceanMaterial>()
             .register_type::<Handle<OceanMaterial>>();
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<OceanSurfaceUniforms>()
+            .add_systems(Render, prepare_ocean_surface_uniforms.in_set(RenderSet::Prepare));
     }
 }
\ No newline at end of file
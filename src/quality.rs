@@ -0,0 +1,468 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU8, Ordering}};
+
+use bevy::{
+    prelude::*,
+    core_pipeline::core_3d,
+    ecs::query::QueryItem,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_graph::{self, RenderGraph, ViewNode, ViewNodeRunner},
+        render_resource::{Buffer, BufferDescriptor, BufferUsages, MapMode, WgpuFeatures},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    compute::{clock::OceanSimulationClock, node::OceanComputeNode, uniforms::OceanComputeSettings, OceanComputeSettingsSet, TEXTURE_SIZE},
+    ocean::{OceanFeatureLevel, OceanMaterial},
+    scene::{create_ocean_plane, PLANE_RES},
+    sky::{SkyPassPostProcessNode, SkyPostProcessSettings},
+};
+
+
+// Compute begin, compute end, shading begin, shading end
+pub const TIMESTAMP_COUNT: u32 = 4;
+const READBACK_BUFFER_COUNT: usize = 3;
+const TIMESTAMP_BUFFER_SIZE: u64 = TIMESTAMP_COUNT as u64 * std::mem::size_of::<u64>() as u64;
+
+
+/// One step of the quality ladder used by [`OceanQualityController`].
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct OceanQualityLevel {
+    pub fft_size: u32,
+    pub cascades: u32,
+    pub plane_res: usize,
+    pub feature_level: OceanFeatureLevel,
+}
+
+/// Moves the ocean up and down a ladder of quality levels to keep its GPU cost within `budget_ms`.
+///
+/// The cost is measured with timestamp queries around the ocean compute node and the main camera's pass the
+/// ocean is drawn in, the opaque one or the transparent one when it refracts. That pass includes whatever else
+/// is drawn in it. The compute cost is averaged over the frames that step the simulation only.
+/// On adapters without `TIMESTAMP_QUERY` the ocean stays at its initial level.
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct OceanQualityController {
+    pub enabled: bool,
+    pub budget_ms: f32,
+
+    /// Ordered from cheapest to most expensive
+    pub levels: Vec<OceanQualityLevel>,
+    pub level: usize,
+
+    /// Fraction of the budget above which the quality is lowered
+    pub downgrade_threshold: f32,
+    /// Fraction of the budget below which the quality is raised
+    pub upgrade_threshold: f32,
+    /// Frames the cost has to stay above the downgrade threshold before lowering the quality,
+    /// raising it takes four times as long so the controller doesn't oscillate
+    pub settle_frames: u32,
+    /// Weight of the newest sample in the smoothed timings
+    pub smoothing: f32,
+
+    pub compute_ms: f32,
+    pub shading_ms: f32,
+
+    frames_over: u32,
+    frames_under: u32,
+    applied_level: usize,
+}
+
+impl OceanQualityController {
+    pub fn total_ms(&self) -> f32 {
+        self.compute_ms + self.shading_ms
+    }
+}
+
+impl Default for OceanQualityController {
+    fn default() -> Self {
+        let levels = vec![
+            OceanQualityLevel { fft_size: 64, cascades: 2, plane_res: 1, feature_level: OceanFeatureLevel::Low },
+            OceanQualityLevel { fft_size: 128, cascades: 3, plane_res: 2, feature_level: OceanFeatureLevel::Medium },
+            OceanQualityLevel { fft_size: TEXTURE_SIZE, cascades: 4, plane_res: 3, feature_level: OceanFeatureLevel::Medium },
+            OceanQualityLevel { fft_size: TEXTURE_SIZE, cascades: 4, plane_res: PLANE_RES, feature_level: OceanFeatureLevel::High },
+        ];
+        let level = levels.len() - 1;
+
+        Self {
+            enabled: true,
+            budget_ms: 2.0,
+
+            levels,
+            level,
+
+            downgrade_threshold: 1.0,
+            upgrade_threshold: 0.6,
+            settle_frames: 30,
+            smoothing: 0.1,
+
+            compute_ms: 0.0,
+            shading_ms: 0.0,
+
+            frames_over: 0,
+            frames_under: 0,
+            applied_level: level,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct OceanGpuTimingSample {
+    /// `None` on frames the simulation didn't step in
+    pub compute_ms: Option<f32>,
+    pub shading_ms: f32,
+}
+
+/// Latest timings read back from the GPU, shared between the main and render world
+#[derive(Resource, Clone, Default)]
+pub struct OceanGpuTimings(Arc<Mutex<Option<OceanGpuTimingSample>>>);
+
+pub fn update_quality_controller(
+    mut controller: ResMut<OceanQualityController>,
+    timings: Res<OceanGpuTimings>,
+) {
+    let Some(sample) = timings.0.lock().unwrap().take() else { return };
+
+    let smoothing = controller.smoothing.clamp(0.0, 1.0);
+    if let Some(compute_ms) = sample.compute_ms {
+        controller.compute_ms += (compute_ms - controller.compute_ms) * smoothing;
+    }
+    controller.shading_ms += (sample.shading_ms - controller.shading_ms) * smoothing;
+
+    if !controller.enabled || controller.levels.is_empty() {
+        return;
+    }
+
+    let load = controller.total_ms() / controller.budget_ms.max(0.01);
+    if load > controller.downgrade_threshold {
+        controller.frames_over += 1;
+        controller.frames_under = 0;
+    } else if load < controller.upgrade_threshold {
+        controller.frames_under += 1;
+        controller.frames_over = 0;
+    } else {
+        controller.frames_over = 0;
+        controller.frames_under = 0;
+    }
+
+    let level = controller.level.min(controller.levels.len() - 1);
+    if controller.frames_over >= controller.settle_frames && level > 0 {
+        controller.level = level - 1;
+    } else if controller.frames_under >= controller.settle_frames * 4 && level + 1 < controller.levels.len() {
+        controller.level = level + 1;
+    }
+}
+
+pub fn apply_quality_level(
+    mut controller: ResMut<OceanQualityController>,
+    mut compute_settings: ResMut<OceanComputeSettings>,
+    mut materials: ResMut<Assets<OceanMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    oceans: Query<(&Handle<OceanMaterial>, &Handle<Mesh>)>,
+) {
+    if controller.level == controller.applied_level || controller.levels.is_empty() {
+        return;
+    }
+    controller.level = controller.level.min(controller.levels.len() - 1);
+    let level = controller.levels[controller.level];
+
+    compute_settings.n = level.fft_size;
+    compute_settings.compute_layers = level.cascades.clamp(1, 4);
+
+    for (material_handle, mesh_handle) in oceans.iter() {
+        if let Some(material) = materials.get_mut(material_handle) {
+            material.feature_level = level.feature_level;
+        }
+        if let Some(mesh) = meshes.get_mut(mesh_handle) {
+            *mesh = create_ocean_plane(level.plane_res);
+        }
+    }
+
+    // Timings measured at the old level say nothing about the new one
+    controller.applied_level = controller.level;
+    controller.frames_over = 0;
+    controller.frames_under = 0;
+}
+
+/// Whether the ocean is drawn in the transparent pass rather than the opaque one, deciding which of them is timed
+#[derive(Resource, ExtractResource, Clone, Copy, Default)]
+pub struct OceanShadingPass {
+    pub transparent: bool,
+}
+
+pub fn update_shading_pass(
+    mut pass: ResMut<OceanShadingPass>,
+    materials: Res<Assets<OceanMaterial>>,
+    oceans: Query<&Handle<OceanMaterial>>,
+) {
+    let transparent = oceans
+        .iter()
+        .filter_map(|handle| materials.get(handle))
+        .any(|material| material.transparent());
+    if pass.transparent != transparent {
+        pass.transparent = transparent;
+    }
+}
+
+
+const READBACK_FREE: u8 = 0;
+const READBACK_WRITTEN: u8 = 1;
+const READBACK_MAPPING: u8 = 2;
+const READBACK_READY: u8 = 3;
+
+struct OceanTimerReadback {
+    buffer: Buffer,
+    state: Arc<AtomicU8>,
+    // Whether the compute timestamps cover a simulation step
+    stepped: AtomicBool,
+}
+
+#[derive(Resource)]
+pub struct OceanGpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: Buffer,
+    readbacks: Vec<OceanTimerReadback>,
+    // Nanoseconds per timestamp tick
+    period: f32,
+}
+
+impl OceanGpuTimer {
+    fn new(render_device: &RenderDevice, render_queue: &RenderQueue) -> Self {
+        let query_set = render_device.wgpu_device().create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("ocean_timestamp_query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: TIMESTAMP_COUNT,
+        });
+
+        let resolve_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("ocean_timestamp_resolve_buffer"),
+            size: TIMESTAMP_BUFFER_SIZE,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readbacks = (0..READBACK_BUFFER_COUNT)
+            .map(|_| OceanTimerReadback {
+                buffer: render_device.create_buffer(&BufferDescriptor {
+                    label: Some("ocean_timestamp_readback_buffer"),
+                    size: TIMESTAMP_BUFFER_SIZE,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                state: Arc::new(AtomicU8::new(READBACK_FREE)),
+                stepped: AtomicBool::new(false),
+            })
+            .collect();
+
+        Self {
+            query_set,
+            resolve_buffer,
+            readbacks,
+            period: render_queue.get_timestamp_period(),
+        }
+    }
+}
+
+/// Writes one of the compute timestamps
+pub struct OceanTimestampNode {
+    query: u32,
+}
+
+impl OceanTimestampNode {
+    pub const COMPUTE_BEGIN: &'static str = "ocean_timestamp_compute_begin";
+    pub const COMPUTE_END: &'static str = "ocean_timestamp_compute_end";
+}
+
+impl render_graph::Node for OceanTimestampNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(timer) = world.get_resource::<OceanGpuTimer>() else {
+            return Ok(());
+        };
+
+        render_context.command_encoder().write_timestamp(&timer.query_set, self.query);
+
+        Ok(())
+    }
+}
+
+/// Writes one of the shading timestamps around the pass the ocean is drawn in, for the main camera only. The last
+/// one of the frame also copies the timestamps into a free readback buffer
+pub struct OceanShadingTimestampNode {
+    query: u32,
+    resolve: bool,
+    // Which pass this is next to, it does nothing while the ocean is drawn in the other one
+    transparent: bool,
+}
+
+impl OceanShadingTimestampNode {
+    pub const OPAQUE_BEGIN: &'static str = "ocean_timestamp_opaque_begin";
+    pub const OPAQUE_END: &'static str = "ocean_timestamp_opaque_end";
+    pub const TRANSPARENT_BEGIN: &'static str = "ocean_timestamp_transparent_begin";
+    pub const TRANSPARENT_END: &'static str = "ocean_timestamp_transparent_end";
+}
+
+impl ViewNode for OceanShadingTimestampNode {
+    // The planar reflection camera has no sky settings, so it doesn't overwrite the main camera's timestamps
+    type ViewQuery = &'static SkyPostProcessSettings;
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        _settings: QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(timer) = world.get_resource::<OceanGpuTimer>() else {
+            return Ok(());
+        };
+        if world.resource::<OceanShadingPass>().transparent != self.transparent {
+            return Ok(());
+        }
+
+        let encoder = render_context.command_encoder();
+        encoder.write_timestamp(&timer.query_set, self.query);
+
+        if !self.resolve {
+            return Ok(());
+        }
+
+        let Some(readback) = timer.readbacks.iter().find(|readback| readback.state.load(Ordering::Acquire) == READBACK_FREE) else {
+            return Ok(());
+        };
+
+        encoder.resolve_query_set(&timer.query_set, 0..TIMESTAMP_COUNT, &timer.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&timer.resolve_buffer, 0, &readback.buffer, 0, TIMESTAMP_BUFFER_SIZE);
+        readback.stepped.store(world.resource::<OceanSimulationClock>().stepped, Ordering::Relaxed);
+        readback.state.store(READBACK_WRITTEN, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+pub fn read_back_timestamps(
+    timer: Option<Res<OceanGpuTimer>>,
+    timings: Res<OceanGpuTimings>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(timer) = timer else { return };
+
+    for readback in timer.readbacks.iter() {
+        match readback.state.load(Ordering::Acquire) {
+            READBACK_WRITTEN => {
+                readback.state.store(READBACK_MAPPING, Ordering::Release);
+                let state = readback.state.clone();
+                render_device.map_buffer(&readback.buffer.slice(..), MapMode::Read, move |result| {
+                    let next = if result.is_ok() { READBACK_READY } else { READBACK_FREE };
+                    state.store(next, Ordering::Release);
+                });
+            },
+            READBACK_READY => {
+                let timestamps: Vec<u64> = {
+                    let data = readback.buffer.slice(..).get_mapped_range();
+                    bytemuck::cast_slice(&data).to_vec()
+                };
+                readback.buffer.unmap();
+                readback.state.store(READBACK_FREE, Ordering::Release);
+
+                let elapsed_ms = |begin: u64, end: u64| end.saturating_sub(begin) as f32 * timer.period / 1_000_000.0;
+                let stepped = readback.stepped.load(Ordering::Relaxed);
+                *timings.0.lock().unwrap() = Some(OceanGpuTimingSample {
+                    compute_ms: stepped.then(|| elapsed_ms(timestamps[0], timestamps[1])),
+                    shading_ms: elapsed_ms(timestamps[2], timestamps[3]),
+                });
+            },
+            _ => (),
+        }
+    }
+
+    render_device.poll(wgpu::Maintain::Poll);
+}
+
+
+pub struct OceanQualityPlugin;
+
+impl Plugin for OceanQualityPlugin {
+    fn build(&self, app: &mut App) {
+        let timings = OceanGpuTimings::default();
+
+        app
+            .init_resource::<OceanQualityController>()
+            .init_resource::<OceanShadingPass>()
+            .insert_resource(timings.clone())
+            .register_type::<OceanQualityController>()
+            .register_type::<OceanQualityLevel>()
+            .add_plugins(ExtractResourcePlugin::<OceanShadingPass>::default())
+            .add_systems(Update, (
+                (update_quality_controller, apply_quality_level).chain().in_set(OceanComputeSettingsSet),
+                update_shading_pass,
+            ));
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(timings)
+            .add_systems(Render, read_back_timestamps.in_set(RenderSet::Cleanup));
+
+        let shading_nodes = [
+            (OceanShadingTimestampNode::OPAQUE_BEGIN, OceanShadingTimestampNode { query: 2, resolve: false, transparent: false }),
+            (OceanShadingTimestampNode::OPAQUE_END, OceanShadingTimestampNode { query: 3, resolve: true, transparent: false }),
+            (OceanShadingTimestampNode::TRANSPARENT_BEGIN, OceanShadingTimestampNode { query: 2, resolve: false, transparent: true }),
+            (OceanShadingTimestampNode::TRANSPARENT_END, OceanShadingTimestampNode { query: 3, resolve: true, transparent: true }),
+        ].map(|(name, node)| (name, ViewNodeRunner::new(node, &mut render_app.world)));
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(OceanTimestampNode::COMPUTE_BEGIN, OceanTimestampNode { query: 0 });
+        render_graph.add_node(OceanTimestampNode::COMPUTE_END, OceanTimestampNode { query: 1 });
+        render_graph.add_node_edges(&[
+            OceanTimestampNode::COMPUTE_BEGIN,
+            OceanComputeNode::NAME,
+            OceanTimestampNode::COMPUTE_END,
+            bevy::render::main_graph::node::CAMERA_DRIVER,
+        ]);
+
+        let Some(core_3d_graph) = render_graph.get_sub_graph_mut(core_3d::graph::NAME) else {
+            return;
+        };
+        for (name, node) in shading_nodes {
+            core_3d_graph.add_node(name, node);
+        }
+        core_3d_graph.add_node_edges(&[
+            core_3d::graph::node::PREPASS,
+            OceanShadingTimestampNode::OPAQUE_BEGIN,
+            core_3d::graph::node::MAIN_OPAQUE_PASS,
+            OceanShadingTimestampNode::OPAQUE_END,
+        ]);
+        core_3d_graph.add_node_edges(&[
+            SkyPassPostProcessNode::NAME,
+            OceanShadingTimestampNode::TRANSPARENT_BEGIN,
+            core_3d::graph::node::MAIN_TRANSPARENT_PASS,
+            OceanShadingTimestampNode::TRANSPARENT_END,
+            core_3d::graph::node::END_MAIN_PASS,
+        ]);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        let render_device = render_app.world.resource::<RenderDevice>();
+        if !render_device.features().contains(WgpuFeatures::TIMESTAMP_QUERY) {
+            warn!("Adapter doesn't support timestamp queries, ocean quality will stay at its initial level");
+            return;
+        }
+
+        let timer = OceanGpuTimer::new(render_device, render_app.world.resource::<RenderQueue>());
+        render_app.insert_resource(timer);
+    }
+}
@@ -0,0 +1,457 @@
+use std::ops::{Add, Mul};
+
+use bevy::{
+    prelude::*,
+    ecs::system::SystemParam,
+    math::{Affine3A, Vec3Swizzles, Vec4Swizzles},
+    render::{extract_resource::ExtractResourcePlugin, render_graph::RenderGraph, Render, RenderApp, RenderSet},
+};
+
+use crate::{
+    compute::{
+        clock::OceanSimulationClock,
+        node::OceanComputeNode,
+        readback::{
+            OceanCascadeSnapshot, OceanLocalWavesChannel, OceanLocalWavesReadbackBuffers, OceanLocalWavesReadbackNode,
+            OceanLocalWavesSnapshot, OceanLocalWavesSource, OceanReadbackBuffers, OceanReadbackChannel, OceanReadbackDemand,
+            OceanReadbackNode, OceanReadbackRequest, OceanSurfaceSnapshot, prepare_local_waves_readback_buffers,
+            prepare_readback_buffers, read_back_local_waves, read_back_surface,
+        },
+        supported_fft_size,
+        uniforms::OceanComputeSettings,
+    },
+    math::smoothstep,
+    ocean::{OceanMaterial, OceanSettings},
+    ripples::RippleComputeNode,
+    scene::PLANE_LENGTH,
+    wake::WakeComputeNode,
+};
+
+
+// Fixed-point iterations used to find the undisplaced point that ends up at a queried location
+pub const INVERSION_ITERATIONS: usize = 8;
+
+// Same offsets as the cascade uvs in ocean.wgsl
+pub const LAYER_OFFSETS: [f32; 4] = [0.0, 0.5, 1.125, 1.25];
+
+// Consumers running in FixedUpdate skip frames, so a request keeps the readback going for a while
+const READBACK_DEMAND_HOLD: f32 = 0.5;
+
+
+/// The ocean surface at a horizontal world position
+#[derive(Debug, Clone, Copy)]
+pub struct OceanSample {
+    /// World space surface point directly above or below the queried position
+    pub position: Vec3,
+    /// Offset of that point from the undisplaced plane
+    pub displacement: Vec3,
+    /// Geometric surface normal, without the shading-only `normal_strength` applied
+    pub normal: Vec3,
+    pub velocity: Vec3,
+    pub foam: f32,
+}
+
+
+/// Latest CPU copy of the simulation, received from the render world
+#[derive(Resource, Default)]
+pub struct OceanSurface {
+    snapshot: OceanSurfaceSnapshot,
+    // Ripples and wakes
+    local_waves: [OceanLocalWavesSnapshot; 2],
+}
+
+pub fn receive_surface_snapshot(
+    mut surface: ResMut<OceanSurface>,
+    channel: Res<OceanReadbackChannel>,
+    local_waves_channel: Res<OceanLocalWavesChannel>,
+) {
+    if let Some(readback) = local_waves_channel.0.lock().unwrap().take() {
+        surface.local_waves = readback.local_waves;
+    }
+
+    let mut readbacks = std::mem::take(&mut *channel.0.lock().unwrap());
+    // The tasks unpacking them can finish in any order
+    readbacks.sort_by_key(|readback| readback.steps);
+    for readback in readbacks {
+        surface.snapshot.apply(readback);
+    }
+}
+
+/// Keeps the cascades, ripples and wakes being read back while [`OceanQuery`] is in use, and asks for any cascades
+/// the CPU doesn't have yet
+pub fn update_readback_request(
+    mut demand: ResMut<OceanReadbackDemand>,
+    mut request: ResMut<OceanReadbackRequest>,
+    surface: Res<OceanSurface>,
+    settings: Res<OceanComputeSettings>,
+    materials: Res<Assets<OceanMaterial>>,
+    oceans: Query<&Handle<OceanMaterial>>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds();
+    if demand.take_requested() {
+        demand.last_request = Some(now);
+    }
+
+    request.active = demand.last_request.is_some_and(|last| now - last < READBACK_DEMAND_HOLD);
+    request.missing_layers = surface.snapshot.missing_layers(supported_fft_size(settings.n), settings.compute_layers);
+
+    // Whatever the first ocean is drawn with, the same one the samplers use
+    let material = oceans.iter().next().and_then(|handle| materials.get(handle));
+    request.local_waves = match material {
+        Some(material) => [
+            (&material.ripples, material.settings.ripple_origin, material.settings.ripple_size),
+            (&material.wakes, material.settings.wake_origin, material.settings.wake_size),
+        ].map(|(image, origin, world_size)| {
+            image.clone().filter(|_| world_size > 0.0).map(|image| OceanLocalWavesSource { image, origin, world_size })
+        }),
+        None => default(),
+    };
+}
+
+
+/// Evaluates the ocean surface on the CPU the same way `ocean.wgsl` displaces and shades it, ripples and wakes
+/// included.
+///
+/// The data lags the rendered ocean by the readback latency, see [`OceanQuery::latency_steps`].
+pub struct OceanSurfaceSampler<'a> {
+    snapshot: &'a OceanSurfaceSnapshot,
+    local_waves: &'a [OceanLocalWavesSnapshot; 2],
+    settings: &'a OceanSettings,
+    transform: Affine3A,
+    inverse: Affine3A,
+    blend: Vec4,
+    step_length: f32,
+}
+
+impl<'a> OceanSurfaceSampler<'a> {
+    pub fn sample(&self, world_xz: Vec2) -> Option<OceanSample> {
+        let target = self.inverse.transform_point3(Vec3::new(world_xz.x, 0.0, world_xz.y)).xz();
+
+        // Vertices are displaced horizontally too, so search for the one that lands on the target
+        let mut point = target;
+        for _ in 0..INVERSION_ITERATIONS {
+            let displacement = self.displacement(Self::plane_uv(point));
+            point = target - displacement.xz();
+        }
+
+        let uv = Self::plane_uv(point);
+        if uv.cmplt(Vec2::ZERO).any() || uv.cmpgt(Vec2::ONE).any() {
+            return None;
+        }
+
+        let displacement = self.displacement(uv);
+        let gradient = self.gradient(uv);
+        let local_normal = Vec3::new(-gradient.x, 1.0, -gradient.y).normalize();
+        let mut position = self.transform.transform_point3(Vec3::new(point.x, 0.0, point.y) + displacement.xyz());
+
+        // Ripples and wakes are world space, and added on top of the transformed cascades
+        let local_waves = self.local_waves(position.xz());
+        position.y += local_waves.height;
+        let normal = self.transform.transform_vector3(local_normal);
+        let gradient = -normal.xz() / normal.y + local_waves.gradient;
+
+        Some(OceanSample {
+            position,
+            displacement: displacement.xyz() + Vec3::Y * local_waves.height,
+            normal: Vec3::new(-gradient.x, 1.0, -gradient.y).normalize(),
+            velocity: self.transform.transform_vector3(self.velocity(uv)) + Vec3::Y * local_waves.velocity,
+            foam: displacement.w + local_waves.foam,
+        })
+    }
+
+    pub fn height_at(&self, world_xz: Vec2) -> Option<f32> {
+        self.sample(world_xz).map(|sample| sample.position.y)
+    }
+
+    /// Upper bound on how far the surface reaches above or below the undisplaced plane
+    pub fn max_height(&self) -> f32 {
+        let cascades: f32 = (0..4)
+            .map(|layer| {
+                let max_height = self.snapshot.cascades[layer].max_height.max(self.snapshot.prev_cascades[layer].max_height);
+                max_height * self.contribution(layer).abs()
+            })
+            .sum();
+        cascades + self.local_waves.iter().map(|local_waves| local_waves.max_height).sum::<f32>()
+    }
+
+    /// Upper bound on the steepness of the surface, as rise over run
+    pub fn max_slope(&self) -> f32 {
+        let cascades: f32 = (0..4)
+            .map(|layer| {
+                let max_slope = self.snapshot.cascades[layer].max_slope.max(self.snapshot.prev_cascades[layer].max_slope);
+                max_slope * self.contribution(layer).abs()
+            })
+            .sum();
+        cascades + self.local_waves.iter().map(|local_waves| local_waves.max_slope).sum::<f32>()
+    }
+
+    /// Height of the undisplaced plane in world space
+    pub fn plane_height(&self) -> f32 {
+        self.transform.translation.y
+    }
+
+    fn plane_uv(local_xz: Vec2) -> Vec2 {
+        (local_xz + PLANE_LENGTH * 0.5) / PLANE_LENGTH
+    }
+
+    fn layer_uv(&self, uv: Vec2, layer: usize) -> Vec2 {
+        let uv = (uv - LAYER_OFFSETS[layer]) * self.settings.tile_layers[layer];
+        uv - uv.floor()
+    }
+
+    /// Ripples and wakes at a world position, faded out towards their edges like `sample_local_heightfield`
+    fn local_waves(&self, world_xz: Vec2) -> OceanLocalWaves {
+        self.local_waves.iter().fold(OceanLocalWaves::default(), |sum, local_waves| {
+            if local_waves.world_size <= 0.0 || local_waves.texels.is_empty() {
+                return sum;
+            }
+
+            let uv = (world_xz - local_waves.origin) / local_waves.world_size;
+            let edge = uv.min(1.0 - uv).min_element();
+            let fade = smoothstep(0.0, 0.1, edge);
+            if fade <= 0.0 {
+                return sum;
+            }
+
+            let size = local_waves.size as usize;
+            let texel = 1.0 / local_waves.size as f32;
+            let height = |uv: Vec2| bilinear(&local_waves.texels, size, uv).x;
+            let state = bilinear(&local_waves.texels, size, uv);
+            let gradient = Vec2::new(
+                height(uv + Vec2::new(texel, 0.0)) - height(uv - Vec2::new(texel, 0.0)),
+                height(uv + Vec2::new(0.0, texel)) - height(uv - Vec2::new(0.0, texel)),
+            ) / (2.0 * texel * local_waves.world_size);
+
+            OceanLocalWaves {
+                height: sum.height + state.x * fade,
+                gradient: sum.gradient + gradient * fade,
+                velocity: sum.velocity + state.y * fade,
+                foam: sum.foam.max(state.z * fade),
+            }
+        })
+    }
+
+    fn contribution(&self, layer: usize) -> f32 {
+        if (layer as u32) < self.settings.active_layers && !self.snapshot.cascades[layer].is_empty() {
+            self.settings.contribute_layers[layer]
+        } else {
+            0.0
+        }
+    }
+
+    /// The last two results of a cascade, the current one standing in for both until there are two
+    fn cascades(&self, layer: usize) -> (&OceanCascadeSnapshot, &OceanCascadeSnapshot) {
+        let current = &self.snapshot.cascades[layer];
+        let prev = &self.snapshot.prev_cascades[layer];
+        (if prev.is_empty() { current } else { prev }, current)
+    }
+
+    /// Summed displacement in xyz and foam in w, as in the ocean vertex shader
+    fn displacement(&self, uv: Vec2) -> Vec4 {
+        let mut displacement = Vec4::new(0.0, 0.0, 0.0, self.settings.foam_subtract);
+        for layer in (0..4).filter(|&layer| !self.snapshot.cascades[layer].is_empty()) {
+            let layer_uv = self.layer_uv(uv, layer);
+            let (prev, current) = self.cascades(layer);
+            let sample = self.bilinear(&prev.displacements, layer_uv).lerp(self.bilinear(&current.displacements, layer_uv), self.blend[layer]);
+            displacement += (sample.xyz() * self.contribution(layer)).extend(sample.w);
+        }
+        displacement
+    }
+
+    fn gradient(&self, uv: Vec2) -> Vec2 {
+        (0..4).filter(|&layer| !self.snapshot.cascades[layer].is_empty()).fold(Vec2::ZERO, |gradient, layer| {
+            let layer_uv = self.layer_uv(uv, layer);
+            let (prev, current) = self.cascades(layer);
+            let sample = self.bilinear(&prev.gradients, layer_uv).lerp(self.bilinear(&current.gradients, layer_uv), self.blend[layer]);
+            gradient + sample * self.contribution(layer)
+        })
+    }
+
+    // Without the velocities texture, falls back to the difference between the last two results of each
+    // cascade over the time between them
+    fn velocity(&self, uv: Vec2) -> Vec3 {
+        (0..4).filter(|&layer| !self.snapshot.cascades[layer].is_empty()).fold(Vec3::ZERO, |velocity, layer| {
+            let layer_uv = self.layer_uv(uv, layer);
+            let (prev, current) = self.cascades(layer);
+            if !current.velocities.is_empty() {
+                return velocity + self.bilinear(&current.velocities, layer_uv).xyz() * self.contribution(layer);
+            }
+            if current.steps == prev.steps {
+                return velocity;
+            }
+
+            let period = (current.steps - prev.steps) as f32 * self.step_length;
+            let difference = self.bilinear(&current.displacements, layer_uv) - self.bilinear(&prev.displacements, layer_uv);
+            velocity + difference.xyz() / period * self.contribution(layer)
+        })
+    }
+
+    fn bilinear<T>(&self, data: &[T], uv: Vec2) -> T
+    where
+        T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+    {
+        bilinear(data, self.snapshot.size as usize, uv)
+    }
+}
+
+#[derive(Default)]
+struct OceanLocalWaves {
+    height: f32,
+    gradient: Vec2,
+    velocity: f32,
+    foam: f32,
+}
+
+// Linear filtering with clamp to edge addressing, matching the cascade and local wave samplers
+fn bilinear<T>(data: &[T], size: usize, uv: Vec2) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let texel = uv * size as f32 - 0.5;
+    let base = texel.floor();
+    let t = texel - base;
+
+    let clamp = |i: f32| (i.max(0.0) as usize).min(size - 1);
+    let (x0, x1) = (clamp(base.x), clamp(base.x + 1.0));
+    let (y0, y1) = (clamp(base.y), clamp(base.y + 1.0));
+    let texel_at = |x: usize, y: usize| data[y * size + x];
+
+    let top = texel_at(x0, y0) * (1.0 - t.x) + texel_at(x1, y0) * t.x;
+    let bottom = texel_at(x0, y1) * (1.0 - t.x) + texel_at(x1, y1) * t.x;
+    top * (1.0 - t.y) + bottom * t.y
+}
+
+
+/// Samples the ocean surface on the CPU, for gameplay code such as buoyancy.
+///
+/// The cascades are read back from the GPU asynchronously after the simulation steps that update them, so results
+/// trail the rendered ocean by a few frames. Ripples and wakes are read back every frame and added on top, as in the
+/// shader. Nothing is read back until something samples through this, so the first calls return `None`, as do points
+/// outside the ocean plane.
+#[derive(SystemParam)]
+pub struct OceanQuery<'w, 's> {
+    surface: Res<'w, OceanSurface>,
+    clock: Res<'w, OceanSimulationClock>,
+    demand: Res<'w, OceanReadbackDemand>,
+    materials: Res<'w, Assets<OceanMaterial>>,
+    oceans: Query<'w, 's, (&'static Handle<OceanMaterial>, &'static GlobalTransform)>,
+}
+
+impl<'w, 's> OceanQuery<'w, 's> {
+    /// Sampler over the first ocean, for evaluating many points at once
+    pub fn sampler(&self) -> Option<OceanSurfaceSampler<'_>> {
+        self.demand.request();
+        let snapshot = &self.surface.snapshot;
+        if snapshot.cascades.iter().all(OceanCascadeSnapshot::is_empty) {
+            return None;
+        }
+        let (material_handle, transform) = self.oceans.iter().next()?;
+        let material = self.materials.get(material_handle)?;
+
+        let transform = transform.affine();
+
+        // Once the simulation has moved past the snapshot, its latest result is the closest we have
+        let blend = if snapshot.steps == self.clock.steps {
+            self.clock.cascade_blend
+        } else {
+            Vec4::ONE
+        };
+
+        Some(OceanSurfaceSampler {
+            snapshot,
+            local_waves: &self.surface.local_waves,
+            settings: &material.settings,
+            transform,
+            inverse: transform.inverse(),
+            blend,
+            step_length: self.clock.step_length(),
+        })
+    }
+
+    /// Simulation steps the CPU copy of the ocean is behind the one being rendered
+    pub fn latency_steps(&self) -> Option<u64> {
+        let snapshot = &self.surface.snapshot;
+        (snapshot.size != 0).then(|| self.clock.steps.saturating_sub(snapshot.steps))
+    }
+
+    pub fn sample(&self, world_xz: Vec2) -> Option<OceanSample> {
+        self.sampler()?.sample(world_xz)
+    }
+
+    pub fn height_at(&self, world_xz: Vec2) -> Option<f32> {
+        self.sample(world_xz).map(|sample| sample.position.y)
+    }
+
+    pub fn normal_at(&self, world_xz: Vec2) -> Option<Vec3> {
+        self.sample(world_xz).map(|sample| sample.normal)
+    }
+
+    pub fn displacement_at(&self, world_xz: Vec2) -> Option<Vec3> {
+        self.sample(world_xz).map(|sample| sample.displacement)
+    }
+
+    pub fn velocity_at(&self, world_xz: Vec2) -> Option<Vec3> {
+        self.sample(world_xz).map(|sample| sample.velocity)
+    }
+}
+
+
+pub struct OceanQueryPlugin;
+
+impl Plugin for OceanQueryPlugin {
+    fn build(&self, app: &mut App) {
+        let channel = OceanReadbackChannel::default();
+        let local_waves_channel = OceanLocalWavesChannel::default();
+
+        app
+            .init_resource::<OceanSurface>()
+            .init_resource::<OceanReadbackDemand>()
+            .init_resource::<OceanReadbackRequest>()
+            .insert_resource(channel.clone())
+            .insert_resource(local_waves_channel.clone())
+            .add_plugins(ExtractResourcePlugin::<OceanReadbackRequest>::default())
+            .add_systems(PreUpdate, receive_surface_snapshot)
+            .add_systems(Last, update_readback_request);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(channel)
+            .insert_resource(local_waves_channel)
+            .init_resource::<OceanReadbackBuffers>()
+            .init_resource::<OceanLocalWavesReadbackBuffers>()
+            .add_systems(Render, (
+                prepare_readback_buffers.in_set(RenderSet::Prepare),
+                prepare_local_waves_readback_buffers.in_set(RenderSet::Prepare),
+                read_back_surface.in_set(RenderSet::Cleanup),
+                read_back_local_waves.in_set(RenderSet::Cleanup),
+            ));
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(OceanReadbackNode::NAME, OceanReadbackNode);
+        render_graph.add_node_edges(&[
+            OceanComputeNode::NAME,
+            OceanReadbackNode::NAME,
+            bevy::render::main_graph::node::CAMERA_DRIVER,
+        ]);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        // After the ripple and wake nodes, which are only added by plugins built after this one
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(OceanLocalWavesReadbackNode::NAME, OceanLocalWavesReadbackNode);
+        for node in [RippleComputeNode::NAME, WakeComputeNode::NAME] {
+            if render_graph.get_node_state(node).is_ok() {
+                render_graph.add_node_edge(node, OceanLocalWavesReadbackNode::NAME);
+            }
+        }
+        render_graph.add_node_edge(OceanLocalWavesReadbackNode::NAME, bevy::render::main_graph::node::CAMERA_DRIVER);
+    }
+}
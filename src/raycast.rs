@@ -0,0 +1,118 @@
+use bevy::{prelude::*, ecs::system::SystemParam, math::{Ray, Vec3Swizzles}};
+
+use crate::query::{OceanQuery, OceanSurfaceSampler};
+
+
+pub const MAX_RAYCAST_DISTANCE: f32 = 1000.0;
+const BISECTION_STEPS: usize = 16;
+// Grazing rays skimming the crests could otherwise take a sample for every MIN_MARCH_STEP of their length
+const MAX_MARCH_STEPS: usize = 512;
+const MIN_MARCH_STEP: f32 = 0.01;
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct OceanHit {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+
+/// Intersects rays with the displaced ocean surface, using the same cascade data as [`OceanQuery`].
+///
+/// Mesh based picking only sees the flat plane the ocean is drawn with.
+#[derive(SystemParam)]
+pub struct OceanRaycast<'w, 's> {
+    ocean: OceanQuery<'w, 's>,
+}
+
+impl<'w, 's> OceanRaycast<'w, 's> {
+    pub fn cast(&self, ray: Ray) -> Option<OceanHit> {
+        self.cast_max_distance(ray, MAX_RAYCAST_DISTANCE)
+    }
+
+    pub fn cast_max_distance(&self, ray: Ray, max_distance: f32) -> Option<OceanHit> {
+        raycast(&self.ocean.sampler()?, ray, max_distance)
+    }
+}
+
+
+/// Marches along `ray` within the band the waves can reach, then bisects the first crossing of the surface.
+///
+/// Steps are as long as the surface's steepest slope allows without skipping over it, so grazing rays take long steps
+/// far from the surface and short ones close to it. Rays that haven't found the surface within `MAX_MARCH_STEPS` samples
+/// miss, as do rays without a finite `max_distance`
+pub fn raycast(sampler: &OceanSurfaceSampler, ray: Ray, max_distance: f32) -> Option<OceanHit> {
+    if !max_distance.is_finite() {
+        return None;
+    }
+
+    let direction = ray.direction.normalize();
+    let plane_height = sampler.plane_height();
+    let max_height = sampler.max_height() + MIN_MARCH_STEP;
+    // The surface can only close in on the ray this fast per unit of distance along it
+    let approach_rate = direction.y.abs() + sampler.max_slope() * direction.xz().length();
+
+    // Clip the ray to the slab between the lowest troughs and highest crests
+    let (mut t, end) = if direction.y.abs() < 1e-6 {
+        if (ray.origin.y - plane_height).abs() > max_height {
+            return None;
+        }
+        (0.0, max_distance)
+    } else {
+        let t_top = (plane_height + max_height - ray.origin.y) / direction.y;
+        let t_bottom = (plane_height - max_height - ray.origin.y) / direction.y;
+        (t_top.min(t_bottom).max(0.0), t_top.max(t_bottom).min(max_distance))
+    };
+
+    // Height of the ray above the surface, None outside the ocean plane
+    let height_above = |t: f32| {
+        let point = ray.origin + direction * t;
+        sampler.height_at(point.xz()).map(|height| point.y - height)
+    };
+
+    // Side of the surface the previous sample was on, rays starting underwater hit the surface from below
+    let mut prev: Option<(f32, bool)> = None;
+    for _ in 0..MAX_MARCH_STEPS {
+        if t > end {
+            break;
+        }
+
+        let Some(height) = height_above(t) else {
+            prev = None;
+            t += max_height.max(MIN_MARCH_STEP);
+            continue;
+        };
+        let above = height > 0.0;
+
+        if let Some((prev_t, prev_above)) = prev {
+            if above != prev_above {
+                let (mut near, mut far) = (prev_t, t);
+                for _ in 0..BISECTION_STEPS {
+                    let middle = (near + far) * 0.5;
+                    match height_above(middle) {
+                        Some(height) if (height > 0.0) == prev_above => near = middle,
+                        _ => far = middle,
+                    }
+                }
+                return hit(sampler, ray.origin, direction, (near + far) * 0.5);
+            }
+        }
+
+        prev = Some((t, above));
+        t += (height.abs() / approach_rate.max(1e-3)).max(MIN_MARCH_STEP);
+    }
+
+    None
+}
+
+fn hit(sampler: &OceanSurfaceSampler, origin: Vec3, direction: Vec3, distance: f32) -> Option<OceanHit> {
+    let point = origin + direction * distance;
+    let sample = sampler.sample(point.xz())?;
+
+    Some(OceanHit {
+        point: Vec3::new(point.x, sample.position.y, point.z),
+        normal: sample.normal,
+        distance,
+    })
+}
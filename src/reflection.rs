@@ -0,0 +1,385 @@
+use bevy::{
+    prelude::*,
+    core_pipeline::{core_3d, clear_color::ClearColorConfig, tonemapping::{DebandDither, Tonemapping}},
+    ecs::query::QueryItem,
+    render::{
+        camera::{camera_system, CameraProjection, CameraProjectionPlugin, CameraRenderGraph, CameraUpdateSystem, RenderTarget},
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        primitives::Frustum,
+        render_asset::RenderAssets,
+        render_graph::{NodeRunError, RenderGraphApp, RenderGraphContext, ViewNode, ViewNodeRunner},
+        render_resource::{Extent3d, TextureFormat, TextureUsages},
+        renderer::RenderContext,
+        texture::BevyDefault,
+        view::{update_frusta, ColorGrading, RenderLayers, ViewTarget, VisibilitySystems, VisibleEntities},
+        RenderApp,
+    },
+    transform::TransformSystem,
+};
+
+use crate::{ocean::OceanMaterial, sky::SkyPostProcessSettings};
+
+
+/// Resolution of the planar reflection relative to the main camera's
+pub const PLANAR_REFLECTION_SCALE: f32 = 0.5;
+/// Layer the ocean is moved to while a planar reflection renders, so the reflection camera doesn't see it
+pub const OCEAN_RENDER_LAYER: u8 = 31;
+
+/// Cameras the ocean reflects the view of, the same ones [`crate::ocean::prepare_ocean_material`] takes sky settings from
+pub type MainCamera = (With<SkyPostProcessSettings>, Without<OceanReflectionCamera>);
+
+type ReflectedOcean<'a> = (Entity, &'a Handle<OceanMaterial>, &'a GlobalTransform, Option<&'a RenderLayers>);
+type OtherCamera = (With<Camera>, Without<OceanReflectionCamera>);
+type ReflectionCameraState<'a> = (&'a mut Camera, &'a mut Transform, &'a mut GlobalTransform, &'a mut OceanReflectionProjection);
+
+
+/// Textures the ocean samples for scene reflections
+#[derive(Resource, ExtractResource, Clone)]
+pub struct OceanReflectionImages {
+    /// Last frame's color of the main camera, before tonemapping
+    pub scene_color: Handle<Image>,
+    /// What the mirrored camera sees, transparent where it saw nothing
+    pub planar: Handle<Image>,
+    /// Whether any ocean uses screen space reflections, the scene color is only copied then
+    pub screen_space: bool,
+}
+
+/// Marks the camera rendering the planar reflection
+#[derive(Component, Default)]
+pub struct OceanReflectionCamera;
+
+/// Perspective projection whose near plane is replaced by an arbitrary clip plane, so nothing below the water
+/// ends up in the planar reflection
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component, Default)]
+pub struct OceanReflectionProjection {
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    pub aspect_ratio: f32,
+    /// View space plane, points on its negative side are clipped. Zero for a regular near plane
+    pub clip_plane: Vec4,
+}
+
+impl Default for OceanReflectionProjection {
+    fn default() -> Self {
+        let perspective = PerspectiveProjection::default();
+        Self {
+            fov: perspective.fov,
+            near: perspective.near,
+            far: perspective.far,
+            aspect_ratio: perspective.aspect_ratio,
+            clip_plane: Vec4::ZERO,
+        }
+    }
+}
+
+impl CameraProjection for OceanReflectionProjection {
+    fn get_projection_matrix(&self) -> Mat4 {
+        let mut projection = Mat4::perspective_infinite_reverse_rh(self.fov, self.aspect_ratio, self.near);
+        if self.clip_plane == Vec4::ZERO {
+            return projection;
+        }
+
+        // Lengyel's oblique near plane, worked out for reverse z: the near condition z <= w turns into the clip
+        // plane, scaled so that points at infinity along any view ray still land at a depth of at least zero
+        let tan_y = (self.fov * 0.5).tan();
+        let tan_x = tan_y * self.aspect_ratio;
+        let max_dot = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .into_iter()
+            .map(|(x, y)| self.clip_plane.dot(Vec4::new(x * tan_x, y * tan_y, -1.0, 0.0)))
+            .fold(f32::MIN, f32::max);
+        if max_dot <= 0.0 {
+            return projection;
+        }
+
+        let z_row = Vec4::new(0.0, 0.0, -1.0, 0.0) - self.clip_plane / max_dot;
+        projection.x_axis.z = z_row.x;
+        projection.y_axis.z = z_row.y;
+        projection.z_axis.z = z_row.z;
+        projection.w_axis.z = z_row.w;
+        projection
+    }
+
+    fn update(&mut self, width: f32, height: f32) {
+        self.aspect_ratio = width / height;
+    }
+
+    fn far(&self) -> f32 {
+        self.far
+    }
+}
+
+
+pub fn reflection_image(usage: TextureUsages) -> Image {
+    let mut image = Image::default();
+    image.texture_descriptor.usage = usage;
+    image
+}
+
+pub fn setup_reflection_images(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    commands.insert_resource(OceanReflectionImages {
+        scene_color: images.add(reflection_image(TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST)),
+        planar: images.add(reflection_image(TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_DST)),
+        screen_space: false,
+    });
+}
+
+pub fn fit_image(images: &mut Assets<Image>, handle: &Handle<Image>, size: UVec2, format: TextureFormat) {
+    let Some(image) = images.get(handle) else { return };
+    let descriptor = &image.texture_descriptor;
+    if descriptor.size.width == size.x && descriptor.size.height == size.y && descriptor.format == format {
+        return;
+    }
+
+    let image = images.get_mut(handle).unwrap();
+    image.texture_descriptor.format = format;
+    image.resize(Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: 1,
+    });
+}
+
+/// Keeps the reflection textures matched to the main camera's target
+pub fn resize_reflection_images(
+    cameras: Query<&Camera, MainCamera>,
+    reflection_images: Res<OceanReflectionImages>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let Some(camera) = cameras.iter().next() else { return };
+    let Some(size) = camera.physical_target_size() else { return };
+
+    // Copied straight from the main texture, so it has to match its format
+    let format = if camera.hdr { ViewTarget::TEXTURE_FORMAT_HDR } else { TextureFormat::bevy_default() };
+    fit_image(&mut images, &reflection_images.scene_color, size, format);
+
+    let planar_size = (size.as_vec2() * PLANAR_REFLECTION_SCALE).as_uvec2().max(UVec2::ONE);
+    fit_image(&mut images, &reflection_images.planar, planar_size, TextureFormat::bevy_default());
+}
+
+/// Mirrors the main camera about the mean water level of the first ocean with a planar reflection, spawning the
+/// reflection camera and moving the ocean onto [`OCEAN_RENDER_LAYER`] the first time one is needed
+pub fn update_reflection_camera(
+    mut commands: Commands,
+    oceans: Query<ReflectedOcean, Without<OceanReflectionCamera>>,
+    materials: Res<Assets<OceanMaterial>>,
+    cameras: Query<(Entity, Option<&RenderLayers>), OtherCamera>,
+    main_cameras: Query<(&GlobalTransform, Option<&Projection>), MainCamera>,
+    mut reflection_cameras: Query<ReflectionCameraState, With<OceanReflectionCamera>>,
+    reflection_images: Res<OceanReflectionImages>,
+) {
+    let planar_ocean = oceans.iter().find(|(_, handle, ..)| {
+        materials.get(handle).is_some_and(|mat| mat.reflection_mode.planar())
+    });
+    let main_camera = main_cameras.iter().next();
+
+    let (Some((_, _, ocean_transform, _)), Some((main_transform, main_projection))) = (planar_ocean, main_camera) else {
+        for (mut camera, ..) in reflection_cameras.iter_mut() {
+            camera.is_active = false;
+        }
+        return;
+    };
+
+    // Every other camera keeps seeing the ocean
+    for (entity, layers) in cameras.iter() {
+        let layers = layers.copied().unwrap_or_default();
+        if !layers.intersects(&RenderLayers::layer(OCEAN_RENDER_LAYER)) {
+            commands.entity(entity).insert(layers.with(OCEAN_RENDER_LAYER));
+        }
+    }
+
+    let Ok((mut camera, mut transform, mut global_transform, mut projection)) = reflection_cameras.get_single_mut() else {
+        commands.spawn((
+            Camera {
+                // Rendered before the main camera samples it
+                order: -1,
+                target: RenderTarget::Image(reflection_images.planar.clone()),
+                ..default()
+            },
+            CameraRenderGraph::new(core_3d::graph::NAME),
+            Camera3d {
+                clear_color: ClearColorConfig::Custom(Color::NONE),
+                ..default()
+            },
+            OceanReflectionProjection::default(),
+            VisibleEntities::default(),
+            Frustum::default(),
+            Transform::default(),
+            GlobalTransform::default(),
+            Tonemapping::None,
+            DebandDither::Disabled,
+            ColorGrading::default(),
+            RenderLayers::layer(0),
+            OceanReflectionCamera,
+        ));
+
+        for (entity, _, _, layers) in oceans.iter() {
+            if layers.is_none_or(|layers| layers.intersects(&RenderLayers::layer(0))) {
+                commands.entity(entity).insert(RenderLayers::layer(OCEAN_RENDER_LAYER));
+            }
+        }
+        return;
+    };
+
+    let water_level = ocean_transform.translation().y;
+    let main_translation = main_transform.translation();
+
+    // From below the surface there's nothing to mirror
+    camera.is_active = main_translation.y > water_level;
+    if !camera.is_active {
+        return;
+    }
+
+    let mirror = Vec3::new(1.0, -1.0, 1.0);
+    let translation = Vec3::new(main_translation.x, 2.0 * water_level - main_translation.y, main_translation.z);
+    // Mirroring the whole basis would flip its handedness, the reflected up keeps it a rotation instead. The ocean
+    // looks the texture up by projecting through this camera, so the image being flipped doesn't matter
+    *transform = Transform::from_translation(translation)
+        .looking_to(main_transform.forward() * mirror, main_transform.up() * mirror);
+    *global_transform = GlobalTransform::from(*transform);
+
+    let perspective = match main_projection {
+        Some(Projection::Perspective(perspective)) => perspective.clone(),
+        _ => PerspectiveProjection::default(),
+    };
+    projection.fov = perspective.fov;
+    projection.near = perspective.near;
+    projection.far = perspective.far;
+
+    // The water plane, facing up, moved into view space
+    let world_plane = Vec4::new(0.0, 1.0, 0.0, -water_level);
+    projection.clip_plane = transform.compute_matrix().transpose() * world_plane;
+}
+
+fn view_projection(camera: &Camera, transform: &GlobalTransform) -> Mat4 {
+    camera.projection_matrix() * transform.compute_matrix().inverse()
+}
+
+pub fn prepare_ocean_reflections(
+    handles: Query<&Handle<OceanMaterial>>,
+    mut materials: ResMut<Assets<OceanMaterial>>,
+    main_cameras: Query<(&Camera, &GlobalTransform), MainCamera>,
+    reflection_cameras: Query<(&Camera, &GlobalTransform), With<OceanReflectionCamera>>,
+    mut reflection_images: ResMut<OceanReflectionImages>,
+    mut prev_view_proj: Local<Option<Mat4>>,
+) {
+    let view_proj = main_cameras.iter().next().map(|(camera, transform)| view_projection(camera, transform));
+    let planar_view_proj = reflection_cameras.iter()
+        .find(|(camera, _)| camera.is_active)
+        .map_or(Mat4::ZERO, |(camera, transform)| view_projection(camera, transform));
+
+    reflection_images.screen_space = false;
+    for handle in handles.iter() {
+        let Some(mat) = materials.get_mut(handle) else { continue };
+
+        if mat.reflection_mode.screen_space() {
+            mat.scene_color.get_or_insert_with(|| reflection_images.scene_color.clone());
+            reflection_images.screen_space = true;
+        }
+        if mat.reflection_mode.planar() {
+            mat.planar_reflection.get_or_insert_with(|| reflection_images.planar.clone());
+        }
+
+        // The scene color is copied at the end of the frame, so it's read with the matrix it was rendered with
+        mat.settings.prev_view_proj = prev_view_proj.or(view_proj).unwrap_or(Mat4::IDENTITY);
+        mat.settings.planar_view_proj = planar_view_proj;
+    }
+
+    *prev_view_proj = view_proj;
+}
+
+
+/// Copies the main camera's color into [`OceanReflectionImages::scene_color`] once the scene is drawn
+#[derive(Default)]
+pub struct OceanSceneColorNode;
+
+impl OceanSceneColorNode {
+    pub const NAME: &str = "ocean_scene_color";
+}
+
+impl ViewNode for OceanSceneColorNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static SkyPostProcessSettings,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, _): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let reflection_images = world.resource::<OceanReflectionImages>();
+        if !reflection_images.screen_space {
+            return Ok(());
+        }
+
+        let Some(scene_color) = world.resource::<RenderAssets<Image>>().get(&reflection_images.scene_color) else {
+            return Ok(());
+        };
+
+        // Catches up a frame after the target is resized
+        let source = view_target.main_texture();
+        if scene_color.texture_format != view_target.main_texture_format()
+            || scene_color.size != Vec2::new(source.width() as f32, source.height() as f32)
+        {
+            return Ok(());
+        }
+
+        render_context.command_encoder().copy_texture_to_texture(
+            source.as_image_copy(),
+            scene_color.texture.as_image_copy(),
+            source.size(),
+        );
+
+        Ok(())
+    }
+}
+
+
+pub struct OceanReflectionPlugin;
+
+impl Plugin for OceanReflectionPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins((
+                CameraProjectionPlugin::<OceanReflectionProjection>::default(),
+                ExtractResourcePlugin::<OceanReflectionImages>::default(),
+            ))
+            .add_systems(Startup, setup_reflection_images)
+            .add_systems(Update, resize_reflection_images)
+            .add_systems(PostUpdate, (
+                update_reflection_camera
+                    .after(TransformSystem::TransformPropagate)
+                    .before(CameraUpdateSystem),
+                update_frusta::<OceanReflectionProjection>
+                    .in_set(VisibilitySystems::UpdateProjectionFrusta)
+                    .after(camera_system::<OceanReflectionProjection>)
+                    .after(TransformSystem::TransformPropagate),
+                prepare_ocean_reflections.after(CameraUpdateSystem),
+            ));
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<OceanSceneColorNode>>(
+                core_3d::graph::NAME,
+                OceanSceneColorNode::NAME,
+            )
+            .add_render_graph_edges(
+                core_3d::graph::NAME,
+                &[
+                    core_3d::graph::node::MAIN_TRANSPARENT_PASS,
+                    OceanSceneColorNode::NAME,
+                    core_3d::graph::node::END_MAIN_PASS,
+                ],
+            );
+    }
+}
@@ -0,0 +1,146 @@
+use bevy::{
+    prelude::*,
+    core_pipeline::core_3d,
+    ecs::query::QueryItem,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{NodeRunError, RenderGraphApp, RenderGraphContext, ViewNode, ViewNodeRunner},
+        render_resource::{TextureFormat, TextureUsages},
+        renderer::RenderContext,
+        texture::BevyDefault,
+        view::ViewTarget,
+        RenderApp,
+    },
+};
+
+use crate::{ocean::OceanMaterial, reflection::{fit_image, reflection_image, MainCamera}, sky::{SkyPassPostProcessNode, SkyPostProcessSettings}};
+
+
+/// Copy of the main camera's color once opaque geometry and the sky are drawn, what refracting oceans see under
+/// their surface
+#[derive(Resource, ExtractResource, Clone)]
+pub struct OceanRefractionImages {
+    pub opaque_color: Handle<Image>,
+    /// Whether any ocean refracts, the opaque color is only copied then
+    pub active: bool,
+}
+
+pub fn setup_refraction_images(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    commands.insert_resource(OceanRefractionImages {
+        opaque_color: images.add(reflection_image(TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST)),
+        active: false,
+    });
+}
+
+/// Keeps the opaque color matched to the main camera's target
+pub fn resize_refraction_images(
+    cameras: Query<&Camera, MainCamera>,
+    refraction_images: Res<OceanRefractionImages>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let Some(camera) = cameras.iter().next() else { return };
+    let Some(size) = camera.physical_target_size() else { return };
+
+    let format = if camera.hdr { ViewTarget::TEXTURE_FORMAT_HDR } else { TextureFormat::bevy_default() };
+    fit_image(&mut images, &refraction_images.opaque_color, size, format);
+}
+
+pub fn prepare_ocean_refraction(
+    handles: Query<&Handle<OceanMaterial>>,
+    mut materials: ResMut<Assets<OceanMaterial>>,
+    mut refraction_images: ResMut<OceanRefractionImages>,
+) {
+    refraction_images.active = false;
+    for handle in handles.iter() {
+        let Some(mat) = materials.get_mut(handle) else { continue };
+
+        if mat.refraction {
+            mat.opaque_color.get_or_insert_with(|| refraction_images.opaque_color.clone());
+            refraction_images.active = true;
+        }
+    }
+}
+
+
+/// Copies the main camera's color into [`OceanRefractionImages::opaque_color`] between the sky pass and the
+/// transparent pass refracting oceans are drawn in
+#[derive(Default)]
+pub struct OceanOpaqueColorNode;
+
+impl OceanOpaqueColorNode {
+    pub const NAME: &str = "ocean_opaque_color";
+}
+
+impl ViewNode for OceanOpaqueColorNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static SkyPostProcessSettings,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, _): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let refraction_images = world.resource::<OceanRefractionImages>();
+        if !refraction_images.active {
+            return Ok(());
+        }
+
+        let Some(opaque_color) = world.resource::<RenderAssets<Image>>().get(&refraction_images.opaque_color) else {
+            return Ok(());
+        };
+
+        // Catches up a frame after the target is resized
+        let source = view_target.main_texture();
+        if opaque_color.texture_format != view_target.main_texture_format()
+            || opaque_color.size != Vec2::new(source.width() as f32, source.height() as f32)
+        {
+            return Ok(());
+        }
+
+        render_context.command_encoder().copy_texture_to_texture(
+            source.as_image_copy(),
+            opaque_color.texture.as_image_copy(),
+            source.size(),
+        );
+
+        Ok(())
+    }
+}
+
+
+pub struct OceanRefractionPlugin;
+
+impl Plugin for OceanRefractionPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins(ExtractResourcePlugin::<OceanRefractionImages>::default())
+            .add_systems(Startup, setup_refraction_images)
+            .add_systems(Update, (resize_refraction_images, prepare_ocean_refraction));
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<OceanOpaqueColorNode>>(
+                core_3d::graph::NAME,
+                OceanOpaqueColorNode::NAME,
+            )
+            .add_render_graph_edges(
+                core_3d::graph::NAME,
+                &[
+                    SkyPassPostProcessNode::NAME,
+                    OceanOpaqueColorNode::NAME,
+                    core_3d::graph::node::MAIN_TRANSPARENT_PASS,
+                ],
+            );
+    }
+}
@@ -0,0 +1,516 @@
+use bevy::{
+    prelude::*,
+    ecs::system::SystemParam,
+    math::Vec3Swizzles,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingResource, BindingType, BufferBindingType, CachedComputePipelineId, ComputePassDescriptor,
+            ComputePipelineDescriptor, Extent3d, FilterMode, PipelineCache, SamplerDescriptor, ShaderStages, ShaderType,
+            StorageTextureAccess, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDimension,
+            UniformBuffer,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{GpuImage, ImageSampler},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{ocean::OceanMaterial, query::OceanQuery, compute::WORKGROUP_SIZE};
+
+
+pub const RIPPLE_TEXTURE_SIZE: u32 = 256;
+pub const MAX_RIPPLE_DISTURBANCES: usize = 32;
+// Courant number above which the explicit wave equation blows up in 2d
+const MAX_COURANT_NUMBER: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+
+/// Local wave equation simulation on top of the FFT ocean, so objects can disturb the water.
+///
+/// Covers a `world_size` square around the [`OceanRippleFocus`] entity, or the first 3d camera without one.
+#[derive(Resource, ExtractResource, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct OceanRippleSettings {
+    pub enabled: bool,
+    pub world_size: f32,
+
+    /// Speed ripples travel at, in m/s. Limited to the fastest the simulation stays stable at, one texel of
+    /// `world_size` per pass of `rate` over the square root of two
+    pub wave_speed: f32,
+    /// Fraction of the ripples' energy lost per second
+    pub damping: f32,
+
+    pub foam_add: f32,
+    pub foam_decay_rate: f32,
+
+    /// Simulation passes per second
+    pub rate: f32,
+    /// Most passes run in a single frame, the simulation slows down rather than spiral after long frames
+    pub max_passes: u32,
+}
+
+impl Default for OceanRippleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            world_size: 64.0,
+
+            wave_speed: 2.0,
+            damping: 0.5,
+
+            foam_add: 0.5,
+            foam_decay_rate: 0.5,
+
+            rate: 120.0,
+            max_passes: 8,
+        }
+    }
+}
+
+/// The ripple simulation follows this entity
+#[derive(Component, Default)]
+pub struct OceanRippleFocus;
+
+/// Makes ripples in proportion to how fast the entity moves through the surface
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct RippleSource {
+    pub radius: f32,
+    pub strength: f32,
+
+    prev_position: Option<Vec3>,
+}
+
+impl RippleSource {
+    pub fn new(radius: f32, strength: f32) -> Self {
+        Self {
+            radius,
+            strength,
+            prev_position: None,
+        }
+    }
+}
+
+impl Default for RippleSource {
+    fn default() -> Self {
+        Self::new(1.0, 0.5)
+    }
+}
+
+/// Pushes the water down around `position`, `strength` is the downwards velocity given to the center in m/s
+#[derive(Event, Debug, Clone, Copy)]
+pub struct OceanSplash {
+    pub position: Vec3,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+
+#[derive(Debug, Clone, Copy, Default, ShaderType)]
+pub struct RippleDisturbance {
+    // World space xz, relative to the simulation origin once sent to the shader
+    pub position: Vec2,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+/// What the ripple simulation does this frame, disturbances and movement are held until a frame runs any passes
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct OceanRippleFrame {
+    /// World space xz of the simulated area's corner, only moved in frames that run passes so it always matches
+    /// the texture
+    pub origin: Vec2,
+    /// Texels the simulated area moves by in this frame's first pass
+    pub shift: IVec2,
+    /// Always even, so the result ends up in the state texture
+    pub passes: u32,
+    pub pass_delta: f32,
+    pub disturbances: Vec<RippleDisturbance>,
+
+    accumulator: f32,
+}
+
+/// Height in r, vertical velocity in g and foam in b
+#[derive(Resource, ExtractResource, Clone)]
+pub struct OceanRippleTextures {
+    pub state: Handle<Image>,
+    pub scratch: Handle<Image>,
+}
+
+
+pub fn setup_ripple_textures(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: RIPPLE_TEXTURE_SIZE,
+            height: RIPPLE_TEXTURE_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 16],
+        TextureFormat::Rgba32Float,
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+
+    commands.insert_resource(OceanRippleTextures {
+        state: images.add(image.clone()),
+        scratch: images.add(image),
+    });
+}
+
+/// Resources `update_ripple_frame` reads and writes every frame.
+#[derive(SystemParam)]
+pub struct RippleFrameResources<'w> {
+    frame: ResMut<'w, OceanRippleFrame>,
+    settings: Res<'w, OceanRippleSettings>,
+    time: Res<'w, Time>,
+}
+
+pub fn update_ripple_frame(
+    resources: RippleFrameResources,
+    focus: Query<&GlobalTransform, With<OceanRippleFocus>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    mut sources: Query<(&mut RippleSource, &GlobalTransform)>,
+    mut splashes: EventReader<OceanSplash>,
+    ocean: OceanQuery,
+) {
+    let RippleFrameResources { mut frame, settings, time } = resources;
+
+    // Last frame's passes consumed everything gathered so far
+    if frame.passes > 0 {
+        frame.disturbances.clear();
+    }
+    frame.passes = 0;
+    frame.shift = IVec2::ZERO;
+
+    if !settings.enabled {
+        splashes.clear();
+        return;
+    }
+
+    let Some(focus) = focus.iter().next().or_else(|| cameras.iter().next()) else { return };
+
+    // Snap to whole texels so the simulation can be moved without resampling it
+    let texel_size = settings.world_size / RIPPLE_TEXTURE_SIZE as f32;
+    let origin = ((focus.translation().xz() - settings.world_size * 0.5) / texel_size).floor() * texel_size;
+
+    let delta = time.delta_seconds();
+    for (mut source, transform) in sources.iter_mut() {
+        let position = transform.translation();
+        let prev_position = source.prev_position.replace(position);
+        let (Some(prev_position), Some(height)) = (prev_position, ocean.height_at(position.xz())) else { continue };
+
+        if (position.y - height).abs() > source.radius || delta <= 0.0 {
+            continue;
+        }
+
+        let speed = (position - prev_position).length() / delta;
+        frame.disturbances.push(RippleDisturbance {
+            position: position.xz(),
+            radius: source.radius,
+            strength: source.strength * speed * delta,
+        });
+    }
+
+    for splash in splashes.iter() {
+        frame.disturbances.push(RippleDisturbance {
+            position: splash.position.xz(),
+            radius: splash.radius,
+            strength: splash.strength,
+        });
+    }
+
+    // Keep the strongest disturbances if there are more than the shader takes
+    if frame.disturbances.len() > MAX_RIPPLE_DISTURBANCES {
+        frame.disturbances.sort_by(|a, b| b.strength.abs().total_cmp(&a.strength.abs()));
+        frame.disturbances.truncate(MAX_RIPPLE_DISTURBANCES);
+    }
+
+    let pass_delta = 1.0 / settings.rate.max(1.0);
+    frame.accumulator += delta;
+    let pairs = (frame.accumulator / (pass_delta * 2.0)).floor();
+    frame.accumulator -= pairs * pass_delta * 2.0;
+    frame.passes = (pairs as u32 * 2).min(settings.max_passes & !1);
+    frame.pass_delta = pass_delta;
+
+    // The texture only moves along with the passes, the material keeps drawing it where it is until then
+    if frame.passes > 0 {
+        frame.shift = ((origin - frame.origin) / texel_size).round().as_ivec2();
+        frame.origin = origin;
+    }
+}
+
+pub fn prepare_ocean_ripples(
+    handles: Query<&Handle<OceanMaterial>>,
+    mut materials: ResMut<Assets<OceanMaterial>>,
+    settings: Res<OceanRippleSettings>,
+    frame: Res<OceanRippleFrame>,
+    textures: Res<OceanRippleTextures>,
+) {
+    for handle in handles.iter() {
+        let Some(mat) = materials.get_mut(handle) else { continue };
+
+        if mat.ripples.is_none() {
+            mat.ripples = Some(textures.state.clone());
+        }
+
+        mat.settings.ripple_origin = frame.origin;
+        mat.settings.ripple_size = if settings.enabled { settings.world_size } else { 0.0 };
+    }
+}
+
+
+#[derive(Clone, Default, ShaderType)]
+pub struct RippleUniform {
+    disturbances: [RippleDisturbance; MAX_RIPPLE_DISTURBANCES],
+    shift: IVec2,
+    texel_size: f32,
+    delta_time: f32,
+    wave_speed: f32,
+    damping: f32,
+    foam_add: f32,
+    foam_decay_rate: f32,
+    disturbance_count: u32,
+}
+
+/// The first pass of a frame moves the simulation and applies the disturbances, the others only simulate
+#[derive(Resource, Default)]
+pub struct RippleUniforms {
+    first: UniformBuffer<RippleUniform>,
+    rest: UniformBuffer<RippleUniform>,
+}
+
+pub fn prepare_ripple_uniforms(
+    mut uniforms: ResMut<RippleUniforms>,
+    settings: Res<OceanRippleSettings>,
+    frame: Res<OceanRippleFrame>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let texel_size = settings.world_size / RIPPLE_TEXTURE_SIZE as f32;
+    let max_wave_speed = MAX_COURANT_NUMBER * texel_size / frame.pass_delta.max(f32::EPSILON);
+
+    let mut rest = RippleUniform {
+        texel_size,
+        delta_time: frame.pass_delta,
+        wave_speed: settings.wave_speed.min(max_wave_speed),
+        damping: settings.damping,
+        foam_add: settings.foam_add,
+        foam_decay_rate: settings.foam_decay_rate,
+        ..default()
+    };
+
+    let mut first = rest.clone();
+    first.shift = frame.shift;
+    first.disturbance_count = frame.disturbances.len().min(MAX_RIPPLE_DISTURBANCES) as u32;
+    for (target, disturbance) in first.disturbances.iter_mut().zip(frame.disturbances.iter()) {
+        *target = RippleDisturbance {
+            position: disturbance.position - frame.origin,
+            ..*disturbance
+        };
+    }
+    rest.shift = IVec2::ZERO;
+
+    uniforms.first.set(first);
+    uniforms.rest.set(rest);
+    uniforms.first.write_buffer(&render_device, &render_queue);
+    uniforms.rest.write_buffer(&render_device, &render_queue);
+}
+
+
+#[derive(Resource)]
+pub struct RippleComputePipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for RippleComputePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ocean_ripple_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(RippleUniform::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/ripples.wgsl");
+
+        let pipeline = world.resource::<PipelineCache>().queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("ocean_ripple_pipeline".into()),
+            layout: vec![layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader,
+            shader_defs: Vec::new(),
+            entry_point: "simulate_ripples".into(),
+        });
+
+        RippleComputePipeline {
+            layout,
+            pipeline,
+        }
+    }
+}
+
+
+#[derive(Default)]
+pub struct RippleComputeNode;
+
+impl RippleComputeNode {
+    pub const NAME: &'static str = "ocean_ripple_node";
+}
+
+impl render_graph::Node for RippleComputeNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let frame = world.resource::<OceanRippleFrame>();
+        if frame.passes == 0 {
+            return Ok(());
+        }
+
+        let ripple_pipeline = world.resource::<RippleComputePipeline>();
+        let Some(pipeline) = world.resource::<PipelineCache>().get_compute_pipeline(ripple_pipeline.pipeline) else {
+            return Ok(());
+        };
+
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+        let textures = world.resource::<OceanRippleTextures>();
+        let (Some(state), Some(scratch)) = (gpu_images.get(&textures.state), gpu_images.get(&textures.scratch)) else {
+            return Ok(());
+        };
+
+        let uniforms = world.resource::<RippleUniforms>();
+        let render_device = render_context.render_device().clone();
+        let create_bind_group = |uniform: &UniformBuffer<RippleUniform>, input: &GpuImage, output: &GpuImage| {
+            render_device.create_bind_group(&BindGroupDescriptor {
+                label: Some("ocean_ripple_bind_group"),
+                layout: &ripple_pipeline.layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: uniform.binding().unwrap(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&input.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&output.texture_view),
+                    },
+                ],
+            })
+        };
+
+        let bind_groups = [
+            create_bind_group(&uniforms.first, state, scratch),
+            create_bind_group(&uniforms.rest, scratch, state),
+            create_bind_group(&uniforms.rest, state, scratch),
+        ];
+
+        let encoder = render_context.command_encoder();
+        for pass_index in 0..frame.passes as usize {
+            let bind_group = match pass_index {
+                0 => &bind_groups[0],
+                i if i % 2 == 1 => &bind_groups[1],
+                _ => &bind_groups[2],
+            };
+
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(RIPPLE_TEXTURE_SIZE / WORKGROUP_SIZE, RIPPLE_TEXTURE_SIZE / WORKGROUP_SIZE, 1);
+        }
+
+        Ok(())
+    }
+}
+
+
+pub struct OceanRipplePlugin;
+
+impl Plugin for OceanRipplePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<OceanRippleSettings>()
+            .init_resource::<OceanRippleFrame>()
+            .register_type::<OceanRippleSettings>()
+            .register_type::<RippleSource>()
+            .add_event::<OceanSplash>()
+            .add_systems(Startup, setup_ripple_textures)
+            .add_systems(Update, (update_ripple_frame, prepare_ocean_ripples).chain())
+            .add_plugins((
+                ExtractResourcePlugin::<OceanRippleSettings>::default(),
+                ExtractResourcePlugin::<OceanRippleFrame>::default(),
+                ExtractResourcePlugin::<OceanRippleTextures>::default(),
+            ));
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<RippleUniforms>()
+            .add_systems(Render, prepare_ripple_uniforms.in_set(RenderSet::Prepare));
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(RippleComputeNode::NAME, RippleComputeNode);
+        render_graph.add_node_edges(&[
+            RippleComputeNode::NAME,
+            bevy::render::main_graph::node::CAMERA_DRIVER,
+        ]);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app.init_resource::<RippleComputePipeline>();
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::{prelude::*, render::{render_resource::{PrimitiveTopology, TextureViewDescriptor, TextureViewDimension}, mesh::Indices}, core_pipeline::{clear_color::ClearColorConfig, Skybox, prepass::DepthPrepass}, asset::LoadState};
+use bevy::{prelude::*, render::{render_resource::{PrimitiveTopology, TextureViewDescriptor, TextureViewDimension}, mesh::Indices}, core_pipeline::{clear_color::ClearColorConfig, Skybox, prepass::DepthPrepass}, asset::LoadState, pbr::NotShadowCaster};
 use bevy_panorbit_camera::PanOrbitCamera;
 
 use crate::{ocean::OceanMaterial, sky::{SkyPostProcessSettings, SkyboxCubemap}};
@@ -34,12 +34,14 @@ pub fn setup_scene(
 
     commands.spawn((
         MaterialMeshBundle {
-            mesh: meshes.add(create_ocean_plane()),
+            mesh: meshes.add(create_ocean_plane(PLANE_RES)),
             material: materials.add(OceanMaterial::default()),
             // material: materials.add(OceanMaterial::default()),
             transform: Transform::from_xyz(0.0, 0.0, 0.0),
             ..default()
         },
+        // Its own displaced shadow would only add acne to what the scatter terms already shade
+        NotShadowCaster,
         // DynamicDetail {
 
         // },
@@ -47,8 +49,9 @@ pub fn setup_scene(
 
     commands.spawn(DirectionalLightBundle {
         directional_light: DirectionalLight {
-            color: Color::WHITE,
-            illuminance: 20_000.0,
+            color: Color::rgb_linear(1.0, 0.9, 0.6),
+            illuminance: 40_000.0,
+            shadows_enabled: true,
             ..default()
         },
         transform: Transform::from_rotation(Quat::from_euler(EulerRot::YXZ, 0.0, -0.1, 0.0)),
@@ -83,9 +86,9 @@ pub fn skybox_loaded(
     }
 }
 
-pub fn create_ocean_plane() -> Mesh {
+pub fn create_ocean_plane(res: usize) -> Mesh {
     let half_length = PLANE_LENGTH * 0.5;
-    let side_vert_count = PLANE_LENGTH as usize * PLANE_RES;
+    let side_vert_count = PLANE_LENGTH as usize * res;
 
     let vertex_count = (side_vert_count + 1) * (side_vert_count + 1);
     let mut positions = vec![Vec3::ZERO; vertex_count];
@@ -0,0 +1,736 @@
+use std::{f32::consts::{PI, TAU}, fmt, path::Path, sync::{Arc, Mutex}};
+
+use bevy::{prelude::*, math::{Affine3A, Vec3Swizzles}, tasks::AsyncComputeTaskPool};
+
+use crate::{
+    compute::{
+        clock::OceanSimulationClock,
+        spectrums::{OceanSpectrumSettings, OceanSpectrumsArray, update_spectrums_array},
+        supported_fft_size,
+        uniforms::OceanComputeSettings,
+    },
+    math::hash,
+    ocean::OceanMaterial,
+    query::LAYER_OFFSETS,
+    scene::PLANE_LENGTH,
+};
+
+
+// Strongest wave components kept for synthesizing motion, out of every texel of every cascade
+pub const MAX_WAVE_COMPONENTS: usize = 2048;
+
+
+#[derive(Debug)]
+pub enum RaoError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    MissingEntry { frequency: f32, heading: f32 },
+    Empty,
+}
+
+impl fmt::Display for RaoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaoError::Io(error) => write!(f, "failed to read RAO table: {error}"),
+            RaoError::Parse { line, message } => write!(f, "RAO table line {line}: {message}"),
+            RaoError::MissingEntry { frequency, heading } => write!(f, "RAO table has no entry for frequency {frequency} rad/s and heading {heading}°"),
+            RaoError::Empty => write!(f, "RAO table is empty"),
+        }
+    }
+}
+
+impl std::error::Error for RaoError {}
+
+impl From<std::io::Error> for RaoError {
+    fn from(error: std::io::Error) -> Self {
+        RaoError::Io(error)
+    }
+}
+
+
+/// Heave, pitch and roll of a vessel.
+///
+/// Heave is in meters, positive up. Pitch and roll are in radians, positive bow up and starboard down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Reflect)]
+pub struct ShipMotionSample {
+    pub heave: f32,
+    pub pitch: f32,
+    pub roll: f32,
+}
+
+impl ShipMotionSample {
+    fn from_array([heave, pitch, roll]: [f32; 3]) -> Self {
+        Self { heave, pitch, roll }
+    }
+}
+
+/// Short term statistics of each motion in the current sea state, from the moments of the response spectrum
+#[derive(Debug, Clone, Copy, Default, PartialEq, Reflect)]
+pub struct ShipMotionStatistics {
+    pub rms: ShipMotionSample,
+    /// Mean of the highest third of the single amplitudes, two standard deviations
+    pub significant_amplitude: ShipMotionSample,
+    /// Mean zero up-crossing period at the encounter frequency, in seconds
+    pub zero_crossing_period: ShipMotionSample,
+}
+
+
+/// Response amplitude operators of a vessel for heave, pitch and roll, tabulated over wave frequency and heading.
+///
+/// Headings are the direction the waves travel in relative to the bow: 0° for following seas, 90° for waves
+/// travelling from starboard to port and 180° for head seas. Tables only covering 0° to 180° are mirrored for the
+/// other side, with roll reversed.
+#[derive(Debug, Clone)]
+pub struct ResponseAmplitudeOperators {
+    /// In rad/s, ascending
+    pub frequencies: Vec<f32>,
+    /// In degrees, ascending
+    pub headings: Vec<f32>,
+    // Complex response per meter of wave amplitude, heave in m and pitch and roll in rad, indexed by
+    // frequency then heading
+    responses: Vec<[Vec2; 3]>,
+}
+
+impl ResponseAmplitudeOperators {
+    /// Reads a table with one row per frequency and heading:
+    ///
+    /// `frequency, heading, heave amplitude, heave phase, pitch amplitude, pitch phase, roll amplitude, roll phase`
+    ///
+    /// Frequencies are in rad/s, headings and phases in degrees, heave in m/m and pitch and roll in deg/m.
+    /// Phases are leads relative to the wave elevation at the origin. Blank lines and `#` comments are skipped, and
+    /// so is the first remaining line if it isn't numeric, as a header.
+    pub fn from_csv(csv: &str) -> Result<Self, RaoError> {
+        let mut rows = Vec::new();
+        let mut first = true;
+
+        for (index, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let header_allowed = std::mem::replace(&mut first, false);
+
+            let fields = line.split(',').map(|field| field.trim().parse::<f32>()).collect::<Result<Vec<_>, _>>();
+            let fields = match fields {
+                Ok(fields) => fields,
+                Err(_) if header_allowed => continue,
+                Err(error) => return Err(RaoError::Parse { line: index + 1, message: error.to_string() }),
+            };
+
+            let &[frequency, heading, heave, heave_phase, pitch, pitch_phase, roll, roll_phase] = fields.as_slice() else {
+                return Err(RaoError::Parse { line: index + 1, message: format!("expected 8 columns, found {}", fields.len()) });
+            };
+
+            let response = |amplitude: f32, phase: f32| Vec2::from_angle(phase.to_radians()) * amplitude;
+            rows.push((frequency, heading, [
+                response(heave, heave_phase),
+                response(pitch.to_radians(), pitch_phase),
+                response(roll.to_radians(), roll_phase),
+            ]));
+        }
+
+        if rows.is_empty() {
+            return Err(RaoError::Empty);
+        }
+
+        let mut frequencies: Vec<f32> = rows.iter().map(|row| row.0).collect();
+        let mut headings: Vec<f32> = rows.iter().map(|row| row.1).collect();
+        for values in [&mut frequencies, &mut headings] {
+            values.sort_by(f32::total_cmp);
+            values.dedup();
+        }
+
+        let mut responses = vec![None; frequencies.len() * headings.len()];
+        for (frequency, heading, response) in rows {
+            let f = frequencies.partition_point(|value| *value < frequency);
+            let h = headings.partition_point(|value| *value < heading);
+            responses[f * headings.len() + h] = Some(response);
+        }
+
+        let responses = responses
+            .into_iter()
+            .enumerate()
+            .map(|(i, response)| response.ok_or(RaoError::MissingEntry {
+                frequency: frequencies[i / headings.len()],
+                heading: headings[i % headings.len()],
+            }))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            frequencies,
+            headings,
+            responses,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RaoError> {
+        Self::from_csv(&std::fs::read_to_string(path)?)
+    }
+
+    /// Complex heave, pitch and roll response to a wave of unit amplitude, interpolated bilinearly and clamped to
+    /// the edges of the table
+    pub fn response(&self, frequency: f32, heading: f32) -> [Vec2; 3] {
+        let mut heading = heading.rem_euclid(360.0);
+        let mut roll_sign = 1.0;
+        if heading > 180.0 && !self.headings.last().is_some_and(|last| *last > 180.0) {
+            heading = 360.0 - heading;
+            roll_sign = -1.0;
+        }
+
+        let (f0, f1, tf) = Self::bracket(&self.frequencies, frequency);
+        let (h0, h1, th) = Self::bracket(&self.headings, heading);
+        let at = |f: usize, h: usize| self.responses[f * self.headings.len() + h];
+
+        let mut response = [Vec2::ZERO; 3];
+        for (motion, value) in response.iter_mut().enumerate() {
+            let low = at(f0, h0)[motion].lerp(at(f0, h1)[motion], th);
+            let high = at(f1, h0)[motion].lerp(at(f1, h1)[motion], th);
+            *value = low.lerp(high, tf);
+        }
+        response[2] *= roll_sign;
+
+        response
+    }
+
+    fn bracket(values: &[f32], value: f32) -> (usize, usize, f32) {
+        if values.len() < 2 {
+            return (0, 0, 0.0);
+        }
+
+        let upper = values.partition_point(|v| *v < value).clamp(1, values.len() - 1);
+        let lower = upper - 1;
+        let t = ((value - values[lower]) / (values[upper] - values[lower])).clamp(0.0, 1.0);
+        (lower, upper, t)
+    }
+}
+
+
+/// One wave of the rendered ocean, elevation `amplitude * cos(wave_vector · xz + frequency * t + phase)` at
+/// `xz` in the ocean plane's local space, `t` being the simulation clock's elapsed time
+#[derive(Debug, Clone, Copy)]
+pub struct WaveComponent {
+    /// In rad/m, pointing against the direction the wave travels in
+    pub wave_vector: Vec2,
+    /// In rad/s
+    pub frequency: f32,
+    pub amplitude: f32,
+    pub phase: f32,
+}
+
+// Components and the share of the height variance they retain, handed back by the synthesis task
+type SynthesizedWaves = (Vec<WaveComponent>, f32);
+
+/// The strongest waves of the rendered ocean, recovered on the CPU by repeating the initial spectrum the compute
+/// shader generates, with the same random numbers.
+///
+/// That takes a pass over every texel of every cascade, so it runs on the async compute pool, one synthesis at a time.
+/// Settings changing while one is running are picked up once it lands, until then the previous components are kept
+#[derive(Resource, Default)]
+pub struct OceanWaveComponents {
+    pub components: Vec<WaveComponent>,
+    /// Fraction of the ocean's height variance carried by `components`
+    pub retained_variance: f32,
+    /// Local to world transform of the ocean plane
+    pub transform: Affine3A,
+
+    // Cascade tiling and contributions the components were last requested for
+    layers: Option<(Vec4, Vec4)>,
+    // Settings changed since the last synthesis was started
+    stale: bool,
+    in_flight: bool,
+    synthesized: Arc<Mutex<Option<SynthesizedWaves>>>,
+}
+
+impl OceanWaveComponents {
+    pub fn elevation(&self, world_xz: Vec2, time: f32) -> f32 {
+        let local_xz = self.transform.inverse().transform_point3(Vec3::new(world_xz.x, 0.0, world_xz.y)).xz();
+        self.components
+            .iter()
+            .map(|wave| wave.amplitude * (wave.wave_vector.dot(local_xz) + wave.frequency * time + wave.phase).cos())
+            .sum()
+    }
+}
+
+fn uniform_to_gauss(u1: f32, u2: f32) -> Vec2 {
+    let r = (-2.0 * u1.ln()).sqrt();
+    Vec2::from_angle(TAU * u2) * r
+}
+
+/// Spectral density the compute shader uses, see `initialize_spectrum` in `displacement.wgsl`
+struct SpectrumModel<'a> {
+    settings: &'a OceanComputeSettings,
+}
+
+impl<'a> SpectrumModel<'a> {
+    fn dispersion(&self, k_length: f32) -> f32 {
+        (self.settings.gravity * k_length * (k_length * self.settings.depth).min(20.0).tanh()).sqrt()
+    }
+
+    fn dispersion_derivative(&self, k_length: f32) -> f32 {
+        let th = (k_length * self.settings.depth).min(20.0).tanh();
+        let ch = (k_length * self.settings.depth).cosh();
+        self.settings.gravity * (self.settings.depth * k_length / ch / ch + th) / self.dispersion(k_length) / 2.0
+    }
+
+    fn normalization_factor(s: f32) -> f32 {
+        let (s2, s3, s4) = (s * s, s * s * s, s * s * s * s);
+        if s < 5.0 {
+            -0.000564 * s4 + 0.00776 * s3 - 0.044 * s2 + 0.192 * s + 0.163
+        } else {
+            -4.80e-08 * s4 + 1.07e-05 * s3 - 9.53e-04 * s2 + 5.90e-02 * s + 3.93e-01
+        }
+    }
+
+    fn spread_power(omega: f32, peak_omega: f32) -> f32 {
+        if omega > peak_omega {
+            9.77 * (omega / peak_omega).abs().powf(-2.5)
+        } else {
+            6.97 * (omega / peak_omega).abs().powf(5.0)
+        }
+    }
+
+    fn direction_spectrum(theta: f32, omega: f32, spectrum: &OceanSpectrumSettings) -> f32 {
+        let c_theta = theta.cos();
+        let s = Self::spread_power(omega, spectrum.peak_omega)
+            + 16.0 * (omega / spectrum.peak_omega).min(20.0).tanh() * spectrum.swell * spectrum.swell;
+        let cosine_2s = Self::normalization_factor(s) * (0.5 * (theta - spectrum.angle)).cos().abs().powf(2.0 * s);
+        (2.0 / PI * c_theta * c_theta) * (1.0 - spectrum.spread_blend) + cosine_2s * spectrum.spread_blend
+    }
+
+    fn tma_correction(&self, omega: f32) -> f32 {
+        let omega_h = omega * (self.settings.depth / self.settings.gravity).sqrt();
+        if omega_h <= 1.0 {
+            0.5 * omega_h * omega_h
+        } else if omega_h < 2.0 {
+            1.0 - 0.5 * (2.0 - omega_h) * (2.0 - omega_h)
+        } else {
+            1.0
+        }
+    }
+
+    fn jonswap(&self, omega: f32, spectrum: &OceanSpectrumSettings) -> f32 {
+        let sigma = if omega <= spectrum.peak_omega { 0.07 } else { 0.09 };
+        let r = (-(omega - spectrum.peak_omega).powi(2) / 2.0 / sigma / sigma / spectrum.peak_omega / spectrum.peak_omega).exp();
+        let peak_omega_over_omega = spectrum.peak_omega / omega;
+
+        spectrum.scale * self.tma_correction(omega) * spectrum.alpha * self.settings.gravity * self.settings.gravity
+            * omega.powi(-5)
+            * (-1.25 * peak_omega_over_omega.powi(4)).exp()
+            * spectrum.gamma.abs().powf(r)
+    }
+
+    fn short_waves_fade(k_length: f32, spectrum: &OceanSpectrumSettings) -> f32 {
+        (-spectrum.short_waves_fade * spectrum.short_waves_fade * k_length * k_length).exp()
+    }
+
+    fn density(&self, k: Vec2, first: &OceanSpectrumSettings, second: &OceanSpectrumSettings) -> f32 {
+        let k_length = k.length();
+        let k_angle = k.y.atan2(k.x);
+        let omega = self.dispersion(k_length);
+
+        let mut spectrum = self.jonswap(omega, first) * Self::direction_spectrum(k_angle, omega, first) * Self::short_waves_fade(k_length, first);
+        if second.scale > 0.0 {
+            spectrum += self.jonswap(omega, second) * Self::direction_spectrum(k_angle, omega, second) * Self::short_waves_fade(k_length, second);
+        }
+        spectrum
+    }
+}
+
+/// Every wave of every contributing cascade, scaled and shifted the way the ocean material tiles the cascades over
+/// the plane. Returns the waves and the total height variance
+fn synthesize_waves(
+    settings: &OceanComputeSettings,
+    spectrums: &OceanSpectrumsArray,
+    tile_layers: Vec4,
+    contributions: Vec4,
+) -> (Vec<WaveComponent>, f32) {
+    let model = SpectrumModel { settings };
+    let n = supported_fft_size(settings.n);
+    let half_n = n as f32 / 2.0;
+    let length_scales = [settings.length_scale_0, settings.length_scale_1, settings.length_scale_2, settings.length_scale_3];
+    let w_0 = TAU / settings.repeat_time;
+
+    let mut waves = Vec::new();
+    let mut variance = 0.0;
+
+    for y in 0..n {
+        for x in 0..n {
+            let mut seed = x.wrapping_add(n.wrapping_mul(y)).wrapping_add(n).wrapping_add(settings.seed);
+
+            for i in 0..settings.compute_layers.min(4) {
+                // The random sequence advances for every cascade, contributing or not
+                seed = seed.wrapping_add(i).wrapping_add((hash(seed) as u32).wrapping_mul(10));
+
+                let layer = i as usize;
+                let contribution = contributions[layer];
+                if contribution == 0.0 {
+                    continue;
+                }
+
+                let length_scale = length_scales[layer] as f32;
+                let delta_k = TAU / length_scale;
+                let k = (Vec2::new(x as f32, y as f32) - half_n) * delta_k;
+                let k_length = k.length();
+                if k_length < settings.low_cutoff || k_length > settings.high_cutoff {
+                    continue;
+                }
+
+                let gauss_1 = uniform_to_gauss(hash(seed.wrapping_mul(2)), hash(seed.wrapping_mul(3)));
+                let gauss_2 = uniform_to_gauss(hash(seed.wrapping_mul(5)), hash(seed.wrapping_mul(7)));
+
+                let density = model.density(k, &spectrums.spectrums[layer * 2], &spectrums.spectrums[layer * 2 + 1]);
+                let h0 = Vec2::new(gauss_2.x, gauss_1.y)
+                    * (2.0 * density * model.dispersion_derivative(k_length).abs() / k_length * delta_k * delta_k).sqrt();
+
+                let amplitude = 2.0 * h0.length() * contribution.abs();
+                if !amplitude.is_finite() || amplitude <= 0.0 {
+                    continue;
+                }
+
+                // The cascade spans PLANE_LENGTH / tile_layers of the plane, and texels are sampled at their centers
+                let scale = length_scale * tile_layers[layer];
+                let origin_uv = (0.5 - LAYER_OFFSETS[layer]) * tile_layers[layer] - 0.5 / n as f32;
+                let phase = h0.y.atan2(h0.x) + k.dot(Vec2::splat(origin_uv)) * length_scale + if contribution < 0.0 { PI } else { 0.0 };
+
+                waves.push(WaveComponent {
+                    wave_vector: k * scale / PLANE_LENGTH,
+                    frequency: ((settings.gravity * k_length).sqrt() / w_0).floor() * w_0 * settings.frame_time,
+                    amplitude,
+                    phase,
+                });
+                variance += amplitude * amplitude * 0.5;
+            }
+        }
+    }
+
+    (waves, variance)
+}
+
+pub fn update_wave_components(
+    mut waves: ResMut<OceanWaveComponents>,
+    settings: Res<OceanComputeSettings>,
+    spectrums: Res<OceanSpectrumsArray>,
+    oceans: Query<(&Handle<OceanMaterial>, &GlobalTransform)>,
+    materials: Res<Assets<OceanMaterial>>,
+) {
+    let Some((mat, transform)) = oceans.iter().find_map(|(handle, transform)| Some((materials.get(handle)?, transform))) else {
+        return;
+    };
+    waves.transform = transform.affine();
+
+    let contributions = Vec4::from_array(std::array::from_fn(|layer| {
+        if (layer as u32) < mat.settings.active_layers { mat.settings.contribute_layers[layer] } else { 0.0 }
+    }));
+    let layers = Some((mat.settings.tile_layers, contributions));
+    if settings.is_changed() || spectrums.is_changed() || waves.layers != layers {
+        waves.layers = layers;
+        waves.stale = true;
+    }
+
+    let synthesized = waves.synthesized.lock().unwrap().take();
+    if let Some((components, retained_variance)) = synthesized {
+        waves.components = components;
+        waves.retained_variance = retained_variance;
+        waves.in_flight = false;
+    }
+
+    if !waves.stale || waves.in_flight {
+        return;
+    }
+    waves.stale = false;
+    waves.in_flight = true;
+
+    let settings = settings.clone();
+    let spectrums = spectrums.clone();
+    let tile_layers = mat.settings.tile_layers;
+    let output = waves.synthesized.clone();
+    AsyncComputeTaskPool::get()
+        .spawn(async move {
+            let (mut components, variance) = synthesize_waves(&settings, &spectrums, tile_layers, contributions);
+            if components.len() > MAX_WAVE_COMPONENTS {
+                components.select_nth_unstable_by(MAX_WAVE_COMPONENTS, |a, b| b.amplitude.total_cmp(&a.amplitude));
+                components.truncate(MAX_WAVE_COMPONENTS);
+            }
+
+            let retained: f32 = components.iter().map(|wave| wave.amplitude * wave.amplitude * 0.5).sum();
+            let retained_variance = if variance > 0.0 { retained / variance } else { 1.0 };
+            *output.lock().unwrap() = Some((components, retained_variance));
+        })
+        .detach();
+}
+
+
+/// Vessel response to a set of wave components, for a fixed heading
+pub struct ShipMotionModel<'a> {
+    waves: &'a OceanWaveComponents,
+    // Response of each component
+    transfer: Vec<[Vec2; 3]>,
+}
+
+impl<'a> ShipMotionModel<'a> {
+    /// `forward` and `right` are the vessel's bow and starboard directions in world space
+    pub fn new(waves: &'a OceanWaveComponents, raos: &ResponseAmplitudeOperators, forward: Vec3, right: Vec3) -> Self {
+        let forward = forward.xz().normalize_or_zero();
+        let right = right.xz().normalize_or_zero();
+
+        let transfer = waves.components
+            .iter()
+            .map(|wave| {
+                let local_direction = -wave.wave_vector.normalize_or_zero();
+                let direction = waves.transform.transform_vector3(Vec3::new(local_direction.x, 0.0, local_direction.y)).xz();
+                let heading = (-direction.dot(right)).atan2(direction.dot(forward)).to_degrees();
+                raos.response(wave.frequency, heading)
+            })
+            .collect();
+
+        Self {
+            waves,
+            transfer,
+        }
+    }
+
+    pub fn sample(&self, world_xz: Vec2, time: f32) -> ShipMotionSample {
+        let local_xz = self.waves.transform.inverse().transform_point3(Vec3::new(world_xz.x, 0.0, world_xz.y)).xz();
+
+        let mut motion = [0.0; 3];
+        for (wave, transfer) in self.waves.components.iter().zip(self.transfer.iter()) {
+            let phase = wave.wave_vector.dot(local_xz) + wave.frequency * time + wave.phase;
+            for (value, response) in motion.iter_mut().zip(transfer) {
+                *value += wave.amplitude * response.length() * (phase + response.y.atan2(response.x)).cos();
+            }
+        }
+
+        ShipMotionSample::from_array(motion)
+    }
+
+    /// Motion of a vessel moving in a straight line at `velocity` from `world_xz`, sampled every `step` seconds
+    pub fn time_series(&self, world_xz: Vec2, velocity: Vec2, start: f32, duration: f32, step: f32) -> Vec<(f32, ShipMotionSample)> {
+        let count = (duration / step.max(1e-4)).ceil() as usize;
+        (0..=count)
+            .map(|i| {
+                let t = i as f32 * step;
+                (start + t, self.sample(world_xz + velocity * t, start + t))
+            })
+            .collect()
+    }
+
+    /// Statistics of a vessel moving at `velocity`, which shifts the frequency the waves are encountered at
+    pub fn statistics(&self, velocity: Vec2) -> ShipMotionStatistics {
+        let local_velocity = self.waves.transform.inverse().transform_vector3(Vec3::new(velocity.x, 0.0, velocity.y)).xz();
+
+        let mut m0 = [0.0; 3];
+        let mut m2 = [0.0; 3];
+        for (wave, transfer) in self.waves.components.iter().zip(self.transfer.iter()) {
+            let encounter_frequency = wave.frequency + wave.wave_vector.dot(local_velocity);
+            for motion in 0..3 {
+                let energy = (wave.amplitude * transfer[motion].length()).powi(2) * 0.5;
+                m0[motion] += energy;
+                m2[motion] += energy * encounter_frequency * encounter_frequency;
+            }
+        }
+
+        let rms = m0.map(f32::sqrt);
+        let period = std::array::from_fn(|motion| if m2[motion] > 0.0 { TAU * (m0[motion] / m2[motion]).sqrt() } else { 0.0 });
+
+        ShipMotionStatistics {
+            rms: ShipMotionSample::from_array(rms),
+            significant_amplitude: ShipMotionSample::from_array(rms.map(|rms| 2.0 * rms)),
+            zero_crossing_period: ShipMotionSample::from_array(period),
+        }
+    }
+}
+
+
+/// Moves a vessel in heave, pitch and roll as its RAOs respond to the rendered ocean
+#[derive(Component, Clone)]
+pub struct ShipMotion {
+    pub raos: Arc<ResponseAmplitudeOperators>,
+    /// Sets the height, pitch and roll of the entity's `Transform`, keeping its horizontal position and yaw
+    pub drive_transform: bool,
+    /// Height of the entity's origin above the undisplaced ocean plane at rest
+    pub height_offset: f32,
+
+    pub motion: ShipMotionSample,
+    pub statistics: ShipMotionStatistics,
+
+    prev_position: Option<Vec3>,
+}
+
+impl ShipMotion {
+    pub fn new(raos: Arc<ResponseAmplitudeOperators>) -> Self {
+        Self {
+            raos,
+            drive_transform: true,
+            height_offset: 0.0,
+
+            motion: ShipMotionSample::default(),
+            statistics: ShipMotionStatistics::default(),
+
+            prev_position: None,
+        }
+    }
+}
+
+pub fn update_ship_motion(
+    mut ships: Query<(&mut ShipMotion, &mut Transform)>,
+    waves: Res<OceanWaveComponents>,
+    clock: Res<OceanSimulationClock>,
+    time: Res<Time>,
+) {
+    if waves.components.is_empty() {
+        return;
+    }
+
+    let simulation_time = clock.elapsed + clock.blend * clock.step_length();
+    let delta = time.delta_seconds();
+
+    for (mut ship, mut transform) in ships.iter_mut() {
+        let position = transform.translation;
+        let velocity = match ship.prev_position.replace(position) {
+            Some(prev_position) if delta > 0.0 => (position - prev_position).xz() / delta,
+            _ => Vec2::ZERO,
+        };
+
+        let forward = transform.forward();
+        let model = ShipMotionModel::new(&waves, &ship.raos, forward, transform.right());
+        ship.motion = model.sample(position.xz(), simulation_time);
+        ship.statistics = model.statistics(velocity);
+
+        if ship.drive_transform {
+            let yaw = Quat::from_rotation_y((-forward.x).atan2(-forward.z));
+            transform.rotation = yaw * Quat::from_rotation_x(ship.motion.pitch) * Quat::from_rotation_z(-ship.motion.roll);
+            transform.translation.y = waves.transform.translation.y + ship.height_offset + ship.motion.heave;
+        }
+    }
+}
+
+
+pub struct SeakeepingPlugin;
+
+impl Plugin for SeakeepingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<OceanWaveComponents>()
+            .register_type::<ShipMotionSample>()
+            .register_type::<ShipMotionStatistics>()
+            .add_systems(Update, (update_wave_components, update_ship_motion).chain().after(update_spectrums_array));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Heave amplitude follows the frequency and roll the heading, so interpolated values are easy to predict
+    const TABLE: &str = "\
+        # test vessel
+        frequency, heading, heave, heave phase, pitch, pitch phase, roll, roll phase
+        0.5, 0, 0.5, 0, 0, 0, 0, 0
+        0.5, 90, 0.5, 0, 0, 0, 1, 0
+        0.5, 180, 0.5, 0, 2, 90, 2, 0
+        1.0, 0, 1.0, 0, 0, 0, 0, 0
+        1.0, 90, 1.0, 0, 0, 0, 1, 0
+        1.0, 180, 1.0, 0, 2, 90, 2, 0
+    ";
+
+    fn table() -> ResponseAmplitudeOperators {
+        ResponseAmplitudeOperators::from_csv(TABLE).unwrap()
+    }
+
+    fn assert_close(a: Vec2, b: Vec2) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{a} != {b}");
+    }
+
+    #[test]
+    fn from_csv_reads_table() {
+        let raos = table();
+        assert_eq!(raos.frequencies, vec![0.5, 1.0]);
+        assert_eq!(raos.headings, vec![0.0, 90.0, 180.0]);
+
+        // Pitch and roll are given in degrees per meter, phases as leads in degrees
+        let [heave, pitch, roll] = raos.response(0.5, 180.0);
+        assert_close(heave, Vec2::new(0.5, 0.0));
+        assert_close(pitch, Vec2::new(0.0, 2f32.to_radians()));
+        assert_close(roll, Vec2::new(2f32.to_radians(), 0.0));
+    }
+
+    #[test]
+    fn from_csv_skips_only_one_header() {
+        let csv = "frequency, heading, heave, heave phase, pitch, pitch phase, roll, roll phase\n\
+            units, deg, m/m, deg, deg/m, deg, deg/m, deg\n\
+            1.0, 0, 1, 0, 0, 0, 0, 0";
+        assert!(matches!(ResponseAmplitudeOperators::from_csv(csv), Err(RaoError::Parse { line: 2, .. })));
+    }
+
+    #[test]
+    fn from_csv_rejects_bad_tables() {
+        assert!(matches!(ResponseAmplitudeOperators::from_csv("# nothing\n"), Err(RaoError::Empty)));
+        assert!(matches!(
+            ResponseAmplitudeOperators::from_csv("1.0, 0, 1, 0, 0, 0, 0"),
+            Err(RaoError::Parse { line: 1, .. }),
+        ));
+        assert!(matches!(
+            ResponseAmplitudeOperators::from_csv("1.0, 0, 1, 0, 0, 0, 0, 0\n2.0, 90, 1, 0, 0, 0, 0, 0"),
+            Err(RaoError::MissingEntry { .. }),
+        ));
+    }
+
+    #[test]
+    fn response_interpolates() {
+        let [heave, _, roll] = table().response(0.75, 45.0);
+        assert_close(heave, Vec2::new(0.75, 0.0));
+        assert_close(roll, Vec2::new(0.5f32.to_radians(), 0.0));
+    }
+
+    #[test]
+    fn response_mirrors_port_side() {
+        let raos = table();
+        let [heave, pitch, roll] = raos.response(1.0, 270.0);
+        let [starboard_heave, starboard_pitch, starboard_roll] = raos.response(1.0, 90.0);
+        assert_close(heave, starboard_heave);
+        assert_close(pitch, starboard_pitch);
+        assert_close(roll, -starboard_roll);
+
+        // Negative headings wrap around first
+        assert_close(raos.response(1.0, -90.0)[2], roll);
+    }
+
+    #[test]
+    fn response_clamps_to_table() {
+        let raos = table();
+        assert_close(raos.response(0.1, 90.0)[0], Vec2::new(0.5, 0.0));
+        assert_close(raos.response(5.0, 90.0)[0], Vec2::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn statistics_of_single_wave() {
+        let waves = OceanWaveComponents {
+            components: vec![WaveComponent {
+                // Travelling towards +x, the vessel's bow, so the vessel sees following seas
+                wave_vector: Vec2::new(-0.1, 0.0),
+                frequency: 1.0,
+                amplitude: 2.0,
+                phase: 0.0,
+            }],
+            retained_variance: 1.0,
+            transform: Affine3A::IDENTITY,
+            ..default()
+        };
+        let model = ShipMotionModel::new(&waves, &table(), Vec3::X, Vec3::Z);
+
+        let statistics = model.statistics(Vec2::ZERO);
+        let rms = 2.0 / 2f32.sqrt();
+        assert!((statistics.rms.heave - rms).abs() < 1e-5);
+        assert!((statistics.significant_amplitude.heave - 2.0 * rms).abs() < 1e-5);
+        assert!((statistics.zero_crossing_period.heave - TAU).abs() < 1e-4);
+        // Following seas don't roll the vessel
+        assert_eq!(statistics.rms.roll, 0.0);
+        assert_eq!(statistics.zero_crossing_period.roll, 0.0);
+
+        // Running with the waves at 5 m/s halves the encounter frequency, doubling the period
+        let statistics = model.statistics(Vec2::new(5.0, 0.0));
+        assert!((statistics.rms.heave - rms).abs() < 1e-5);
+        assert!((statistics.zero_crossing_period.heave - 2.0 * TAU).abs() < 1e-4);
+    }
+}
@@ -13,12 +13,19 @@ use bevy::{
             ShaderType, TextureFormat, TextureSampleType, TextureViewDimension, BufferBindingType, TextureAspect, TextureViewDescriptor,
         },
         renderer::{RenderContext, RenderDevice},
-        texture::BevyDefault,
+        texture::{BevyDefault, FallbackImage},
         view::{ViewTarget, ViewUniforms, ViewUniform, ViewUniformOffset}, RenderApp, extract_resource::{ExtractResource, ExtractResourcePlugin}, render_asset::RenderAssets
     },
     ecs::query::QueryItem, pbr::{GpuLights, LightMeta, ViewLightsUniformOffset},
 };
 
+use crate::{
+    compute::uniforms::OceanComputeTextures,
+    ocean::{OceanSurfaceUniform, OceanSurfaceUniforms},
+    ripples::OceanRippleTextures,
+    wake::OceanWakeTexture,
+};
+
 
 #[derive(Default)]
 pub struct SkyPassPostProcessNode;
@@ -72,6 +79,19 @@ impl ViewNode for SkyPassPostProcessNode {
             return Ok(());
         };
 
+        // The waterline and light shafts are sampled from the same textures the ocean is drawn with. Without an
+        // ocean the surface uniform says so, and the fallbacks are never read
+        let fallback = world.resource::<FallbackImage>();
+        let textures = world.resource::<OceanComputeTextures>();
+        let cascade_view = |handle: &Handle<Image>| gpu_images.get(handle).unwrap_or(&fallback.d2_array).texture_view.clone();
+        let local_view = |handle: Option<&Handle<Image>>| handle.and_then(|handle| gpu_images.get(handle)).unwrap_or(&fallback.d2).texture_view.clone();
+        let displacements = cascade_view(&textures.displacements);
+        let prev_displacements = cascade_view(&textures.prev_displacements);
+        let gradients = cascade_view(&textures.gradients);
+        let prev_gradients = cascade_view(&textures.prev_gradients);
+        let ripples = local_view(world.get_resource::<OceanRippleTextures>().map(|textures| &textures.state));
+        let wakes = local_view(world.get_resource::<OceanWakeTexture>().map(|texture| &texture.0));
+
         let post_process = view_target.0.post_process_write();
 
         let bind_group = render_context
@@ -108,6 +128,34 @@ impl ViewNode for SkyPassPostProcessNode {
                         binding: 6,
                         resource: BindingResource::TextureView(&depth_view),
                     },
+                    BindGroupEntry {
+                        binding: 7,
+                        resource: world.resource::<OceanSurfaceUniforms>().buf.binding().unwrap(),
+                    },
+                    BindGroupEntry {
+                        binding: 8,
+                        resource: BindingResource::TextureView(&displacements),
+                    },
+                    BindGroupEntry {
+                        binding: 9,
+                        resource: BindingResource::TextureView(&prev_displacements),
+                    },
+                    BindGroupEntry {
+                        binding: 10,
+                        resource: BindingResource::TextureView(&gradients),
+                    },
+                    BindGroupEntry {
+                        binding: 11,
+                        resource: BindingResource::TextureView(&prev_gradients),
+                    },
+                    BindGroupEntry {
+                        binding: 12,
+                        resource: BindingResource::TextureView(&ripples),
+                    },
+                    BindGroupEntry {
+                        binding: 13,
+                        resource: BindingResource::TextureView(&wakes),
+                    },
                 ],
             });
 
@@ -140,6 +188,17 @@ impl FromWorld for SkyPassPostProcessPipeline {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
 
+        let surface_texture = |binding, view_dimension| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+
         let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("sky_pass_post_process_bind_group_layout"),
             entries: &[
@@ -209,6 +268,22 @@ impl FromWorld for SkyPassPostProcessPipeline {
                     },
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(OceanSurfaceUniform::min_size()),
+                    },
+                    count: None,
+                },
+                surface_texture(8, TextureViewDimension::D2Array),
+                surface_texture(9, TextureViewDimension::D2Array),
+                surface_texture(10, TextureViewDimension::D2Array),
+                surface_texture(11, TextureViewDimension::D2Array),
+                surface_texture(12, TextureViewDimension::D2),
+                surface_texture(13, TextureViewDimension::D2),
             ],
         });
 
@@ -252,7 +327,8 @@ impl FromWorld for SkyPassPostProcessPipeline {
 #[derive(Component, Debug, Clone, Copy, ExtractComponent, ShaderType, Reflect)]
 #[reflect(Debug, Default)]
 pub struct SkyPostProcessSettings {
-    pub sun_color: Vec3,
+    /// Brightness of the sun disk relative to the radiance of the first directional light, which gives its color
+    pub sun_strength: f32,
     pub sun_falloff: f32,
 
     pub fog_color: Vec3,
@@ -260,12 +336,17 @@ pub struct SkyPostProcessSettings {
     pub fog_offset: f32,
     pub fog_height: f32,
     pub fog_attenuation: f32,
+
+    /// Light the water scatters towards the eye under the surface, relative to the sun's radiance
+    pub underwater_color: Vec3,
+    /// Brightness of the light shafts under the surface, where the waves focus the sun
+    pub light_shaft_strength: f32,
 }
 
 impl Default for SkyPostProcessSettings {
     fn default() -> Self {
         Self {
-            sun_color: Vec3::new(1.0, 0.9, 0.6),
+            sun_strength: 0.12,
             sun_falloff: 3500.0,
 
             fog_color: Vec3::new(0.8, 0.8, 0.8),
@@ -274,6 +355,9 @@ impl Default for SkyPostProcessSettings {
             fog_offset: 0.1,
             fog_height: 218.0,
             fog_attenuation: 1.63,
+
+            underwater_color: Vec3::new(0.004, 0.03, 0.045),
+            light_shaft_strength: 0.5,
         }
     }
 }
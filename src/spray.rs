@@ -0,0 +1,695 @@
+use bevy::{
+    prelude::*,
+    core_pipeline::core_3d,
+    ecs::query::QueryItem,
+    pbr::{GpuLights, LightMeta, ViewLightsUniformOffset},
+    render::{
+        extract_component::ComponentUniforms,
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{self, NodeRunError, RenderGraph, RenderGraphApp, RenderGraphContext, ViewNode, ViewNodeRunner},
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingResource, BindingType, BlendState, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+            CachedComputePipelineId, CachedRenderPipelineId, ColorTargetState, ColorWrites, CompareFunction,
+            ComputePassDescriptor, ComputePipelineDescriptor, DepthStencilState, FragmentState, MultisampleState,
+            Operations, PipelineCache, PrimitiveState, RenderPassDepthStencilAttachment,
+            RenderPassDescriptor, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
+            ShaderType, TextureFormat, TextureSampleType, TextureViewDimension, UniformBuffer, VertexState,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        view::{ViewDepthTexture, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    compute::{node::OceanComputeNode, uniforms::{OceanComputeSettings, OceanComputeTextures}, OceanComputeSettingsSet, WORKGROUP_SIZE},
+    ocean::OceanMaterial,
+    scene::PLANE_LENGTH,
+    sky::{SkyPostProcessSettings, SkyboxCubemap},
+};
+
+
+pub const MAX_SPRAY_PARTICLES: u32 = 65536;
+// Cells across the ocean plane that each spawn spray where the surface under them is breaking
+pub const SPRAY_EMITTER_SIZE: u32 = 128;
+const UPDATE_WORKGROUP_SIZE: u32 = 64;
+
+
+/// Spray thrown off breaking crests, wherever the compute pass accumulates foam
+#[derive(Resource, ExtractResource, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct OceanSpraySettings {
+    pub enabled: bool,
+    /// Particles per second and square meter of crest breaking at an intensity of one
+    pub emission_rate: f32,
+
+    pub min_size: f32,
+    pub max_size: f32,
+    pub min_lifetime: f32,
+    pub max_lifetime: f32,
+    /// Speed particles leave the crest with on top of the surface velocity, at an intensity of one
+    pub ejection_speed: f32,
+
+    /// Rate particles take on the wind velocity at, per second
+    pub drag: f32,
+    pub wind: Vec2,
+
+    pub color: Vec3,
+    pub opacity: f32,
+    pub environment_light_strength: f32,
+}
+
+impl Default for OceanSpraySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            emission_rate: 2.0,
+
+            min_size: 0.1,
+            max_size: 0.6,
+            min_lifetime: 0.5,
+            max_lifetime: 2.5,
+            ejection_speed: 3.0,
+
+            drag: 1.5,
+            wind: Vec2::new(8.0, 0.0),
+
+            color: Vec3::new(0.9, 0.95, 1.0),
+            opacity: 0.6,
+            environment_light_strength: 0.4,
+        }
+    }
+}
+
+
+/// Where the ocean plane is and how its cascades are combined, taken from the first ocean entity
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct OceanSprayFrame {
+    pub active: bool,
+    pub transform: Mat4,
+    pub tile_layers: Vec4,
+    // Zero for cascades that aren't simulated
+    pub contribute_layers: Vec4,
+    pub delta_time: f32,
+    pub seed: u32,
+}
+
+pub fn update_spray_frame(
+    mut frame: ResMut<OceanSprayFrame>,
+    settings: Res<OceanSpraySettings>,
+    oceans: Query<(&Handle<OceanMaterial>, &GlobalTransform)>,
+    materials: Res<Assets<OceanMaterial>>,
+    time: Res<Time>,
+) {
+    frame.active = false;
+    if !settings.enabled {
+        return;
+    }
+
+    let Some((mat, transform)) = oceans.iter().find_map(|(handle, transform)| Some((materials.get(handle)?, transform))) else {
+        return;
+    };
+
+    frame.active = true;
+    frame.transform = transform.compute_matrix();
+    frame.tile_layers = mat.settings.tile_layers;
+    frame.contribute_layers = Vec4::from_array(std::array::from_fn(|layer| {
+        if (layer as u32) < mat.settings.active_layers { mat.settings.contribute_layers[layer] } else { 0.0 }
+    }));
+    frame.delta_time = time.delta_seconds();
+    frame.seed = frame.seed.wrapping_add(1);
+}
+
+/// Spray inherits the surface velocity, so the compute shader has to produce it while spray is enabled
+pub fn request_spray_velocities(
+    settings: Res<OceanSpraySettings>,
+    mut compute_settings: ResMut<OceanComputeSettings>,
+) {
+    if settings.enabled && compute_settings.compute_velocities == 0 {
+        compute_settings.compute_velocities = 1;
+    }
+}
+
+
+#[derive(Clone, Default, ShaderType)]
+pub struct SprayUniform {
+    transform: Mat4,
+    tile_layers: Vec4,
+    contribute_layers: Vec4,
+    wind: Vec2,
+    plane_length: f32,
+    delta_time: f32,
+    gravity: f32,
+    seed: u32,
+    emitter_size: u32,
+    emission_rate: f32,
+    min_size: f32,
+    max_size: f32,
+    min_lifetime: f32,
+    max_lifetime: f32,
+    ejection_speed: f32,
+    drag: f32,
+    has_velocities: u32,
+    color: Vec3,
+    opacity: f32,
+    environment_light_strength: f32,
+}
+
+#[derive(Clone, ShaderType)]
+pub struct SprayParticle {
+    position: Vec3,
+    age: f32,
+    velocity: Vec3,
+    lifetime: f32,
+    size: f32,
+    // Height of the crest the particle left, it's gone once it falls back below
+    surface_height: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct SprayUniforms {
+    buf: UniformBuffer<SprayUniform>,
+}
+
+pub fn prepare_spray_uniforms(
+    mut uniforms: ResMut<SprayUniforms>,
+    settings: Res<OceanSpraySettings>,
+    frame: Res<OceanSprayFrame>,
+    compute_settings: Res<OceanComputeSettings>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    uniforms.buf.set(SprayUniform {
+        transform: frame.transform,
+        tile_layers: frame.tile_layers,
+        contribute_layers: frame.contribute_layers,
+        wind: settings.wind,
+        plane_length: PLANE_LENGTH,
+        delta_time: frame.delta_time,
+        gravity: compute_settings.gravity,
+        seed: frame.seed,
+        emitter_size: SPRAY_EMITTER_SIZE,
+        emission_rate: settings.emission_rate,
+        min_size: settings.min_size,
+        max_size: settings.max_size,
+        min_lifetime: settings.min_lifetime,
+        max_lifetime: settings.max_lifetime,
+        ejection_speed: settings.ejection_speed,
+        drag: settings.drag,
+        has_velocities: compute_settings.compute_velocities,
+        color: settings.color,
+        opacity: settings.opacity,
+        environment_light_strength: settings.environment_light_strength,
+    });
+    uniforms.buf.write_buffer(&render_device, &render_queue);
+}
+
+
+/// Ring buffer of particles, new ones overwrite the oldest once it's full
+#[derive(Resource)]
+pub struct SprayBuffers {
+    particles: Buffer,
+    // Index the next particle is written to
+    counter: Buffer,
+}
+
+impl FromWorld for SprayBuffers {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        Self {
+            particles: render_device.create_buffer(&BufferDescriptor {
+                label: Some("ocean_spray_particle_buffer"),
+                size: SprayParticle::min_size().get() * MAX_SPRAY_PARTICLES as u64,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }),
+            counter: render_device.create_buffer(&BufferDescriptor {
+                label: Some("ocean_spray_counter_buffer"),
+                size: 4,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }),
+        }
+    }
+}
+
+
+#[derive(Resource)]
+pub struct SprayComputePipeline {
+    layout: BindGroupLayout,
+    update_pipeline: CachedComputePipelineId,
+    emit_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for SprayComputePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let storage_buffer = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let cascade_texture = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ocean_spray_compute_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(SprayUniform::min_size()),
+                    },
+                    count: None,
+                },
+                storage_buffer(1),
+                storage_buffer(2),
+                cascade_texture(3),
+                cascade_texture(4),
+                cascade_texture(5),
+            ],
+        });
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/spray_simulate.wgsl");
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let queue_pipeline = |entry_point: &'static str| pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some(format!("ocean_spray_{entry_point}_pipeline").into()),
+            layout: vec![layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader: shader.clone(),
+            shader_defs: Vec::new(),
+            entry_point: entry_point.into(),
+        });
+
+        let update_pipeline = queue_pipeline("update_spray");
+        let emit_pipeline = queue_pipeline("emit_spray");
+
+        Self {
+            layout,
+            update_pipeline,
+            emit_pipeline,
+        }
+    }
+}
+
+
+/// Ages and moves the existing spray, then spawns new particles from this frame's breaking crests
+#[derive(Default)]
+pub struct SprayComputeNode;
+
+impl SprayComputeNode {
+    pub const NAME: &'static str = "ocean_spray_compute_node";
+}
+
+impl render_graph::Node for SprayComputeNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if !world.resource::<OceanSprayFrame>().active {
+            return Ok(());
+        }
+
+        let spray_pipeline = world.resource::<SprayComputePipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let (Some(update_pipeline), Some(emit_pipeline)) = (
+            pipeline_cache.get_compute_pipeline(spray_pipeline.update_pipeline),
+            pipeline_cache.get_compute_pipeline(spray_pipeline.emit_pipeline),
+        ) else {
+            return Ok(());
+        };
+
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+        let textures = world.resource::<OceanComputeTextures>();
+        let (Some(displacements), Some(velocities), Some(breaking)) = (
+            gpu_images.get(&textures.displacements),
+            gpu_images.get(&textures.velocities),
+            gpu_images.get(&textures.breaking),
+        ) else {
+            return Ok(());
+        };
+
+        let uniforms = world.resource::<SprayUniforms>();
+        let buffers = world.resource::<SprayBuffers>();
+        let bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
+            label: Some("ocean_spray_compute_bind_group"),
+            layout: &spray_pipeline.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.buf.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: buffers.particles.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.counter.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&displacements.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&velocities.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&breaking.texture_view),
+                },
+            ],
+        });
+
+        let mut pass = render_context.command_encoder().begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_bind_group(0, &bind_group, &[]);
+
+        pass.set_pipeline(update_pipeline);
+        pass.dispatch_workgroups(MAX_SPRAY_PARTICLES / UPDATE_WORKGROUP_SIZE, 1, 1);
+
+        pass.set_pipeline(emit_pipeline);
+        pass.dispatch_workgroups(SPRAY_EMITTER_SIZE / WORKGROUP_SIZE, SPRAY_EMITTER_SIZE / WORKGROUP_SIZE, 1);
+
+        Ok(())
+    }
+}
+
+
+#[derive(Resource)]
+pub struct SprayRenderPipeline {
+    layout: BindGroupLayout,
+    sampler: Sampler,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for SprayRenderPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ocean_spray_render_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(SprayUniform::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(ViewUniform::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(GpuLights::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(SkyPostProcessSettings::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = render_device.create_sampler(&SamplerDescriptor::default());
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/spray.wgsl");
+
+        let pipeline_id = world
+            .resource_mut::<PipelineCache>()
+            .queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some("ocean_spray_render_pipeline".into()),
+                layout: vec![layout.clone()],
+                vertex: VertexState {
+                    shader: shader.clone(),
+                    shader_defs: vec![],
+                    entry_point: "vertex".into(),
+                    buffers: vec![],
+                },
+                fragment: Some(FragmentState {
+                    shader,
+                    shader_defs: vec![],
+                    entry_point: "fragment".into(),
+                    targets: vec![Some(ColorTargetState {
+                        format: TextureFormat::bevy_default(),
+                        blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                // Tested against the scene but not written, the particles are sorted by nothing
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::GreaterEqual,
+                    stencil: default(),
+                    bias: default(),
+                }),
+                multisample: MultisampleState::default(),
+                push_constant_ranges: vec![],
+            });
+
+        Self {
+            layout,
+            sampler,
+            pipeline_id,
+        }
+    }
+}
+
+
+/// Draws every particle as a camera facing quad, on top of the sky pass and transparent meshes
+#[derive(Default)]
+pub struct SprayRenderNode;
+
+impl SprayRenderNode {
+    pub const NAME: &str = "ocean_spray_render";
+}
+
+impl ViewNode for SprayRenderNode {
+    type ViewQuery = (
+        &'static ViewTarget,
+        &'static ViewDepthTexture,
+        bevy::ecs::system::lifetimeless::Read<ViewUniformOffset>,
+        bevy::ecs::system::lifetimeless::Read<ViewLightsUniformOffset>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (view_target, depth, view_offset, lights_offset): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        if !world.resource::<OceanSprayFrame>().active {
+            return Ok(());
+        }
+
+        let spray_pipeline = world.resource::<SprayRenderPipeline>();
+        let Some(pipeline) = world.resource::<PipelineCache>().get_render_pipeline(spray_pipeline.pipeline_id) else {
+            return Ok(());
+        };
+
+        let skybox = world.resource::<SkyboxCubemap>();
+        if !skybox.is_loaded { return Ok(()) };
+        let Some(skybox_view) = world.resource::<RenderAssets<Image>>().get(&skybox.skybox) else {
+            return Ok(());
+        };
+
+        let Some(sky_settings_binding) = world.resource::<ComponentUniforms<SkyPostProcessSettings>>().uniforms().binding() else {
+            return Ok(());
+        };
+        let Some(view_binding) = world.resource::<ViewUniforms>().uniforms.binding() else {
+            return Ok(());
+        };
+        let Some(lights_binding) = world.resource::<LightMeta>().view_gpu_lights.binding() else {
+            return Ok(());
+        };
+
+        let uniforms = world.resource::<SprayUniforms>();
+        let buffers = world.resource::<SprayBuffers>();
+        let bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
+            label: Some("ocean_spray_render_bind_group"),
+            layout: &spray_pipeline.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffers.particles.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: uniforms.buf.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: view_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: lights_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: sky_settings_binding.clone(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&skybox_view.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: BindingResource::Sampler(&spray_pipeline.sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("ocean_spray_pass"),
+            color_attachments: &[Some(view_target.get_color_attachment(Operations::default()))],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(Operations::default()),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[view_offset.offset, lights_offset.offset]);
+        render_pass.draw(0..6, 0..MAX_SPRAY_PARTICLES);
+
+        Ok(())
+    }
+}
+
+
+pub struct OceanSprayPlugin;
+
+impl Plugin for OceanSprayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<OceanSpraySettings>()
+            .init_resource::<OceanSprayFrame>()
+            .register_type::<OceanSpraySettings>()
+            .add_systems(Update, (update_spray_frame, request_spray_velocities.in_set(OceanComputeSettingsSet)))
+            .add_plugins((
+                ExtractResourcePlugin::<OceanSpraySettings>::default(),
+                ExtractResourcePlugin::<OceanSprayFrame>::default(),
+            ));
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<SprayUniforms>()
+            .add_systems(Render, prepare_spray_uniforms.in_set(RenderSet::Prepare))
+            .add_render_graph_node::<ViewNodeRunner<SprayRenderNode>>(
+                core_3d::graph::NAME,
+                SprayRenderNode::NAME,
+            )
+            .add_render_graph_edges(
+                core_3d::graph::NAME,
+                &[
+                    // After the transparent pass, which a refracting ocean is drawn in
+                    core_3d::graph::node::MAIN_TRANSPARENT_PASS,
+                    SprayRenderNode::NAME,
+                    core_3d::graph::node::END_MAIN_PASS,
+                ],
+            );
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(SprayComputeNode::NAME, SprayComputeNode);
+        render_graph.add_node_edges(&[
+            OceanComputeNode::NAME,
+            SprayComputeNode::NAME,
+            bevy::render::main_graph::node::CAMERA_DRIVER,
+        ]);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<SprayBuffers>()
+            .init_resource::<SprayComputePipeline>()
+            .init_resource::<SprayRenderPipeline>();
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    math::{Vec3Swizzles, Vec4Swizzles},
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{self, RenderGraph},
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+            BindingResource, BindingType, BufferBindingType, CachedComputePipelineId, ComputePassDescriptor,
+            ComputePipelineDescriptor, Extent3d, FilterMode, PipelineCache, SamplerDescriptor, ShaderStages, ShaderType,
+            StorageTextureAccess, TextureDimension, TextureFormat, TextureUsages, TextureViewDimension, UniformBuffer,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::ImageSampler,
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{compute::{uniforms::OceanComputeSettings, WORKGROUP_SIZE}, ocean::OceanMaterial, ripples::OceanRippleFocus};
+
+
+pub const WAKE_TEXTURE_SIZE: u32 = 256;
+pub const MAX_WAKE_SHIPS: usize = 32;
+// Per ship, including its current position
+pub const MAX_WAKE_POINTS: usize = 16;
+
+
+/// World space texture the wakes of all [`ShipWake`]s are drawn into every frame.
+///
+/// Covers a `world_size` square around the [`OceanRippleFocus`] entity, or the first 3d camera without one.
+#[derive(Resource, ExtractResource, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct OceanWakeSettings {
+    pub enabled: bool,
+    pub world_size: f32,
+}
+
+impl Default for OceanWakeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            world_size: 512.0,
+        }
+    }
+}
+
+/// Leaves an analytic Kelvin wake and a foam trail behind a moving entity.
+///
+/// The wavelength follows from the ship's speed, and the wake follows the path it took rather than its current heading.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct ShipWake {
+    pub hull_length: f32,
+    pub beam: f32,
+    /// Height of the wake waves near the hull at high speed
+    pub amplitude: f32,
+    /// Distance behind the ship over which the waves die out
+    pub decay_length: f32,
+
+    /// Seconds the foam trail takes to fade, which also sets how far back the trail reaches. At most
+    /// `MAX_WAKE_POINTS` points are kept, so long trails are drawn with straighter segments
+    pub foam_lifetime: f32,
+    /// Strength of the foam along the edges of the wake
+    pub cusp_foam: f32,
+
+    // Older positions of the ship, newest first, as world xz, elapsed seconds and speed
+    #[reflect(ignore)]
+    history: VecDeque<Vec4>,
+    prev_position: Option<Vec3>,
+    speed: f32,
+}
+
+impl ShipWake {
+    pub fn new(hull_length: f32, beam: f32) -> Self {
+        Self {
+            hull_length,
+            beam,
+            amplitude: 0.02 * hull_length,
+            decay_length: 4.0 * hull_length,
+
+            foam_lifetime: 20.0,
+            cusp_foam: 0.3,
+
+            history: VecDeque::new(),
+            prev_position: None,
+            speed: 0.0,
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+}
+
+impl Default for ShipWake {
+    fn default() -> Self {
+        Self::new(20.0, 5.0)
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Default, ShaderType)]
+pub struct GpuShipWake {
+    pub first_point: u32,
+    pub point_count: u32,
+    pub hull_length: f32,
+    pub beam: f32,
+    pub amplitude: f32,
+    pub decay_length: f32,
+    pub foam_lifetime: f32,
+    pub cusp_foam: f32,
+}
+
+/// Ships and their paths to draw this frame
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct OceanWakeFrame {
+    /// World space xz of the wake texture's corner
+    pub origin: Vec2,
+    pub ships: Vec<GpuShipWake>,
+    // World xz, age in seconds and speed
+    pub points: Vec<Vec4>,
+}
+
+/// Wave height in r and foam in b, laid out like the ripple textures
+#[derive(Resource, ExtractResource, Clone)]
+pub struct OceanWakeTexture(pub Handle<Image>);
+
+
+pub fn setup_wake_texture(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: WAKE_TEXTURE_SIZE,
+            height: WAKE_TEXTURE_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 16],
+        TextureFormat::Rgba32Float,
+    );
+    image.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+
+    commands.insert_resource(OceanWakeTexture(images.add(image)));
+}
+
+pub fn record_wake_history(
+    mut ships: Query<(&mut ShipWake, &GlobalTransform)>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    let elapsed = time.elapsed_seconds();
+
+    for (mut ship, transform) in ships.iter_mut() {
+        let position = transform.translation();
+
+        if let Some(prev_position) = ship.prev_position.replace(position) {
+            if delta > 0.0 {
+                let speed = (position - prev_position).xz().length() / delta;
+                ship.speed += (speed - ship.speed) * (delta * 4.0).min(1.0);
+            }
+        }
+
+        // Points are spread out so the trail reaches back as far as the foam lasts at the current speed, but no closer
+        // than half a hull length
+        let trail_length = ship.foam_lifetime * ship.speed;
+        let spacing = (ship.hull_length.max(1.0) * 0.5).max(trail_length / (MAX_WAKE_POINTS - 2) as f32);
+        let moved = !ship.history.front().is_some_and(|newest| newest.xy().distance(position.xz()) < spacing);
+        if moved {
+            let point = Vec4::new(position.x, position.z, elapsed, ship.speed);
+            ship.history.push_front(point);
+            ship.history.truncate(MAX_WAKE_POINTS - 1);
+        }
+
+        // Past the foam's lifetime the trail has nothing left to draw, the oldest point kept only ends it
+        let foam_lifetime = ship.foam_lifetime;
+        while ship.history.len() >= 2 && elapsed - ship.history[ship.history.len() - 2].z > foam_lifetime {
+            ship.history.pop_back();
+        }
+    }
+}
+
+pub fn update_wake_frame(
+    mut frame: ResMut<OceanWakeFrame>,
+    settings: Res<OceanWakeSettings>,
+    ships: Query<(&ShipWake, &GlobalTransform)>,
+    focus: Query<&GlobalTransform, With<OceanRippleFocus>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    time: Res<Time>,
+    mut warned: Local<bool>,
+) {
+    frame.ships.clear();
+    frame.points.clear();
+
+    if !settings.enabled {
+        return;
+    }
+
+    let Some(focus) = focus.iter().next().or_else(|| cameras.iter().next()) else { return };
+
+    // Snapped to whole texels so the wakes don't shimmer as the camera moves
+    let texel_size = settings.world_size / WAKE_TEXTURE_SIZE as f32;
+    frame.origin = ((focus.translation().xz() - settings.world_size * 0.5) / texel_size).floor() * texel_size;
+
+    let ship_count = ships.iter().len();
+    if ship_count > MAX_WAKE_SHIPS && !std::mem::replace(&mut *warned, true) {
+        warn!("{ship_count} ships with a ShipWake, only the first {MAX_WAKE_SHIPS} leave wakes");
+    }
+
+    let elapsed = time.elapsed_seconds();
+    for (ship, transform) in ships.iter().take(MAX_WAKE_SHIPS) {
+        let first_point = frame.points.len() as u32;
+
+        let position = transform.translation();
+        frame.points.push(Vec4::new(position.x, position.z, 0.0, ship.speed));
+        frame.points.extend(ship.history.iter().map(|point| Vec4::new(point.x, point.y, elapsed - point.z, point.w)));
+
+        let point_count = frame.points.len() as u32 - first_point;
+        frame.ships.push(GpuShipWake {
+            first_point,
+            point_count,
+            hull_length: ship.hull_length,
+            beam: ship.beam,
+            amplitude: ship.amplitude,
+            decay_length: ship.decay_length,
+            foam_lifetime: ship.foam_lifetime,
+            cusp_foam: ship.cusp_foam,
+        });
+    }
+}
+
+pub fn prepare_ocean_wakes(
+    handles: Query<&Handle<OceanMaterial>>,
+    mut materials: ResMut<Assets<OceanMaterial>>,
+    settings: Res<OceanWakeSettings>,
+    frame: Res<OceanWakeFrame>,
+    texture: Res<OceanWakeTexture>,
+) {
+    for handle in handles.iter() {
+        let Some(mat) = materials.get_mut(handle) else { continue };
+
+        if mat.wakes.is_none() {
+            mat.wakes = Some(texture.0.clone());
+        }
+
+        // The texture isn't redrawn without any ships, so it must not be sampled either
+        mat.settings.wake_origin = frame.origin;
+        mat.settings.wake_size = if settings.enabled && !frame.ships.is_empty() { settings.world_size } else { 0.0 };
+    }
+}
+
+
+#[derive(Clone, ShaderType)]
+pub struct WakeUniform {
+    ships: [GpuShipWake; MAX_WAKE_SHIPS],
+    points: [Vec4; MAX_WAKE_SHIPS * MAX_WAKE_POINTS],
+    origin: Vec2,
+    size: f32,
+    gravity: f32,
+    ship_count: u32,
+}
+
+impl Default for WakeUniform {
+    fn default() -> Self {
+        Self {
+            ships: [GpuShipWake::default(); MAX_WAKE_SHIPS],
+            points: [Vec4::ZERO; MAX_WAKE_SHIPS * MAX_WAKE_POINTS],
+            origin: Vec2::ZERO,
+            size: 0.0,
+            gravity: 0.0,
+            ship_count: 0,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct WakeUniforms {
+    buf: UniformBuffer<WakeUniform>,
+}
+
+pub fn prepare_wake_uniforms(
+    mut uniforms: ResMut<WakeUniforms>,
+    settings: Res<OceanWakeSettings>,
+    frame: Res<OceanWakeFrame>,
+    compute_settings: Res<OceanComputeSettings>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    let uniform = uniforms.buf.get_mut();
+    uniform.origin = frame.origin;
+    uniform.size = settings.world_size;
+    uniform.gravity = compute_settings.gravity;
+    uniform.ship_count = frame.ships.len() as u32;
+    uniform.ships[..frame.ships.len()].copy_from_slice(&frame.ships);
+    uniform.points[..frame.points.len()].copy_from_slice(&frame.points);
+
+    uniforms.buf.write_buffer(&render_device, &render_queue);
+}
+
+
+#[derive(Resource)]
+pub struct WakeComputePipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for WakeComputePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("ocean_wake_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(WakeUniform::min_size()),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/wake.wgsl");
+
+        let pipeline = world.resource::<PipelineCache>().queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("ocean_wake_pipeline".into()),
+            layout: vec![layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader,
+            shader_defs: Vec::new(),
+            entry_point: "draw_wakes".into(),
+        });
+
+        WakeComputePipeline {
+            layout,
+            pipeline,
+        }
+    }
+}
+
+
+#[derive(Default)]
+pub struct WakeComputeNode;
+
+impl WakeComputeNode {
+    pub const NAME: &'static str = "ocean_wake_node";
+}
+
+impl render_graph::Node for WakeComputeNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if world.resource::<OceanWakeFrame>().ships.is_empty() {
+            return Ok(());
+        }
+
+        let wake_pipeline = world.resource::<WakeComputePipeline>();
+        let Some(pipeline) = world.resource::<PipelineCache>().get_compute_pipeline(wake_pipeline.pipeline) else {
+            return Ok(());
+        };
+
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+        let Some(texture) = gpu_images.get(&world.resource::<OceanWakeTexture>().0) else {
+            return Ok(());
+        };
+
+        let uniforms = world.resource::<WakeUniforms>();
+        let bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
+            label: Some("ocean_wake_bind_group"),
+            layout: &wake_pipeline.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniforms.buf.binding().unwrap(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&texture.texture_view),
+                },
+            ],
+        });
+
+        let mut pass = render_context.command_encoder().begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.set_pipeline(pipeline);
+        pass.dispatch_workgroups(WAKE_TEXTURE_SIZE / WORKGROUP_SIZE, WAKE_TEXTURE_SIZE / WORKGROUP_SIZE, 1);
+
+        Ok(())
+    }
+}
+
+
+pub struct OceanWakePlugin;
+
+impl Plugin for OceanWakePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<OceanWakeSettings>()
+            .init_resource::<OceanWakeFrame>()
+            .register_type::<OceanWakeSettings>()
+            .register_type::<ShipWake>()
+            .add_systems(Startup, setup_wake_texture)
+            .add_systems(Update, (record_wake_history, update_wake_frame, prepare_ocean_wakes).chain())
+            .add_plugins((
+                ExtractResourcePlugin::<OceanWakeSettings>::default(),
+                ExtractResourcePlugin::<OceanWakeFrame>::default(),
+                ExtractResourcePlugin::<OceanWakeTexture>::default(),
+            ));
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<WakeUniforms>()
+            .add_systems(Render, prepare_wake_uniforms.in_set(RenderSet::Prepare));
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(WakeComputeNode::NAME, WakeComputeNode);
+        render_graph.add_node_edges(&[
+            WakeComputeNode::NAME,
+            bevy::render::main_graph::node::CAMERA_DRIVER,
+        ]);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app.init_resource::<WakeComputePipeline>();
+    }
+}